@@ -1332,6 +1332,14 @@ _EOD
 	    file_write_all("$targetdir/root/.ssh/authorized_keys", join("\n", @$ssh_keys));
 	}
 
+	# copy extra files from the installation medium, e.g. added by the auto-install-assistant
+	my $extra_files = Proxmox::Install::Config::get_extra_files();
+	for my $file (@$extra_files) {
+	    my ($source, $target, $mode) = $file->@{qw(source target mode)};
+	    syscmd(['install', '-D', '-m', $mode, $source, "${targetdir}${target}"]) == 0 ||
+		die "unable to copy extra file '$source' to '$target'\n";
+	}
+
 	my $mailto = Proxmox::Install::Config::get_mailto();
 	if ($iso_env->{product} eq 'pmg') {
 	    # save admin email
@@ -93,6 +93,7 @@ my sub init_cfg {
 	root_password => undef,
 	mailto => 'mail@example.invalid',
 	root_ssh_keys => [],
+	extra_files => [],
 
 	# network related
 	mngmt_nic => undef,
@@ -219,6 +220,9 @@ sub get_mailto { return get('mailto'); }
 sub set_root_ssh_keys { set_key('root_ssh_keys', $_[0]); }
 sub get_root_ssh_keys { return get('root_ssh_keys'); }
 
+sub set_extra_files { set_key('extra_files', $_[0]); }
+sub get_extra_files { return get('extra_files'); }
+
 sub set_mngmt_nic { set_key('mngmt_nic', $_[0]); }
 sub get_mngmt_nic { return get('mngmt_nic'); }
 
@@ -0,0 +1,172 @@
+//! Preparing one ISO per host of an inventory, with answer files rendered from a template.
+
+use anyhow::{bail, format_err, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use proxmox_auto_installer::{
+    answer::Answer,
+    template::{self, split_csv_line},
+};
+
+/// A host of the inventory, with the values its answer file is rendered with.
+#[derive(Debug)]
+pub struct Host {
+    pub name: String,
+    values: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlInventory {
+    host: Vec<BTreeMap<String, toml::Value>>,
+}
+
+/// Reads the inventory, either a TOML file with a `[[host]]` table per host, or a CSV file with a
+/// header line and a row per host. Fields containing commas can be quoted with double quotes.
+/// Each host needs a unique `name`.
+pub fn read_inventory(path: &Path) -> Result<Vec<Host>> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format_err!("Reading inventory {path:?} failed: {err}"))?;
+    let hosts = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => parse_toml_inventory(&content),
+        _ => parse_csv_inventory(&content),
+    }
+    .map_err(|err| format_err!("Error parsing inventory {path:?}: {err}"))?;
+
+    if hosts.is_empty() {
+        bail!("The inventory {path:?} contains no hosts.");
+    }
+    for (i, host) in hosts.iter().enumerate() {
+        if host.name.is_empty()
+            || !host
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        {
+            bail!("Invalid host name '{}' in inventory.", host.name);
+        }
+        if hosts[..i].iter().any(|other| other.name == host.name) {
+            bail!(
+                "Host '{}' is listed more than once in inventory.",
+                host.name
+            );
+        }
+    }
+    Ok(hosts)
+}
+
+fn parse_toml_inventory(content: &str) -> Result<Vec<Host>> {
+    let inventory: TomlInventory = toml::from_str(content)?;
+    inventory
+        .host
+        .into_iter()
+        .map(|values| {
+            let Value::Object(values) = serde_json::to_value(values)? else {
+                unreachable!();
+            };
+            host_from_values(values)
+        })
+        .collect()
+}
+
+fn parse_csv_inventory(content: &str) -> Result<Vec<Host>> {
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    let Some(header) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let columns = split_csv_line(header).map_err(|err| format_err!("header: {err}"))?;
+    lines
+        .enumerate()
+        .map(|(i, line)| {
+            let fields = split_csv_line(line).map_err(|err| format_err!("row {}: {err}", i + 1))?;
+            if fields.len() != columns.len() {
+                bail!(
+                    "row {} has {} fields, expected {}",
+                    i + 1,
+                    fields.len(),
+                    columns.len()
+                );
+            }
+            host_from_values(
+                columns
+                    .iter()
+                    .cloned()
+                    .zip(fields.into_iter().map(Value::String))
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+fn host_from_values(values: Map<String, Value>) -> Result<Host> {
+    let name = match values.get("name") {
+        Some(Value::String(name)) => name.clone(),
+        _ => bail!("host without 'name'"),
+    };
+    Ok(Host { name, values })
+}
+
+impl Host {
+    /// Renders the answer file template for the host, where its values are available as
+    /// `host.<column>`, and validates the result.
+    pub fn render_answer(&self, template: &str, lookup_dir: &Path) -> Result<String> {
+        let context = serde_json::json!({ "host": self.values });
+        let answer =
+            template::render(template, &context, &[lookup_dir.to_path_buf()]).map_err(|err| {
+                format_err!("Rendering answer file for '{}' failed: {err}", self.name)
+            })?;
+        if let Err(err) = toml::from_str::<Answer>(&answer) {
+            bail!("Answer file for '{}' is invalid: {err}", self.name);
+        }
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inventory() {
+        let hosts = parse_csv_inventory(
+            "# hosts\nname,fqdn,cidr\npve1,pve1.example.com,192.0.2.11/24\n\
+             \"pve2\", pve2.example.com ,192.0.2.12/24\n\
+             pve3,\"pve3.example.com\",\"192.0.2.13/24,198.51.100.13/24\"\n",
+        )
+        .unwrap();
+        assert_eq!(hosts.len(), 3);
+        assert_eq!(hosts[1].name, "pve2");
+        assert_eq!(hosts[1].values["fqdn"], "pve2.example.com");
+        assert_eq!(hosts[2].values["cidr"], "192.0.2.13/24,198.51.100.13/24");
+        assert!(parse_csv_inventory("name,fqdn\npve1,\"pve1.example.com\n").is_err());
+        assert!(parse_csv_inventory("name,fqdn\npve1\n").is_err());
+        assert!(parse_csv_inventory("fqdn\npve1.example.com\n").is_err());
+
+        let hosts = parse_toml_inventory(
+            "[[host]]\nname = \"pve1\"\nfqdn = \"pve1.example.com\"\nvlan = 10\n",
+        )
+        .unwrap();
+        assert_eq!(hosts[0].name, "pve1");
+        assert_eq!(hosts[0].values["vlan"], 10);
+
+        let answer = hosts[0]
+            .render_answer(
+                "[global]\nkeyboard = \"de\"\ncountry = \"at\"\nfqdn = \"{{ host.fqdn }}\"\n\
+                 mailto = \"root@example.com\"\ntimezone = \"Europe/Vienna\"\n\
+                 root_password = \"123456\"\n\n[network]\nsource = \"from-dhcp\"\n\n\
+                 [disk-setup]\nfilesystem = \"ext4\"\ndisk_list = [\"sda\"]\n",
+                Path::new("."),
+            )
+            .unwrap();
+        assert!(answer.contains("fqdn = \"pve1.example.com\""));
+        assert!(hosts[0]
+            .render_answer("fqdn = \"{{ host.missing }}\"\n", Path::new("."))
+            .is_err());
+    }
+}
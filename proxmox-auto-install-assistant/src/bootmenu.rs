@@ -0,0 +1,316 @@
+//! Adapting the boot menus of installation ISOs, for example for hosts with only a serial console.
+
+use anyhow::{bail, format_err, Result};
+
+use crate::iso::IsoImage;
+
+pub const GRUB_CONFIG: &str = "/boot/grub/grub.cfg";
+pub const ISOLINUX_CONFIG: &str = "/boot/isolinux/isolinux.cfg";
+
+/// Kernel parameters of the boot menu entry for the automated installation, see 'unconfigured.sh'.
+const AUTO_INSTALL_PARAMS: &[&str] = &["proxmox-start-auto-installer", "proxauto"];
+
+#[derive(Debug, Default)]
+pub struct BootMenuOptions {
+    /// Make the automated installation the default entry
+    pub default_auto: bool,
+    /// Menu timeout in seconds
+    pub timeout: Option<u32>,
+    /// Additional kernel parameters for all entries
+    pub kernel_params: Vec<String>,
+}
+
+impl BootMenuOptions {
+    pub fn is_empty(&self) -> bool {
+        !self.default_auto && self.timeout.is_none() && self.kernel_params.is_empty()
+    }
+}
+
+/// Checks that a kernel parameter can be added to the boot menu configurations as is.
+pub fn parse_kernel_param(param: &str) -> Result<String> {
+    if param.is_empty()
+        || param
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "\"'\\;$#{}".contains(c))
+    {
+        bail!("invalid kernel parameter '{param}'");
+    }
+    Ok(param.to_string())
+}
+
+/// Returns the adapted boot menu configurations of the image, with their paths.
+pub fn adapted_configs(
+    image: &IsoImage,
+    options: &BootMenuOptions,
+) -> Result<Vec<(&'static str, String)>> {
+    let mut configs = Vec::new();
+    for (path, adapt) in [
+        (
+            GRUB_CONFIG,
+            adapt_grub_config as fn(&str, &BootMenuOptions) -> Result<String>,
+        ),
+        (ISOLINUX_CONFIG, adapt_isolinux_config),
+    ] {
+        let Some(config) = image.read_file(path)? else {
+            continue;
+        };
+        let config = String::from_utf8(config)
+            .map_err(|_| format_err!("Boot menu configuration '{path}' is not valid UTF-8."))?;
+        let config = adapt(&config, options).map_err(|err| {
+            format_err!("Adapting boot menu configuration '{path}' failed: {err}")
+        })?;
+        configs.push((path, config));
+    }
+    if configs.is_empty() {
+        bail!("No boot menu configuration found in the ISO.");
+    }
+    Ok(configs)
+}
+
+fn is_auto_install_entry(kernel_cmdline: &str) -> bool {
+    kernel_cmdline
+        .split_whitespace()
+        .any(|param| AUTO_INSTALL_PARAMS.contains(&param))
+}
+
+/// Adapts a GRUB configuration. The default entry is set by its title, prefixed with the titles
+/// of the submenus it is in, like 'Advanced Options>Install Proxmox VE (Automated)'.
+fn adapt_grub_config(config: &str, options: &BootMenuOptions) -> Result<String> {
+    let mut lines = Vec::new();
+    // the titles of the currently open blocks, `None` for blocks other than menu entries
+    let mut blocks: Vec<Option<String>> = Vec::new();
+    let mut auto_entry = None;
+    let mut has_timeout = false;
+
+    for line in config.lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        let mut words = trimmed.split_whitespace();
+        let command = words.next().unwrap_or_default();
+
+        match command {
+            "set" if trimmed.starts_with("set timeout=") => {
+                if let Some(timeout) = options.timeout {
+                    lines.push(format!("{indent}set timeout={timeout}"));
+                    has_timeout = true;
+                    continue;
+                }
+            }
+            "set" if trimmed.starts_with("set default=") && options.default_auto => continue,
+            "linux" | "linuxefi" => {
+                if auto_entry.is_none() && is_auto_install_entry(trimmed) {
+                    let titles: Vec<&str> = blocks.iter().flatten().map(String::as_str).collect();
+                    auto_entry = Some(titles.join(">"));
+                }
+                if !options.kernel_params.is_empty() {
+                    let params = options.kernel_params.join(" ");
+                    lines.push(format!("{} {params}", line.trim_end()));
+                    continue;
+                }
+            }
+            "menuentry" | "submenu" => blocks.push(Some(grub_title(&trimmed[command.len()..])?)),
+            _ if trimmed.starts_with('}') => {
+                blocks.pop();
+            }
+            _ if trimmed.trim_end().ends_with('{') => blocks.push(None),
+            _ => {}
+        }
+        lines.push(line.to_string());
+    }
+
+    let mut header = Vec::new();
+    if options.default_auto {
+        let Some(auto_entry) = auto_entry else {
+            bail!("no boot menu entry for the automated installation found");
+        };
+        header.push(format!("set default=\"{auto_entry}\""));
+    }
+    if let (Some(timeout), false) = (options.timeout, has_timeout) {
+        header.push(format!("set timeout={timeout}"));
+    }
+    header.extend(lines);
+    Ok(header.join("\n") + "\n")
+}
+
+/// Returns the title of a GRUB menu entry, from the arguments of the 'menuentry' command.
+fn grub_title(args: &str) -> Result<String> {
+    let args = args.trim_start();
+    let title = match args.chars().next() {
+        Some(quote @ ('\'' | '"')) => args[1..]
+            .split_once(quote)
+            .map(|(title, _)| title)
+            .ok_or_else(|| format_err!("unterminated menu entry title '{args}'"))?,
+        _ => args.split_whitespace().next().unwrap_or_default(),
+    };
+    if title.contains('"') {
+        bail!("unsupported menu entry title '{title}'");
+    }
+    Ok(title.to_string())
+}
+
+/// Adapts an isolinux configuration, where the timeout is set in tenths of a second.
+fn adapt_isolinux_config(config: &str, options: &BootMenuOptions) -> Result<String> {
+    let mut lines = Vec::new();
+    let mut label = None;
+    let mut auto_label = None;
+    let mut has_timeout = false;
+
+    for line in config.lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        let (keyword, value) = trimmed
+            .split_once(char::is_whitespace)
+            .unwrap_or((trimmed, ""));
+
+        match keyword.to_ascii_uppercase().as_str() {
+            "TIMEOUT" => {
+                if let Some(timeout) = options.timeout {
+                    // a timeout of 0 waits forever
+                    lines.push(format!(
+                        "{indent}TIMEOUT {}",
+                        timeout.saturating_mul(10).max(1)
+                    ));
+                    has_timeout = true;
+                    continue;
+                }
+            }
+            "DEFAULT" | "ONTIMEOUT" if options.default_auto => continue,
+            "MENU" if options.default_auto && value.trim().eq_ignore_ascii_case("default") => {
+                continue;
+            }
+            "LABEL" => label = Some(value.trim().to_string()),
+            "APPEND" => {
+                if auto_label.is_none() && is_auto_install_entry(value) {
+                    auto_label = label.clone();
+                }
+                if !options.kernel_params.is_empty() {
+                    let params = options.kernel_params.join(" ");
+                    lines.push(format!("{} {params}", line.trim_end()));
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        lines.push(line.to_string());
+    }
+
+    let mut header = Vec::new();
+    if options.default_auto {
+        let Some(auto_label) = auto_label else {
+            bail!("no boot menu entry for the automated installation found");
+        };
+        header.push(format!("DEFAULT {auto_label}"));
+    }
+    if let (Some(timeout), false) = (options.timeout, has_timeout) {
+        header.push(format!("TIMEOUT {}", timeout.saturating_mul(10).max(1)));
+    }
+    header.extend(lines);
+    Ok(header.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRUB_CONFIG: &str = "\
+set default=0
+set timeout=4
+
+menuentry 'Install Proxmox VE (Graphical)' --class debian {
+    linux /boot/linux26 ro ramdisk_size=16777216 rw quiet splash=silent
+    initrd /boot/initrd.img
+}
+
+submenu 'Advanced Options' {
+    menuentry \"Install Proxmox VE (Automated)\" {
+        linux /boot/linux26 ro ramdisk_size=16777216 rw quiet splash=silent proxmox-start-auto-installer
+        initrd /boot/initrd.img
+    }
+}
+";
+
+    const ISOLINUX_CONFIG: &str = "\
+DEFAULT install
+TIMEOUT 40
+
+LABEL install
+  MENU DEFAULT
+  KERNEL /boot/linux26
+  APPEND initrd=/boot/initrd.img ro quiet
+
+LABEL auto
+  KERNEL /boot/linux26
+  APPEND initrd=/boot/initrd.img ro quiet proxmox-start-auto-installer
+";
+
+    fn options() -> BootMenuOptions {
+        BootMenuOptions {
+            default_auto: true,
+            timeout: Some(0),
+            kernel_params: vec!["console=ttyS0,115200".into(), "nomodeset".into()],
+        }
+    }
+
+    #[test]
+    fn adapt_grub() {
+        let config = adapt_grub_config(GRUB_CONFIG, &options()).unwrap();
+        assert_eq!(
+            config,
+            "\
+set default=\"Advanced Options>Install Proxmox VE (Automated)\"
+set timeout=0
+
+menuentry 'Install Proxmox VE (Graphical)' --class debian {
+    linux /boot/linux26 ro ramdisk_size=16777216 rw quiet splash=silent console=ttyS0,115200 nomodeset
+    initrd /boot/initrd.img
+}
+
+submenu 'Advanced Options' {
+    menuentry \"Install Proxmox VE (Automated)\" {
+        linux /boot/linux26 ro ramdisk_size=16777216 rw quiet splash=silent proxmox-start-auto-installer console=ttyS0,115200 nomodeset
+        initrd /boot/initrd.img
+    }
+}
+"
+        );
+
+        let no_auto_entry = GRUB_CONFIG.replace("proxmox-start-auto-installer", "proxtui");
+        assert!(adapt_grub_config(&no_auto_entry, &options()).is_err());
+        let options = BootMenuOptions {
+            timeout: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            adapt_grub_config("menuentry 'Install' {\n}\n", &options).unwrap(),
+            "set timeout=10\nmenuentry 'Install' {\n}\n"
+        );
+    }
+
+    #[test]
+    fn adapt_isolinux() {
+        let config = adapt_isolinux_config(ISOLINUX_CONFIG, &options()).unwrap();
+        assert_eq!(
+            config,
+            "\
+DEFAULT auto
+TIMEOUT 1
+
+LABEL install
+  KERNEL /boot/linux26
+  APPEND initrd=/boot/initrd.img ro quiet console=ttyS0,115200 nomodeset
+
+LABEL auto
+  KERNEL /boot/linux26
+  APPEND initrd=/boot/initrd.img ro quiet proxmox-start-auto-installer console=ttyS0,115200 nomodeset
+"
+        );
+    }
+
+    #[test]
+    fn kernel_params() {
+        assert!(parse_kernel_param("console=ttyS0,115200n8").is_ok());
+        assert!(parse_kernel_param("quiet splash").is_err());
+        assert!(parse_kernel_param("foo=\"bar\"").is_err());
+        assert!(parse_kernel_param("").is_err());
+    }
+}
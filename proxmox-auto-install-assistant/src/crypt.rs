@@ -0,0 +1,143 @@
+//! Hashing passwords with the system's libcrypt, as used for `root_password_hashed`.
+
+use anyhow::{bail, format_err, Result};
+use clap::{Args, ValueEnum};
+use std::{
+    ffi::{c_char, c_int, c_ulong, c_void, CStr, CString},
+    io,
+};
+
+use crate::prompt::Prompt;
+
+/// Size of `struct crypt_data` of libxcrypt, the scratch area of `crypt_rn`.
+const CRYPT_DATA_SIZE: usize = 32768;
+/// Size of the output buffer of `crypt_gensalt_rn`, `CRYPT_GENSALT_OUTPUT_SIZE` of libxcrypt.
+const CRYPT_GENSALT_OUTPUT_SIZE: usize = 192;
+
+/// Create a password hash for 'root_password_hashed'
+///
+/// The password is read from the terminal, or from the first line of the standard input if it is
+/// not a terminal, and its hash is printed.
+#[derive(Args, Debug)]
+pub struct CommandHashPassword {
+    /// Hashing method
+    #[arg(long, value_enum, default_value_t = HashMethod::Yescrypt)]
+    method: HashMethod,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum HashMethod {
+    /// yescrypt, the default for passwords on Debian
+    Yescrypt,
+    /// SHA-512 based crypt
+    Sha512,
+}
+
+impl HashMethod {
+    fn prefix(self) -> &'static str {
+        match self {
+            HashMethod::Yescrypt => "$y$",
+            HashMethod::Sha512 => "$6$",
+        }
+    }
+}
+
+#[link(name = "crypt")]
+extern "C" {
+    fn crypt_rn(
+        phrase: *const c_char,
+        setting: *const c_char,
+        data: *mut c_void,
+        size: c_int,
+    ) -> *mut c_char;
+    fn crypt_gensalt_rn(
+        prefix: *const c_char,
+        count: c_ulong,
+        rbytes: *const c_char,
+        nrbytes: c_int,
+        output: *mut c_char,
+        output_size: c_int,
+    ) -> *mut c_char;
+}
+
+pub fn hash_password_cmd(args: &CommandHashPassword) -> Result<()> {
+    // keep the standard output to the hash, for use in scripts
+    let mut prompt = Prompt::stdin(io::stderr());
+    let confirm = prompt.is_terminal();
+    let password = prompt.new_password("Password", confirm)?;
+    println!("{}", hash_password(&password, args.method)?);
+    Ok(())
+}
+
+/// Hashes the password with a random salt, in the format of `/etc/shadow`.
+pub fn hash_password(password: &str, method: HashMethod) -> Result<String> {
+    let prefix = CString::new(method.prefix())?;
+    let mut setting = vec![0 as c_char; CRYPT_GENSALT_OUTPUT_SIZE];
+    // SAFETY: the output buffer is valid for the given size and the prefix is NUL-terminated;
+    // with no random bytes given, libcrypt gathers them from the operating system.
+    let result = unsafe {
+        crypt_gensalt_rn(
+            prefix.as_ptr(),
+            0,
+            std::ptr::null(),
+            0,
+            setting.as_mut_ptr(),
+            setting.len() as c_int,
+        )
+    };
+    if result.is_null() {
+        bail!(
+            "Generating password salt failed: {}",
+            io::Error::last_os_error()
+        );
+    }
+    // SAFETY: on success, the setting is a NUL-terminated string within the buffer.
+    let setting = unsafe { CStr::from_ptr(setting.as_ptr()) };
+    crypt(password, setting)
+}
+
+fn crypt(password: &str, setting: &CStr) -> Result<String> {
+    let password = CString::new(password)
+        .map_err(|_| format_err!("The password must not contain NUL characters."))?;
+    let mut data = vec![0u8; CRYPT_DATA_SIZE];
+    // SAFETY: all strings are NUL-terminated and the scratch area has the size of
+    // `struct crypt_data`, which also holds the returned hash.
+    let hash = unsafe {
+        crypt_rn(
+            password.as_ptr(),
+            setting.as_ptr(),
+            data.as_mut_ptr() as *mut c_void,
+            data.len() as c_int,
+        )
+    };
+    if hash.is_null() {
+        bail!("Hashing password failed: {}", io::Error::last_os_error());
+    }
+    // SAFETY: on success, the hash is a NUL-terminated string within the scratch area.
+    let hash = unsafe { CStr::from_ptr(hash) }
+        .to_string_lossy()
+        .into_owned();
+    if hash.starts_with('*') {
+        bail!("Hashing password failed.");
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash() {
+        for method in [HashMethod::Yescrypt, HashMethod::Sha512] {
+            let hash = hash_password("123456", method).unwrap();
+            assert!(hash.starts_with(method.prefix()));
+            assert_ne!(hash, hash_password("123456", method).unwrap());
+
+            let setting = CString::new(hash.clone()).unwrap();
+            assert_eq!(crypt("123456", &setting).unwrap(), hash);
+            assert_ne!(crypt("1234567", &setting).unwrap(), hash);
+        }
+        assert!(hash_password("12\x003456", HashMethod::Yescrypt).is_err());
+    }
+}
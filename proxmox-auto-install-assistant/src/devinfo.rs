@@ -0,0 +1,251 @@
+//! Human readable output of the device and system information, to help with writing filters.
+
+use anyhow::{format_err, Result};
+use glob::Pattern;
+use serde_json::Value;
+use std::{collections::BTreeMap, fs};
+
+use proxmox_auto_installer::sysroot::SysRoot;
+
+use crate::Devicetype;
+
+/// The udev properties of devices, keyed by their name.
+pub type UdevDevices = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Default columns of the disk table. Besides udev properties, `SIZE` is available.
+pub const DISK_COLUMNS: &[&str] = &["SIZE", "ID_MODEL", "ID_SERIAL_SHORT", "ID_WWN"];
+/// Default columns of the network interface table. Besides udev properties, `MAC` is available.
+pub const NIC_COLUMNS: &[&str] = &[
+    "MAC",
+    "ID_NET_DRIVER",
+    "ID_MODEL_FROM_DATABASE",
+    "ID_NET_NAME_MAC",
+];
+
+/// Formats the devices as table with a row per device, showing the given properties.
+pub fn table(root: &SysRoot, kind: &Devicetype, devices: &UdevDevices, keys: &[String]) -> String {
+    let mut rows = vec![std::iter::once("NAME".to_string())
+        .chain(keys.iter().cloned())
+        .collect::<Vec<_>>()];
+    for (name, props) in devices {
+        let mut row = vec![name.clone()];
+        for key in keys {
+            let value = match (kind, key.as_str()) {
+                (Devicetype::Disk, "SIZE") => disk_size(root, name),
+                (Devicetype::Network, "MAC") => read_sysfs(root, "/sys/class/net", name, "address"),
+                _ => props.get(key).cloned(),
+            };
+            row.push(value.unwrap_or_else(|| "-".to_string()));
+        }
+        rows.push(row);
+    }
+
+    let widths: Vec<usize> = (0..=keys.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut out = String::new();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{value:<width$}"))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn read_sysfs(root: &SysRoot, class: &str, name: &str, attribute: &str) -> Option<String> {
+    let value = fs::read_to_string(root.path(class).join(name).join(attribute)).ok()?;
+    Some(value.trim().to_string())
+}
+
+fn disk_size(root: &SysRoot, name: &str) -> Option<String> {
+    // the size is always in 512 byte sectors, independent of the block size
+    let sectors: u64 = read_sysfs(root, "/sys/block", name, "size")?.parse().ok()?;
+    Some(format!(
+        "{:.2} GiB",
+        (sectors * 512) as f64 / 1024. / 1024. / 1024.
+    ))
+}
+
+/// Formats the devices as tree, with all their properties, or only the given ones.
+pub fn tree(devices: &UdevDevices, keys: &[String]) -> String {
+    let devices: BTreeMap<&String, BTreeMap<&String, &String>> = devices
+        .iter()
+        .map(|(name, props)| {
+            let props = props
+                .iter()
+                .filter(|(key, _)| keys.is_empty() || keys.contains(key))
+                .collect();
+            (name, props)
+        })
+        .collect();
+    value_tree(&serde_json::json!(devices))
+}
+
+/// Formats a JSON value as indented tree, similar to YAML.
+pub fn value_tree(value: &Value) -> String {
+    let mut out = String::new();
+    write_tree(&mut out, value, 0);
+    out
+}
+
+fn write_tree(out: &mut String, value: &Value, indent: usize) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    Value::Object(inner) if !inner.is_empty() => {
+                        out.push_str(&format!("{pad}{key}:\n"));
+                        write_tree(out, value, indent + 2);
+                    }
+                    Value::Array(inner) if !inner.is_empty() => {
+                        out.push_str(&format!("{pad}{key}:\n"));
+                        write_tree(out, value, indent + 2);
+                    }
+                    _ => out.push_str(&format!("{pad}{key}: {}\n", scalar(value))),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                // render the item on its own, then prefix its first line with the list marker
+                let mut inner = String::new();
+                write_tree(&mut inner, item, 0);
+                for (i, line) in inner.lines().enumerate() {
+                    let marker = if i == 0 { "- " } else { "  " };
+                    out.push_str(&format!("{pad}{marker}{line}\n"));
+                }
+            }
+        }
+        _ => out.push_str(&format!("{pad}{}\n", scalar(value))),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Null => "-".to_string(),
+        Value::Array(_) => "[]".to_string(),
+        Value::Object(_) => "{}".to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Explains for each device which of the filters matched. `selected` are the devices selected
+/// by the filters, as determined by the installer.
+pub fn explain_match(
+    kind: &Devicetype,
+    devices: &UdevDevices,
+    filters: &BTreeMap<String, String>,
+    selected: &[String],
+) -> Result<String> {
+    let patterns = filters
+        .iter()
+        .map(|(key, value)| {
+            let pattern = Pattern::new(value)
+                .map_err(|err| format_err!("Invalid glob in filter '{key}={value}': {err}"))?;
+            Ok((key, value, pattern))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let width = filters
+        .iter()
+        .map(|(key, value)| key.len() + value.len() + 1)
+        .max()
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    for (name, props) in devices {
+        let mut any_matched = false;
+        let mut lines = Vec::new();
+        for (key, value, pattern) in &patterns {
+            let filter = format!("{key}={value}");
+            lines.push(match props.get(*key) {
+                Some(actual) if pattern.matches(actual) => {
+                    any_matched = true;
+                    format!("  {filter:<width$}  matched      '{actual}'")
+                }
+                Some(actual) => format!("  {filter:<width$}  not matched  '{actual}'"),
+                None => format!("  {filter:<width$}  missing"),
+            });
+        }
+
+        let verdict = match (selected.contains(name), kind) {
+            (true, _) => "selected",
+            (false, Devicetype::Network) if any_matched => {
+                "not selected, only the first matching interface is used"
+            }
+            (false, _) => "not selected",
+        };
+        out.push_str(&format!("{name}: {verdict}\n"));
+        for line in lines {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> SysRoot {
+        SysRoot::new("../proxmox-auto-installer/tests/resources/sysroot")
+    }
+
+    #[test]
+    fn device_output() {
+        let root = fixture();
+        let disks = root.udev_disks().unwrap();
+        let keys: Vec<String> = DISK_COLUMNS.iter().map(|key| key.to_string()).collect();
+        assert_eq!(
+            table(&root, &Devicetype::Disk, &disks, &keys),
+            "NAME     SIZE        ID_MODEL                   ID_SERIAL_SHORT  ID_WWN\n\
+             nvme0n1  476.94 GiB  Samsung SSD 980 PRO 500GB  S5GXNF0R123456   -\n\
+             sda      931.51 GiB  ST1000NM0008-2F2100        ZFA1B2C3         -\n"
+        );
+
+        let nics = root.udev_nics().unwrap();
+        let keys = ["MAC".to_string(), "ID_NET_DRIVER".to_string()];
+        let table = table(&root, &Devicetype::Network, &nics, &keys);
+        assert!(table.starts_with("NAME    MAC                ID_NET_DRIVER\n"));
+        assert!(table.contains("\neno1    3c:ec:ef:00:11:22  e1000e\n"));
+
+        assert_eq!(
+            tree(&disks, &["ID_SERIAL_SHORT".to_string()]),
+            "nvme0n1:\n  ID_SERIAL_SHORT: S5GXNF0R123456\nsda:\n  ID_SERIAL_SHORT: ZFA1B2C3\n"
+        );
+        assert_eq!(
+            value_tree(&serde_json::json!({
+                "disks": [{"name": "sda", "size": 1024}, {"name": "sdb", "size": null}],
+                "macs": ["aa", "bb"],
+                "pci": [],
+            })),
+            "disks:\n  - name: sda\n    size: 1024\n  - name: sdb\n    size: -\n\
+             macs:\n  - aa\n  - bb\npci: []\n"
+        );
+
+        let filters = BTreeMap::from([
+            ("ID_SERIAL_SHORT".to_string(), "ZFA*".to_string()),
+            ("ID_WWN".to_string(), "0x5*".to_string()),
+        ]);
+        assert_eq!(
+            explain_match(&Devicetype::Disk, &disks, &filters, &["sda".to_string()]).unwrap(),
+            "nvme0n1: not selected\n\
+             \x20 ID_SERIAL_SHORT=ZFA*  not matched  'S5GXNF0R123456'\n\
+             \x20 ID_WWN=0x5*           missing\n\
+             sda: selected\n\
+             \x20 ID_SERIAL_SHORT=ZFA*  matched      'ZFA1B2C3'\n\
+             \x20 ID_WWN=0x5*           missing\n"
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! Converting the low-level configuration of an interactive installation into an answer file.
+
+use anyhow::{bail, format_err, Result};
+use clap::Args;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use proxmox_auto_installer::{answer::Answer, udevinfo::UdevInfo, utils::get_matched_udev_indexes};
+use proxmox_installer_common::{
+    options::{BtrfsRaidLevel, FsType, ZfsRaidLevel},
+    setup::InstallConfig,
+};
+
+/// Create an answer file from the configuration of an interactive installation.
+///
+/// The graphical and the text-based installer store the configuration of an installation in
+/// '/tmp/low-level-config.json'. Together with the udev information of the host, found in
+/// '/run/proxmox-installer/run-env-udev.json' during the installation, an answer file for the
+/// same setup is created.
+///
+/// The management interface is selected by its MAC address and the disks by their serial number.
+/// If more than one disk is used, they are selected by their model if it matches exactly the used
+/// disks, otherwise by their current names. Kernel names like 'sda' are not stable and can change
+/// between boots or with other disks attached, so check the 'disk_list' of such answer files,
+/// which is marked by a comment, before using them.
+#[derive(Args, Debug)]
+pub struct CommandAnswerFromConfig {
+    /// Path to the low-level configuration of the installation
+    config: PathBuf,
+
+    /// Path to the udev information of the host
+    #[arg(long)]
+    udev: PathBuf,
+
+    /// Path to write the answer file to, defaults to the standard output
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+/// An answer file, as written by the assistant.
+#[derive(Serialize)]
+pub struct AnswerOut {
+    pub global: GlobalOut,
+    pub network: NetworkOut,
+    #[serde(rename = "disk-setup")]
+    pub disks: DiskSetupOut,
+}
+
+#[derive(Serialize)]
+pub struct GlobalOut {
+    pub country: String,
+    pub fqdn: String,
+    pub keyboard: String,
+    pub mailto: String,
+    pub timezone: String,
+    pub root_password: Option<String>,
+    pub root_password_hashed: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub root_ssh_keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct NetworkOut {
+    pub source: &'static str,
+    pub cidr: String,
+    pub dns: String,
+    pub gateway: String,
+    pub filter: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+pub struct DiskSetupOut {
+    pub filesystem: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub disk_list: Vec<String>,
+    pub filter: Option<BTreeMap<String, String>>,
+    pub filter_match: Option<&'static str>,
+    pub zfs: Option<ZfsOut>,
+    pub lvm: Option<LvmOut>,
+    pub btrfs: Option<BtrfsOut>,
+}
+
+#[derive(Serialize)]
+pub struct ZfsOut {
+    pub raid: &'static str,
+    pub ashift: usize,
+    pub arc_max: Option<usize>,
+    pub checksum: String,
+    pub compress: String,
+    pub copies: usize,
+    pub hdsize: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct LvmOut {
+    pub hdsize: Option<f64>,
+    pub swapsize: Option<f64>,
+    pub maxroot: Option<f64>,
+    pub maxvz: Option<f64>,
+    pub minfree: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct BtrfsOut {
+    pub raid: &'static str,
+    pub hdsize: Option<f64>,
+}
+
+impl AnswerOut {
+    /// Returns the answer file and checks that it can be parsed.
+    pub fn to_toml(&self) -> Result<String> {
+        let mut answer = toml::to_string(self)?;
+        if !self.disks.disk_list.is_empty() {
+            answer = answer.replacen(
+                "\ndisk_list = ",
+                "\n# The disks are selected by their current kernel names, which can change\n\
+                 # between boots. Check them before use, or select the disks with a 'filter'.\n\
+                 disk_list = ",
+                1,
+            );
+        }
+        if let Err(err) = toml::from_str::<Answer>(&answer) {
+            bail!("The created answer file is invalid: {err}");
+        }
+        Ok(answer)
+    }
+
+    /// Writes the answer file to `output`, or to the standard output.
+    pub fn write(&self, output: Option<&Path>) -> Result<()> {
+        let answer = self.to_toml()?;
+        match output {
+            Some(path) => {
+                fs::write(path, answer)
+                    .map_err(|err| format_err!("Writing answer file {path:?} failed: {err}"))?;
+                println!("Answer file written to {path:?}.");
+            }
+            None => print!("{answer}"),
+        }
+        Ok(())
+    }
+}
+
+pub fn answer_from_config(args: &CommandAnswerFromConfig) -> Result<()> {
+    let config = fs::read_to_string(&args.config)
+        .map_err(|err| format_err!("Reading {:?} failed: {err}", args.config))?;
+    let config: Value = serde_json::from_str(&config)
+        .map_err(|err| format_err!("Error parsing {:?}: {err}", args.config))?;
+    let udev = fs::read_to_string(&args.udev)
+        .map_err(|err| format_err!("Reading {:?} failed: {err}", args.udev))?;
+    let udev: UdevInfo = serde_json::from_str(&udev)
+        .map_err(|err| format_err!("Error parsing {:?}: {err}", args.udev))?;
+
+    convert(config, &udev)?.write(args.output.as_deref())
+}
+
+/// Converts the low-level configuration into an answer file.
+fn convert(mut config: Value, udev: &UdevInfo) -> Result<AnswerOut> {
+    // 'target_hd' is skipped when deserializing, and the graphical installer stores the disk
+    // indexes of 'disk_selection' as numbers
+    let target_hd = config
+        .get("target_hd")
+        .and_then(Value::as_str)
+        .map(String::from);
+    if let Some(Value::Object(selection)) = config.get_mut("disk_selection") {
+        for index in selection.values_mut() {
+            if let Value::Number(number) = index {
+                *index = Value::String(number.to_string());
+            }
+        }
+    }
+    let config: InstallConfig = serde_json::from_value(config)
+        .map_err(|err| format_err!("Error parsing low-level configuration: {err}"))?;
+
+    Ok(AnswerOut {
+        global: GlobalOut {
+            country: config.country.clone(),
+            fqdn: format!("{}.{}", config.hostname, config.domain),
+            keyboard: config.keymap.clone(),
+            mailto: config.mailto.clone(),
+            timezone: config.timezone.clone(),
+            root_password: config.root_password.plain.clone(),
+            root_password_hashed: config.root_password.hashed.clone(),
+            root_ssh_keys: config.root_ssh_keys.clone(),
+        },
+        network: NetworkOut {
+            source: "from-answer",
+            cidr: config.cidr.to_string(),
+            dns: config.dns.to_string(),
+            gateway: config.gateway.to_string(),
+            filter: nic_filter(&config.mngmt_nic, udev)?,
+        },
+        disks: disk_setup(&config, target_hd.as_deref(), udev)?,
+    })
+}
+
+fn nic_filter(name: &str, udev: &UdevInfo) -> Result<BTreeMap<String, String>> {
+    let nic = udev
+        .nics
+        .get(name)
+        .ok_or_else(|| format_err!("Management interface '{name}' not found in udev info."))?;
+    let mac = nic
+        .get("ID_NET_NAME_MAC")
+        .ok_or_else(|| format_err!("No MAC address found for interface '{name}'."))?;
+    Ok(BTreeMap::from([("ID_NET_NAME_MAC".into(), mac.clone())]))
+}
+
+fn disk_setup(
+    config: &InstallConfig,
+    target_hd: Option<&str>,
+    udev: &UdevInfo,
+) -> Result<DiskSetupOut> {
+    let mut setup = DiskSetupOut {
+        filesystem: "ext4",
+        disk_list: Vec::new(),
+        filter: None,
+        filter_match: None,
+        zfs: None,
+        lvm: None,
+        btrfs: None,
+    };
+
+    let indexes: Vec<String> = match config.filesys {
+        FsType::Ext4 | FsType::Xfs => {
+            let Some(target_hd) = target_hd else {
+                bail!("No target disk found in low-level configuration.");
+            };
+            let index = udev
+                .disks
+                .iter()
+                .find(|(_, props)| props.get("DEVNAME").map(String::as_str) == Some(target_hd))
+                .map(|(index, _)| index.clone())
+                .ok_or_else(|| format_err!("Disk '{target_hd}' not found in udev info."))?;
+            vec![index]
+        }
+        FsType::Zfs(_) | FsType::Btrfs(_) => config.disk_selection.values().cloned().collect(),
+    };
+
+    match config.filesys {
+        FsType::Ext4 | FsType::Xfs => {
+            setup.filesystem = match config.filesys {
+                FsType::Xfs => "xfs",
+                _ => "ext4",
+            };
+            setup.lvm = Some(LvmOut {
+                hdsize: Some(config.hdsize),
+                swapsize: config.swapsize,
+                maxroot: config.maxroot,
+                maxvz: config.maxvz,
+                minfree: config.minfree,
+            });
+        }
+        FsType::Zfs(level) => {
+            let Some(opts) = &config.zfs_opts else {
+                bail!("No ZFS options found in low-level configuration.");
+            };
+            setup.filesystem = "zfs";
+            setup.zfs = Some(ZfsOut {
+                raid: zfs_raid_name(level),
+                ashift: opts.ashift,
+                arc_max: Some(opts.arc_max),
+                checksum: opts.checksum.to_string(),
+                compress: opts.compress.to_string(),
+                copies: opts.copies,
+                hdsize: Some(config.hdsize),
+            });
+        }
+        FsType::Btrfs(level) => {
+            setup.filesystem = "btrfs";
+            setup.btrfs = Some(BtrfsOut {
+                raid: btrfs_raid_name(level),
+                hdsize: Some(config.hdsize),
+            });
+        }
+    }
+
+    match disk_filter(&indexes, udev)? {
+        Some(filter) => {
+            setup.filter = Some(filter);
+            setup.filter_match = Some("all");
+        }
+        None => {
+            eprintln!(
+                "Warning: the disks cannot be selected by udev properties, using their current \
+                 names instead."
+            );
+            for index in &indexes {
+                let name = udev
+                    .disks
+                    .get(index)
+                    .and_then(|props| props.get("DEVNAME"))
+                    .ok_or_else(|| format_err!("Disk with index '{index}' not found."))?;
+                setup
+                    .disk_list
+                    .push(name.trim_start_matches("/dev/").to_string());
+            }
+        }
+    }
+    Ok(setup)
+}
+
+/// Returns a filter matching exactly the disks with the udev indexes, either by their serial
+/// number for a single disk or by their model.
+fn disk_filter(indexes: &[String], udev: &UdevInfo) -> Result<Option<BTreeMap<String, String>>> {
+    let props: Vec<&BTreeMap<String, String>> = indexes
+        .iter()
+        .map(|index| {
+            udev.disks
+                .get(index)
+                .ok_or_else(|| format_err!("Disk with index '{index}' not found in udev info."))
+        })
+        .collect::<Result<_>>()?;
+
+    let key = match props.len() {
+        0 => bail!("No disks found in low-level configuration."),
+        1 => "ID_SERIAL",
+        _ => "ID_MODEL",
+    };
+    let Some(value) = props[0].get(key) else {
+        return Ok(None);
+    };
+    if props.iter().any(|props| props.get(key) != Some(value)) || value.contains(['*', '?', '[']) {
+        return Ok(None);
+    }
+
+    let filter = BTreeMap::from([(key.to_string(), value.clone())]);
+    let mut expected = indexes.to_vec();
+    expected.sort();
+    match get_matched_udev_indexes(&filter, &udev.disks, true) {
+        Ok(matched) if matched == expected => Ok(Some(filter)),
+        _ => Ok(None),
+    }
+}
+
+pub fn zfs_raid_name(level: ZfsRaidLevel) -> &'static str {
+    match level {
+        ZfsRaidLevel::Raid0 => "raid0",
+        ZfsRaidLevel::Raid1 => "raid1",
+        ZfsRaidLevel::Raid10 => "raid10",
+        ZfsRaidLevel::RaidZ => "raidz-1",
+        ZfsRaidLevel::RaidZ2 => "raidz-2",
+        ZfsRaidLevel::RaidZ3 => "raidz-3",
+    }
+}
+
+pub fn btrfs_raid_name(level: BtrfsRaidLevel) -> &'static str {
+    match level {
+        BtrfsRaidLevel::Raid0 => "raid0",
+        BtrfsRaidLevel::Raid1 => "raid1",
+        BtrfsRaidLevel::Raid10 => "raid10",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOURCES: &str = "../proxmox-auto-installer/tests/resources";
+
+    fn udev() -> UdevInfo {
+        let udev = fs::read_to_string(format!("{RESOURCES}/run-env-udev.json")).unwrap();
+        serde_json::from_str(&udev).unwrap()
+    }
+
+    fn convert_resource(name: &str, udev: &UdevInfo) -> toml::Table {
+        let config = fs::read_to_string(format!("{RESOURCES}/parse_answer/{name}.json")).unwrap();
+        let answer = convert(serde_json::from_str(&config).unwrap(), udev).unwrap();
+        toml::from_str(&answer.to_toml().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn from_config() {
+        let udev = udev();
+        let answer = convert_resource("minimal", &udev);
+        assert_eq!(
+            answer["global"]["fqdn"].as_str(),
+            Some("pveauto.testinstall")
+        );
+        assert_eq!(answer["global"]["root_password"].as_str(), Some("123456"));
+        assert_eq!(
+            answer["network"]["filter"]["ID_NET_NAME_MAC"].as_str(),
+            Some("enxb42e99acadb4")
+        );
+        let disks = &answer["disk-setup"];
+        assert_eq!(disks["filesystem"].as_str(), Some("ext4"));
+        assert_eq!(
+            disks["filter"]["ID_SERIAL"].as_str(),
+            Some("SAMSUNG_MZ7KM240HAGR-00005_S2HRNX0J403550")
+        );
+
+        let answer = convert_resource("zfs", &udev);
+        let disks = &answer["disk-setup"];
+        assert_eq!(disks["zfs"]["raid"].as_str(), Some("raid1"));
+        assert_eq!(disks["zfs"]["compress"].as_str(), Some("lz4"));
+        assert_eq!(disks["zfs"]["copies"].as_integer(), Some(2));
+        // two of the four disks of that model are used
+        assert!(disks.get("filter").is_none());
+        assert_eq!(
+            disks["disk_list"].as_array().unwrap(),
+            &["sda", "sdb"].map(toml::Value::from)
+        );
+
+        let config = fs::read_to_string(format!("{RESOURCES}/parse_answer/zfs.json")).unwrap();
+        let answer = convert(serde_json::from_str(&config).unwrap(), &udev).unwrap();
+        assert!(answer.to_toml().unwrap().contains(
+            "\n# between boots. Check them before use, or select the disks with a 'filter'.\n\
+             disk_list = [\"sda\", \"sdb\"]\n"
+        ));
+    }
+}
@@ -0,0 +1,801 @@
+//! Creating an answer file from an installed system, to reinstall it with the same identity.
+
+use anyhow::{bail, format_err, Result};
+use clap::Args;
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use proxmox_installer_common::{
+    setup::{read_json, LocaleInfo},
+    RUNTIME_DIR,
+};
+
+use crate::fromconfig::{AnswerOut, BtrfsOut, DiskSetupOut, GlobalOut, LvmOut, NetworkOut, ZfsOut};
+
+/// Create an answer file from an installed system, to reinstall it with the same identity.
+///
+/// The root file system of the installed system must be mounted, for example with
+/// 'proxmox-chroot prepare', and its ZFS pool, LVM volume group or Btrfs file system must be
+/// available on the current host.
+///
+/// The host name, network configuration, time zone, keyboard layout, root password and SSH keys
+/// and the email address of the administrator are read from the installed system. The disk setup
+/// is taken over from the root file system, including the disks it is located on. To reinstall
+/// onto new disks, set them with '--disk'.
+#[derive(Args, Debug)]
+pub struct CommandAnswerFromSystem {
+    /// Path the root file system of the installed system is mounted at
+    target: PathBuf,
+
+    /// Disk to install to instead of the current ones, like 'sda'. Can be specified multiple
+    /// times.
+    #[arg(long = "disk")]
+    disks: Vec<String>,
+
+    /// Email address of the administrator, if it cannot be found in the installed system
+    #[arg(long)]
+    mailto: Option<String>,
+
+    /// Path to the locale information of the installer, defaults to the one of the running
+    /// installation environment
+    #[arg(long)]
+    locales: Option<PathBuf>,
+
+    /// Path to write the answer file to, defaults to the standard output
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+/// The interface with the address of the host, as configured in '/etc/network/interfaces'.
+#[derive(Debug, PartialEq)]
+struct ManagementInterface {
+    /// The physical interface, the first port of a bridge or bond
+    nic: String,
+    cidr: String,
+    gateway: String,
+}
+
+/// The layout of a ZFS pool, from 'zpool status'.
+#[derive(Debug, PartialEq)]
+struct PoolLayout {
+    raid: &'static str,
+    devices: Vec<String>,
+}
+
+pub fn answer_from_system(args: &CommandAnswerFromSystem) -> Result<()> {
+    let target = &args.target;
+    let locales_path = args
+        .locales
+        .clone()
+        .unwrap_or_else(|| Path::new(RUNTIME_DIR).join("locales.json"));
+    let locales: LocaleInfo = read_json(&locales_path)
+        .map_err(|err| format_err!("Reading locale information {locales_path:?} failed: {err}"))?;
+
+    let hostname = read_target_file(target, "/etc/hostname")?;
+    let hosts = read_target_file(target, "/etc/hosts").unwrap_or_default();
+    let resolv_conf = read_target_file(target, "/etc/resolv.conf").unwrap_or_default();
+    let fqdn = fqdn(&hostname, &hosts, &resolv_conf)?;
+
+    let interfaces = read_target_file(target, "/etc/network/interfaces")?;
+    let iface = management_interface(&interfaces)?;
+    let Some(dns) = nameserver(&resolv_conf) else {
+        bail!("No name server found in '/etc/resolv.conf'.");
+    };
+
+    let timezone = read_target_file(target, "/etc/timezone")?
+        .trim()
+        .to_string();
+    let keyboard = read_target_file(target, "/etc/default/keyboard")?;
+    let keyboard = keymap(&keyboard, &locales)?;
+
+    let debconf = read_target_file(target, "/var/cache/debconf/config.dat").unwrap_or_default();
+    let country = debconf_value(&debconf, "pve-manager/country")
+        .map(|country| country.to_lowercase())
+        .or_else(|| country_of_timezone(&timezone, &locales))
+        .ok_or_else(|| format_err!("Could not determine the country."))?;
+
+    let mailto = match &args.mailto {
+        Some(mailto) => mailto.clone(),
+        None => find_mailto(target).ok_or_else(|| {
+            format_err!("Could not determine the email address, set it with '--mailto'.")
+        })?,
+    };
+
+    let shadow = read_target_file(target, "/etc/shadow")?;
+    let root_password_hashed = shadow
+        .lines()
+        .find_map(|line| line.strip_prefix("root:"))
+        .and_then(|rest| rest.split(':').next())
+        .filter(|hash| hash.starts_with('$'))
+        .map(String::from);
+    if root_password_hashed.is_none() {
+        bail!("No root password found in '/etc/shadow'.");
+    }
+
+    let root_ssh_keys = match read_authorized_keys(target) {
+        Ok(keys) => keys
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect(),
+        Err(err) => {
+            eprintln!("Warning: root SSH keys not taken over: {err}");
+            Vec::new()
+        }
+    };
+
+    let answer = AnswerOut {
+        global: GlobalOut {
+            country,
+            fqdn,
+            keyboard,
+            mailto,
+            timezone,
+            root_password: None,
+            root_password_hashed,
+            root_ssh_keys,
+        },
+        network: NetworkOut {
+            source: "from-answer",
+            cidr: iface.cidr,
+            dns,
+            gateway: iface.gateway,
+            filter: nic_filter(&iface.nic),
+        },
+        disks: disk_setup(target, &args.disks)?,
+    };
+    answer.write(args.output.as_deref())
+}
+
+fn target_path(target: &Path, path: &str) -> PathBuf {
+    target.join(path.trim_start_matches('/'))
+}
+
+fn read_target_file(target: &Path, path: &str) -> Result<String> {
+    fs::read_to_string(target_path(target, path))
+        .map_err(|err| format_err!("Reading '{path}' of installed system failed: {err}"))
+}
+
+/// Reads the SSH keys of root. On Proxmox VE, the file is a link into the cluster file system,
+/// which is not available in the installed system.
+fn read_authorized_keys(target: &Path) -> Result<String> {
+    let path = "/root/.ssh/authorized_keys";
+    match fs::read_link(target_path(target, path)) {
+        Ok(link) if link.is_absolute() => read_target_file(target, &link.to_string_lossy())
+            .map_err(|_| {
+                format_err!(
+                    "'{path}' links to '{}', which is not available",
+                    link.display()
+                )
+            }),
+        _ => read_target_file(target, path),
+    }
+}
+
+fn fqdn(hostname: &str, hosts: &str, resolv_conf: &str) -> Result<String> {
+    let hostname = hostname.trim();
+    if hostname.is_empty() {
+        bail!("Empty host name in '/etc/hostname'.");
+    }
+    if hostname.contains('.') {
+        return Ok(hostname.to_string());
+    }
+
+    let prefix = format!("{hostname}.");
+    let from_hosts = hosts
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split_whitespace().skip(1))
+        .find(|name| name.starts_with(&prefix));
+    if let Some(fqdn) = from_hosts {
+        return Ok(fqdn.to_string());
+    }
+
+    let search = resolv_conf.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("search" | "domain") => words.next(),
+            _ => None,
+        }
+    });
+    match search {
+        Some(domain) => Ok(format!("{hostname}.{domain}")),
+        None => bail!("Could not determine the domain of '{hostname}'."),
+    }
+}
+
+fn nameserver(resolv_conf: &str) -> Option<String> {
+    resolv_conf.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("nameserver") => words.next().map(String::from),
+            _ => None,
+        }
+    })
+}
+
+/// Returns the options of all 'iface' stanzas of an interfaces file, by interface name.
+fn parse_interfaces(content: &str) -> Vec<(String, BTreeMap<String, String>)> {
+    let mut stanzas: Vec<(String, BTreeMap<String, String>)> = Vec::new();
+    let mut current = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (keyword, value) = line
+            .split_once(char::is_whitespace)
+            .map(|(keyword, value)| (keyword, value.trim()))
+            .unwrap_or((line, ""));
+        match keyword {
+            "iface" => {
+                let name = value.split_whitespace().next().unwrap_or_default();
+                stanzas.push((name.to_string(), BTreeMap::new()));
+                current = true;
+            }
+            "auto" | "source" | "source-directory" | "mapping" => current = false,
+            _ if keyword.starts_with("allow-") => current = false,
+            _ if current => {
+                let options = &mut stanzas.last_mut().unwrap().1;
+                options.insert(keyword.to_string(), value.to_string());
+            }
+            _ => {}
+        }
+    }
+    stanzas
+}
+
+fn management_interface(content: &str) -> Result<ManagementInterface> {
+    let stanzas = parse_interfaces(content);
+    let Some((name, options)) = stanzas
+        .iter()
+        .find(|(_, options)| options.contains_key("address") && options.contains_key("gateway"))
+    else {
+        bail!("No interface with an address and a gateway found in '/etc/network/interfaces'.");
+    };
+
+    let address = &options["address"];
+    let cidr = match (address.contains('/'), options.get("netmask")) {
+        (true, _) => address.clone(),
+        (false, Some(netmask)) => {
+            let netmask: Ipv4Addr = netmask
+                .parse()
+                .map_err(|_| format_err!("Invalid netmask '{netmask}' of '{name}'."))?;
+            format!("{address}/{}", u32::from(netmask).count_ones())
+        }
+        (false, None) => bail!("No netmask found for address '{address}' of '{name}'."),
+    };
+
+    // follow bridges and bonds down to the first physical interface
+    let mut nic = name.clone();
+    for _ in 0..2 {
+        let Some((_, options)) = stanzas.iter().find(|(name, _)| *name == nic) else {
+            break;
+        };
+        let port = ["bridge-ports", "bridge_ports", "bond-slaves", "bond_slaves"]
+            .iter()
+            .filter_map(|key| options.get(*key))
+            .flat_map(|ports| ports.split_whitespace())
+            .find(|port| *port != "none");
+        match port {
+            Some(port) => nic = port.to_string(),
+            None => break,
+        }
+    }
+
+    Ok(ManagementInterface {
+        nic,
+        cidr,
+        gateway: options["gateway"].clone(),
+    })
+}
+
+/// Selects the interface by its MAC address if it exists on the current host, as for reinstalls
+/// on the same hardware, otherwise by its name.
+fn nic_filter(nic: &str) -> BTreeMap<String, String> {
+    let mac = fs::read_to_string(format!("/sys/class/net/{nic}/address"))
+        .ok()
+        .map(|mac| mac.trim().replace(':', ""))
+        .filter(|mac| mac.len() == 12);
+    match mac {
+        Some(mac) => BTreeMap::from([("ID_NET_NAME_MAC".into(), format!("enx{mac}"))]),
+        None => {
+            eprintln!("Warning: interface '{nic}' not found, selecting it by its name.");
+            BTreeMap::from([("ID_NET_NAME".into(), nic.to_string())])
+        }
+    }
+}
+
+/// Returns the keyboard layout of the answer file for the layout in '/etc/default/keyboard'.
+fn keymap(content: &str, locales: &LocaleInfo) -> Result<String> {
+    let value = |key: &str| {
+        content.lines().find_map(|line| {
+            let (name, value) = line.trim().split_once('=')?;
+            (name == key).then(|| value.trim_matches('"').to_string())
+        })
+    };
+    let Some(layout) = value("XKBLAYOUT") else {
+        bail!("No keyboard layout found in '/etc/default/keyboard'.");
+    };
+    let variant = value("XKBVARIANT").unwrap_or_default();
+
+    let mut candidates: Vec<(&String, bool)> = locales
+        .kmap
+        .iter()
+        .filter(|(_, kmap)| kmap.xkb_layout == layout)
+        .map(|(key, kmap)| (key, kmap.xkb_variant == variant))
+        .collect();
+    // prefer a matching variant, then the layout named like the key, like 'de' for 'de'
+    candidates.sort_by_key(|(key, same_variant)| (!same_variant, **key != layout, (*key).clone()));
+    match candidates.first() {
+        Some((key, _)) => Ok(key.to_string()),
+        None => bail!("Unknown keyboard layout '{layout}'."),
+    }
+}
+
+/// Returns the value of a debconf question from the 'config.dat' database.
+fn debconf_value(content: &str, name: &str) -> Option<String> {
+    content
+        .split("\n\n")
+        .find(|entry| entry.lines().next() == Some(&format!("Name: {name}")))?
+        .lines()
+        .find_map(|line| line.strip_prefix("Value: "))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn country_of_timezone(timezone: &str, locales: &LocaleInfo) -> Option<String> {
+    let mut countries: Vec<&String> = locales
+        .cczones
+        .iter()
+        .filter(|(_, zones)| zones.iter().any(|zone| zone == timezone))
+        .map(|(country, _)| country)
+        .collect();
+    countries.sort();
+    countries.first().map(|country| country.to_string())
+}
+
+/// Looks up the email address of root in the configuration of the product.
+fn find_mailto(target: &Path) -> Option<String> {
+    if let Ok(config) = read_target_file(target, "/etc/pmg/pmg.conf") {
+        return section_email(&config, "section: admin");
+    }
+    if let Ok(config) = read_target_file(target, "/etc/proxmox-backup/user.cfg") {
+        return section_email(&config, "user: root@pam");
+    }
+    // the configuration database of the cluster file system contains the file content as is
+    let db = fs::read(target_path(target, "/var/lib/pve-cluster/config.db")).ok()?;
+    pve_user_email(&db)
+}
+
+fn section_email(config: &str, header: &str) -> Option<String> {
+    config
+        .split("\n\n")
+        .find(|section| section.trim_start().starts_with(header))?
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("email "))
+        .map(|email| email.trim().to_string())
+}
+
+/// Returns the email address of 'root@pam' from the 'user.cfg' of Proxmox VE, within `data`.
+fn pve_user_email(data: &[u8]) -> Option<String> {
+    let pattern = b"user:root@pam:";
+    let start = data
+        .windows(pattern.len())
+        .position(|window| window == pattern)?;
+    let line: Vec<u8> = data[start..]
+        .iter()
+        .take_while(|c| c.is_ascii_graphic() || **c == b' ')
+        .copied()
+        .collect();
+    // user:root@pam:<enable>:<expire>:<firstname>:<lastname>:<email>:...
+    String::from_utf8(line)
+        .ok()?
+        .split(':')
+        .nth(6)
+        .filter(|email| email.contains('@'))
+        .map(String::from)
+}
+
+/// Returns the source and the type of the file system mounted at `target`.
+fn root_mount(target: &Path) -> Result<(String, String)> {
+    let target = fs::canonicalize(target)
+        .map_err(|err| format_err!("Target {target:?} not found: {err}"))?;
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    // the last mount at the path is the visible one
+    mounts
+        .lines()
+        .rev()
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [source, mountpoint, fstype, ..] if Path::new(mountpoint) == target => {
+                    Some((source.to_string(), fstype.to_string()))
+                }
+                _ => None,
+            }
+        })
+        .ok_or_else(|| format_err!("No file system mounted at {target:?}."))
+}
+
+fn run(command: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(command)
+        .args(args)
+        .output()
+        .map_err(|err| format_err!("Running '{command}' failed: {err}"))?;
+    if !output.status.success() {
+        bail!(
+            "'{command} {}' failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn disk_setup(target: &Path, disks: &[String]) -> Result<DiskSetupOut> {
+    let (source, fstype) = root_mount(target)?;
+    let mut setup = DiskSetupOut {
+        filesystem: "ext4",
+        disk_list: Vec::new(),
+        filter: None,
+        filter_match: None,
+        zfs: None,
+        lvm: None,
+        btrfs: None,
+    };
+
+    let devices = match fstype.as_str() {
+        "zfs" => {
+            let pool = source.split('/').next().unwrap_or_default();
+            let layout = parse_zpool_status(&run("zpool", &["status", "-P", pool])?)?;
+            let ashift = run("zpool", &["get", "-Hp", "-o", "value", "ashift", pool])?;
+            let properties = run(
+                "zfs",
+                &[
+                    "get",
+                    "-H",
+                    "-o",
+                    "value",
+                    "compression,checksum,copies",
+                    pool,
+                ],
+            )?;
+            let properties: Vec<&str> = properties.lines().collect();
+            let [compress, checksum, copies] = properties[..] else {
+                bail!("Unexpected output of 'zfs get'.");
+            };
+            let zfs_conf = read_target_file(target, "/etc/modprobe.d/zfs.conf").unwrap_or_default();
+
+            setup.filesystem = "zfs";
+            setup.zfs = Some(ZfsOut {
+                raid: layout.raid,
+                ashift: ashift.trim().parse()?,
+                arc_max: zfs_arc_max(&zfs_conf),
+                checksum: checksum.to_string(),
+                compress: compress.to_string(),
+                copies: copies.parse()?,
+                hdsize: None,
+            });
+            layout.devices
+        }
+        "btrfs" => {
+            let target = target.to_string_lossy();
+            let raid = btrfs_raid(&run("btrfs", &["filesystem", "df", &target])?)?;
+            setup.filesystem = "btrfs";
+            setup.btrfs = Some(BtrfsOut { raid, hdsize: None });
+            btrfs_devices(&run("btrfs", &["filesystem", "show", &target])?)
+        }
+        "ext4" | "xfs" => {
+            setup.filesystem = if fstype == "xfs" { "xfs" } else { "ext4" };
+            let vg = run("lvs", &["--noheadings", "-o", "vg_name", &source])?;
+            let vg = vg.trim();
+            let sizes = run(
+                "lvs",
+                &[
+                    "--noheadings",
+                    "--units",
+                    "g",
+                    "--nosuffix",
+                    "-o",
+                    "lv_name,lv_size",
+                    vg,
+                ],
+            )?;
+            let size = |name: &str| {
+                sizes.lines().find_map(|line| {
+                    let mut fields = line.split_whitespace();
+                    (fields.next() == Some(name)).then(|| fields.next()?.parse::<f64>().ok())?
+                })
+            };
+            let free = run(
+                "vgs",
+                &[
+                    "--noheadings",
+                    "--units",
+                    "g",
+                    "--nosuffix",
+                    "-o",
+                    "vg_free",
+                    vg,
+                ],
+            )?;
+            setup.lvm = Some(LvmOut {
+                hdsize: None,
+                swapsize: size("swap"),
+                maxroot: size("root"),
+                maxvz: size("data"),
+                minfree: free.trim().parse().ok(),
+            });
+            let pvs = run(
+                "pvs",
+                &[
+                    "--noheadings",
+                    "-o",
+                    "pv_name",
+                    "-S",
+                    &format!("vg_name={vg}"),
+                ],
+            )?;
+            pvs.lines().map(|line| line.trim().to_string()).collect()
+        }
+        _ => bail!("Unsupported root file system '{fstype}' on '{source}'."),
+    };
+
+    setup.disk_list = match disks.is_empty() {
+        true => devices
+            .iter()
+            .map(|device| parent_disk(device))
+            .collect::<Result<_>>()?,
+        false => disks.to_vec(),
+    };
+    setup.disk_list.dedup();
+    if setup.lvm.is_some() && setup.disk_list.len() != 1 {
+        bail!("Exactly one disk is required for '{}'.", setup.filesystem);
+    }
+    Ok(setup)
+}
+
+fn parse_zpool_status(output: &str) -> Result<PoolLayout> {
+    let mut lines = output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("NAME"))
+        .skip(1)
+        .take_while(|line| !line.trim().is_empty());
+    let Some(pool) = lines.next() else {
+        bail!("No pool found in 'zpool status' output.");
+    };
+    let pool_indent = pool.len() - pool.trim_start().len();
+
+    let mut groups = Vec::new();
+    let mut devices = Vec::new();
+    for line in lines {
+        // the data devices end with the 'logs', 'cache' or similar sections
+        if line.len() - line.trim_start().len() <= pool_indent {
+            break;
+        }
+        let name = line.split_whitespace().next().unwrap_or_default();
+        if name.starts_with('/') {
+            devices.push(name.to_string());
+        } else if let Some((kind, _)) = name.rsplit_once('-') {
+            groups.push(kind.to_string());
+        }
+    }
+
+    let raid = match (groups.first().map(String::as_str), groups.len()) {
+        (None, _) => "raid0",
+        (Some("mirror"), 1) => "raid1",
+        (Some("mirror"), _) => "raid10",
+        (Some("raidz1" | "raidz"), 1) => "raidz-1",
+        (Some("raidz2"), 1) => "raidz-2",
+        (Some("raidz3"), 1) => "raidz-3",
+        (Some(kind), _) => bail!("Unsupported pool layout with '{kind}' groups."),
+    };
+    if devices.is_empty() {
+        bail!("No devices found in 'zpool status' output.");
+    }
+    Ok(PoolLayout { raid, devices })
+}
+
+/// Returns the ZFS ARC size limit in MiB from the module options.
+fn zfs_arc_max(content: &str) -> Option<usize> {
+    content
+        .split_whitespace()
+        .find_map(|option| option.strip_prefix("zfs_arc_max="))
+        .and_then(|bytes| bytes.parse::<usize>().ok())
+        .map(|bytes| bytes / 1024 / 1024)
+}
+
+fn btrfs_raid(df: &str) -> Result<&'static str> {
+    let profile = df
+        .lines()
+        .find_map(|line| line.strip_prefix("Data, "))
+        .and_then(|rest| rest.split(':').next())
+        .unwrap_or_default();
+    Ok(match profile {
+        "single" | "RAID0" => "raid0",
+        "RAID1" => "raid1",
+        "RAID10" => "raid10",
+        _ => bail!("Unsupported Btrfs data profile '{profile}'."),
+    })
+}
+
+fn btrfs_devices(show: &str) -> Vec<String> {
+    show.lines()
+        .filter(|line| line.trim_start().starts_with("devid"))
+        .filter_map(|line| line.split_whitespace().last())
+        .map(String::from)
+        .collect()
+}
+
+/// Returns the name of the disk a partition or device belongs to, like 'sda' for '/dev/sda3'.
+fn parent_disk(device: &str) -> Result<String> {
+    let device = fs::canonicalize(device)
+        .map_err(|err| format_err!("Device '{device}' not found: {err}"))?;
+    let name = device.file_name().unwrap_or_default().to_string_lossy();
+    let sys = Path::new("/sys/class/block").join(&*name);
+    if !sys.join("partition").exists() {
+        return Ok(name.into_owned());
+    }
+    let sys = fs::canonicalize(&sys)?;
+    match sys.parent().and_then(Path::file_name) {
+        Some(disk) => Ok(disk.to_string_lossy().into_owned()),
+        None => bail!("No disk found for partition '{name}'."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locales() -> LocaleInfo {
+        read_json("../proxmox-auto-installer/tests/resources/locales.json").unwrap()
+    }
+
+    #[test]
+    fn interfaces() {
+        let interfaces = "\
+auto lo
+iface lo inet loopback
+
+iface eno1 inet manual
+
+auto vmbr0
+iface vmbr0 inet static
+	address 192.168.1.114/24
+	gateway 192.168.1.1
+	bridge-ports eno1
+	bridge-stp off
+	bridge-fd 0
+
+iface eno2 inet manual
+
+source /etc/network/interfaces.d/*
+";
+        assert_eq!(
+            management_interface(interfaces).unwrap(),
+            ManagementInterface {
+                nic: "eno1".into(),
+                cidr: "192.168.1.114/24".into(),
+                gateway: "192.168.1.1".into(),
+            }
+        );
+
+        let interfaces = "\
+auto bond0
+iface bond0 inet manual
+    bond-slaves enp1s0 enp2s0
+    bond-mode active-backup
+
+auto vmbr0
+iface vmbr0 inet static
+    address 10.0.0.2
+    netmask 255.255.255.0
+    gateway 10.0.0.1
+    bridge-ports bond0
+";
+        let iface = management_interface(interfaces).unwrap();
+        assert_eq!(iface.nic, "enp1s0");
+        assert_eq!(iface.cidr, "10.0.0.2/24");
+        assert!(management_interface("iface eno1 inet dhcp\n").is_err());
+    }
+
+    #[test]
+    fn identity() {
+        let hosts = "127.0.0.1 localhost.localdomain localhost\n\
+                     192.168.1.114 pveauto.testinstall pveauto\n";
+        let resolv_conf = "search example.com\nnameserver 192.168.1.254\n";
+        assert_eq!(
+            fqdn("pveauto\n", hosts, resolv_conf).unwrap(),
+            "pveauto.testinstall"
+        );
+        assert_eq!(
+            fqdn("pve2", hosts, resolv_conf).unwrap(),
+            "pve2.example.com"
+        );
+        assert_eq!(nameserver(resolv_conf).unwrap(), "192.168.1.254");
+
+        let locales = locales();
+        let keyboard = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"ch\"\nXKBVARIANT=\"fr_nodeadkeys\"\n";
+        assert_eq!(keymap(keyboard, &locales).unwrap(), "fr-ch");
+        assert_eq!(keymap("XKBLAYOUT=\"de\"\n", &locales).unwrap(), "de");
+        assert!(keymap("XKBLAYOUT=\"xx\"\n", &locales).is_err());
+        assert_eq!(
+            country_of_timezone("Europe/Vienna", &locales).unwrap(),
+            "at"
+        );
+
+        let debconf = "Name: pve-manager/country\nTemplate: pve-manager/country\nValue: AT\n\
+                       Owner: pve-manager\n\nName: tzdata/Areas\nValue: Europe\n";
+        assert_eq!(debconf_value(debconf, "pve-manager/country").unwrap(), "AT");
+        assert!(debconf_value(debconf, "missing").is_none());
+
+        let db = b"\x00\x13user.cfg\x00user:root@pam:1:0:::mail@example.com::\n\x00";
+        assert_eq!(pve_user_email(db).unwrap(), "mail@example.com");
+        let pmg_conf = "section: admin\n\temail mail@example.com\n\nsection: mail\n\tport 25\n";
+        assert_eq!(
+            section_email(pmg_conf, "section: admin").unwrap(),
+            "mail@example.com"
+        );
+    }
+
+    #[test]
+    fn disk_layout() {
+        let status = "  pool: rpool
+ state: ONLINE
+config:
+
+\tNAME                        STATE     READ WRITE CKSUM
+\trpool                       ONLINE       0     0     0
+\t  mirror-0                  ONLINE       0     0     0
+\t    /dev/sda3               ONLINE       0     0     0
+\t    /dev/sdb3               ONLINE       0     0     0
+\t  mirror-1                  ONLINE       0     0     0
+\t    /dev/sdc3               ONLINE       0     0     0
+\t    /dev/sdd3               ONLINE       0     0     0
+\tlogs
+\t  /dev/nvme0n1p1            ONLINE       0     0     0
+
+errors: No known data errors
+";
+        let layout = parse_zpool_status(status).unwrap();
+        assert_eq!(layout.raid, "raid10");
+        assert_eq!(
+            layout.devices,
+            ["/dev/sda3", "/dev/sdb3", "/dev/sdc3", "/dev/sdd3"]
+        );
+        let single = "\tNAME        STATE\n\trpool       ONLINE\n\t  /dev/sda3 ONLINE\n";
+        assert_eq!(
+            parse_zpool_status(single).unwrap(),
+            PoolLayout {
+                raid: "raid0",
+                devices: vec!["/dev/sda3".into()],
+            }
+        );
+        assert!(parse_zpool_status("no pools available\n").is_err());
+        let raidz = status.replace("mirror-0", "raidz2-0").replace(
+            "\t  mirror-1                  ONLINE       0     0     0\n",
+            "",
+        );
+        assert_eq!(parse_zpool_status(&raidz).unwrap().raid, "raidz-2");
+
+        assert_eq!(
+            zfs_arc_max("options zfs zfs_arc_max=8589934592\n"),
+            Some(8192)
+        );
+        assert_eq!(
+            btrfs_raid("Data, RAID1: total=1.00GiB, used=0.00B\n").unwrap(),
+            "raid1"
+        );
+        assert_eq!(
+            btrfs_devices("Label: none\n\tTotal devices 2\n\tdevid    1 size 10.00GiB used 2.00GiB path /dev/sda3\n\tdevid    2 size 10.00GiB used 2.00GiB path /dev/sdb3\n"),
+            ["/dev/sda3", "/dev/sdb3"]
+        );
+    }
+}
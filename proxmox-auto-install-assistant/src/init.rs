@@ -0,0 +1,476 @@
+//! Interactive creation of answer files.
+
+use anyhow::{bail, format_err, Result};
+use clap::{Args, ValueEnum};
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use proxmox_auto_installer::answer::{Answer, KeyboardLayout};
+use proxmox_installer_common::{
+    disk_checks::{check_btrfs_raid_config, check_for_duplicate_disks, check_zfs_raid_config},
+    options::{Disk, FsType, FS_TYPES},
+    setup::{read_json, LocaleInfo, ProxmoxProduct},
+    utils::{CidrAddress, Fqdn},
+    RUNTIME_DIR,
+};
+
+use crate::crypt::{hash_password, HashMethod};
+use crate::fromconfig::{btrfs_raid_name, zfs_raid_name};
+use crate::prompt::Prompt;
+
+/// Create an answer file by answering a few questions
+///
+/// Asks for the most important settings of the installation and writes an answer file with
+/// comments, which can then be adapted further or used as is. The choices of countries, time
+/// zones and keyboard layouts are taken from the locale information of the installer, if
+/// available.
+#[derive(Args, Debug)]
+pub struct CommandInit {
+    /// Path to write the answer file to.
+    #[arg(long, short, default_value = "answer.toml")]
+    output: PathBuf,
+
+    /// Path to the 'locales.json' of the installer, defaults to the one of the installation
+    /// environment. Without it, countries and time zones are not checked.
+    #[arg(long)]
+    locales: Option<PathBuf>,
+}
+
+pub fn init(args: &CommandInit) -> Result<()> {
+    let locales = match &args.locales {
+        Some(path) => Some(
+            read_json::<LocaleInfo, _>(path)
+                .map_err(|err| format_err!("Reading locale information {path:?} failed: {err}"))?,
+        ),
+        None => read_json(Path::new(RUNTIME_DIR).join("locales.json")).ok(),
+    };
+    if locales.is_none() {
+        eprintln!("No locale information found, countries and time zones are not checked.");
+    }
+
+    let mut prompt = Prompt::stdin(io::stdout());
+    if args.output.exists()
+        && !prompt.confirm(&format!("{:?} exists, overwrite it?", args.output), false)?
+    {
+        bail!("Aborted, answer file {:?} exists.", args.output);
+    }
+
+    let answer = run_wizard(&mut prompt, locales.as_ref())?.to_toml()?;
+    fs::write(&args.output, answer)
+        .map_err(|err| format_err!("Writing answer file {:?} failed: {err}", args.output))?;
+    println!("\nAnswer file written to {:?}.", args.output);
+    Ok(())
+}
+
+/// The settings collected by the wizard.
+struct Settings {
+    product: ProxmoxProduct,
+    country: String,
+    fqdn: Fqdn,
+    keyboard: KeyboardLayout,
+    mailto: String,
+    timezone: String,
+    root_password_hashed: String,
+    root_ssh_keys: Vec<String>,
+    network: Option<StaticNetwork>,
+    filesystem: FsType,
+    disks: Vec<String>,
+}
+
+struct StaticNetwork {
+    cidr: CidrAddress,
+    gateway: IpAddr,
+    dns: IpAddr,
+    filter: (&'static str, String),
+}
+
+fn run_wizard<R: BufRead, W: Write>(
+    prompt: &mut Prompt<R, W>,
+    locales: Option<&LocaleInfo>,
+) -> Result<Settings> {
+    let products = [
+        ProxmoxProduct::PVE,
+        ProxmoxProduct::PBS,
+        ProxmoxProduct::PMG,
+    ]
+    .map(|product| (product, product_name(product).to_string()));
+    let product = prompt.choose("Product to install", &products, 0)?;
+
+    let default_fqdn = format!("{}.example.invalid", product.default_hostname());
+    let fqdn = prompt.ask("Hostname (FQDN)", Some(&default_fqdn), |value| {
+        Fqdn::from(value).map_err(|err| err.to_string())
+    })?;
+    let mailto = prompt.ask("Email address for notifications", None, |value| {
+        let (user, domain) = value.split_once('@').unwrap_or_default();
+        if user.is_empty() || !domain.contains('.') {
+            return Err("not an email address".to_string());
+        }
+        Ok(value.to_string())
+    })?;
+
+    prompt.separator()?;
+    let country = prompt.ask("Country code, for example 'at'", None, |value| {
+        let value = value.to_lowercase();
+        match locales {
+            Some(locales) if !locales.countries.contains_key(&value) => {
+                Err(format!("unknown country '{value}'"))
+            }
+            _ => Ok(value),
+        }
+    })?;
+    let timezone = choose_timezone(prompt, locales, &country)?;
+    let keyboard = choose_keyboard(prompt, locales, &country)?;
+
+    prompt.separator()?;
+    let password = prompt.new_password("Root password", true)?;
+    let root_password_hashed = hash_password(&password, HashMethod::Yescrypt)?;
+    let root_ssh_keys = prompt.ask(
+        "Public SSH key file for root, optional",
+        Some("none"),
+        |value| {
+            if value == "none" {
+                return Ok(Vec::new());
+            }
+            let keys = fs::read_to_string(value).map_err(|err| err.to_string())?;
+            let keys: Vec<String> = keys
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect();
+            match keys.is_empty() {
+                true => Err("no keys found".to_string()),
+                false => Ok(keys),
+            }
+        },
+    )?;
+
+    let network = prompt.choose(
+        "Network configuration",
+        &[
+            (
+                false,
+                "DHCP, use the configuration received while installing".into(),
+            ),
+            (true, "Static configuration".into()),
+        ],
+        0,
+    )?;
+    let network = match network {
+        true => Some(ask_static_network(prompt)?),
+        false => None,
+    };
+
+    let filesystems: Vec<(FsType, String)> =
+        FS_TYPES.iter().map(|fs| (*fs, fs.to_string())).collect();
+    let filesystem = prompt.choose("Filesystem of the root disk", &filesystems, 0)?;
+    let disks = prompt.ask(
+        "Disks to install to, for example 'sda sdb'",
+        None,
+        |value| {
+            let disks: Vec<String> = value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|disk| !disk.is_empty())
+                .map(|disk| disk.trim_start_matches("/dev/").to_string())
+                .collect();
+            check_disks(filesystem, &disks)?;
+            Ok(disks)
+        },
+    )?;
+
+    Ok(Settings {
+        product,
+        country,
+        fqdn,
+        keyboard,
+        mailto,
+        timezone,
+        root_password_hashed,
+        root_ssh_keys,
+        network,
+        filesystem,
+        disks,
+    })
+}
+
+fn product_name(product: ProxmoxProduct) -> &'static str {
+    match product {
+        ProxmoxProduct::PVE => "Proxmox VE",
+        ProxmoxProduct::PBS => "Proxmox Backup Server",
+        ProxmoxProduct::PMG => "Proxmox Mail Gateway",
+    }
+}
+
+fn choose_timezone<R: BufRead, W: Write>(
+    prompt: &mut Prompt<R, W>,
+    locales: Option<&LocaleInfo>,
+    country: &str,
+) -> Result<String> {
+    let Some(locales) = locales else {
+        return prompt.text("Time zone", Some("UTC"));
+    };
+
+    let mut zones = locales.cczones.get(country).cloned().unwrap_or_default();
+    zones.sort();
+    zones.push("UTC".to_string());
+    let default = locales
+        .countries
+        .get(country)
+        .and_then(|info| zones.iter().position(|zone| *zone == info.zone))
+        .unwrap_or(0);
+    let zones: Vec<(String, String)> = zones.into_iter().map(|z| (z.clone(), z)).collect();
+    prompt.choose("Time zone", &zones, default)
+}
+
+fn choose_keyboard<R: BufRead, W: Write>(
+    prompt: &mut Prompt<R, W>,
+    locales: Option<&LocaleInfo>,
+    country: &str,
+) -> Result<KeyboardLayout> {
+    let layouts: Vec<(KeyboardLayout, String)> = KeyboardLayout::value_variants()
+        .iter()
+        .map(|layout| {
+            let id = layout.to_string();
+            let label = match locales.and_then(|locales| locales.kmap.get(&id)) {
+                Some(kmap) => format!("{} ({id})", kmap.name),
+                None => id,
+            };
+            (layout.clone(), label)
+        })
+        .collect();
+
+    let country_kmap = locales
+        .and_then(|locales| locales.countries.get(country))
+        .map(|info| info.kmap.as_str())
+        .filter(|kmap| !kmap.is_empty())
+        .unwrap_or("en-us");
+    let default = layouts
+        .iter()
+        .position(|(layout, _)| layout.to_string() == country_kmap)
+        .unwrap_or(0);
+    prompt.choose("Keyboard layout", &layouts, default)
+}
+
+fn ask_static_network<R: BufRead, W: Write>(prompt: &mut Prompt<R, W>) -> Result<StaticNetwork> {
+    let cidr = prompt.ask(
+        "IP address with prefix length, for example '192.0.2.10/24'",
+        None,
+        |value| {
+            value
+                .parse::<CidrAddress>()
+                .map_err(|_| "not an address with prefix length".to_string())
+        },
+    )?;
+    let gateway = prompt.ask("Gateway", None, |value| {
+        value.parse::<IpAddr>().map_err(|err| err.to_string())
+    })?;
+    let gateway_str = gateway.to_string();
+    let dns = prompt.ask("DNS server", Some(&gateway_str), |value| {
+        value.parse::<IpAddr>().map_err(|err| err.to_string())
+    })?;
+    let filter = prompt.ask(
+        "MAC address or name of the management interface",
+        None,
+        |value| Ok(interface_filter(value)),
+    )?;
+    Ok(StaticNetwork {
+        cidr,
+        gateway,
+        dns,
+        filter,
+    })
+}
+
+/// Selects an interface by its MAC address, which stays the same on the installed system, or
+/// else by its name.
+fn interface_filter(value: &str) -> (&'static str, String) {
+    let mac = value.replace([':', '-'], "").to_lowercase();
+    if mac.len() == 12 && mac.chars().all(|c| c.is_ascii_hexdigit()) {
+        ("ID_NET_NAME_MAC", format!("enx{mac}"))
+    } else {
+        ("ID_NET_NAME", value.to_string())
+    }
+}
+
+fn check_disks(filesystem: FsType, disks: &[String]) -> Result<(), String> {
+    let disks: Vec<Disk> = disks
+        .iter()
+        .map(|name| Disk {
+            index: name.clone(),
+            path: format!("/dev/{name}"),
+            model: None,
+            size: 0.,
+            block_size: None,
+        })
+        .collect();
+    check_for_duplicate_disks(&disks)
+        .map_err(|disk| format!("disk '{}' is listed more than once", disk.index))?;
+    match filesystem {
+        FsType::Ext4 | FsType::Xfs if disks.len() != 1 => {
+            Err("ext4 and XFS are installed to exactly one disk".to_string())
+        }
+        FsType::Zfs(level) => check_zfs_raid_config(level, &disks),
+        FsType::Btrfs(level) => check_btrfs_raid_config(level, &disks),
+        _ => Ok(()),
+    }
+}
+
+/// Quotes a string as TOML value.
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+impl Settings {
+    /// Returns the commented answer file and checks that it can be parsed.
+    fn to_toml(&self) -> Result<String> {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "# Answer file for the automated installation of {}, created by\n\
+             # 'proxmox-auto-install-assistant init'. Check it after editing with\n\
+             # 'proxmox-auto-install-assistant validate-answer'.\n",
+            product_name(self.product)
+        )?;
+
+        writeln!(out, "[global]")?;
+        writeln!(out, "country = {}", quote(&self.country))?;
+        writeln!(out, "keyboard = {}", quote(&self.keyboard.to_string()))?;
+        writeln!(out, "timezone = {}", quote(&self.timezone))?;
+        writeln!(out, "fqdn = {}", quote(&self.fqdn.to_string()))?;
+        writeln!(out, "# Address for notifications of the installed system.")?;
+        writeln!(out, "mailto = {}", quote(&self.mailto))?;
+        writeln!(
+            out,
+            "# Hash of the root password, in the format of /etc/shadow. Create it with\n\
+             # 'proxmox-auto-install-assistant hash-password', or set a plain\n\
+             # 'root_password' instead."
+        )?;
+        writeln!(
+            out,
+            "root_password_hashed = {}",
+            quote(&self.root_password_hashed)
+        )?;
+        if !self.root_ssh_keys.is_empty() {
+            writeln!(out, "# Public SSH keys allowed to log in as root.")?;
+            writeln!(out, "root_ssh_keys = [")?;
+            for key in &self.root_ssh_keys {
+                writeln!(out, "    {},", quote(key))?;
+            }
+            writeln!(out, "]")?;
+        }
+
+        writeln!(out, "\n[network]")?;
+        match &self.network {
+            None => {
+                writeln!(
+                    out,
+                    "# Use the configuration received via DHCP while installing."
+                )?;
+                writeln!(out, "source = \"from-dhcp\"")?;
+            }
+            Some(network) => {
+                writeln!(out, "source = \"from-answer\"")?;
+                writeln!(out, "cidr = {}", quote(&network.cidr.to_string()))?;
+                writeln!(out, "gateway = {}", quote(&network.gateway.to_string()))?;
+                writeln!(out, "dns = {}", quote(&network.dns.to_string()))?;
+                writeln!(
+                    out,
+                    "# The management interface, see 'proxmox-auto-install-assistant device-info'\n\
+                     # for the properties to match against."
+                )?;
+                let (key, value) = &network.filter;
+                writeln!(out, "filter.{key} = {}", quote(value))?;
+            }
+        }
+
+        writeln!(out, "\n[disk-setup]")?;
+        match self.filesystem {
+            FsType::Ext4 => writeln!(out, "filesystem = \"ext4\"")?,
+            FsType::Xfs => writeln!(out, "filesystem = \"xfs\"")?,
+            FsType::Zfs(level) => {
+                writeln!(out, "filesystem = \"zfs\"")?;
+                writeln!(out, "zfs.raid = {}", quote(zfs_raid_name(level)))?;
+            }
+            FsType::Btrfs(level) => {
+                writeln!(out, "filesystem = \"btrfs\"")?;
+                writeln!(out, "btrfs.raid = {}", quote(btrfs_raid_name(level)))?;
+            }
+        }
+        writeln!(
+            out,
+            "# Disks to install to, by their names. Alternatively, select them with a 'filter'."
+        )?;
+        let disks: Vec<String> = self.disks.iter().map(|disk| quote(disk)).collect();
+        writeln!(out, "disk_list = [{}]", disks.join(", "))?;
+
+        if let Err(err) = toml::from_str::<Answer>(&out) {
+            bail!("The created answer file is invalid: {err}");
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn run(input: &str) -> Result<String> {
+        let locales: LocaleInfo =
+            read_json("../proxmox-auto-installer/tests/resources/locales.json").unwrap();
+        let mut prompt = Prompt::new(Cursor::new(input.as_bytes()), Vec::new(), false);
+        run_wizard(&mut prompt, Some(&locales))?.to_toml()
+    }
+
+    #[test]
+    fn wizard() {
+        let answer =
+            run("\n\nroot@example.com\nAT\n\n\n123456\n123456\n\n\n6\nsda,sdb\nsda,sdb,sdc\n")
+                .unwrap();
+        let parsed: toml::Table = toml::from_str(&answer).unwrap();
+        assert_eq!(
+            parsed["global"]["fqdn"].as_str(),
+            Some("pve.example.invalid")
+        );
+        assert_eq!(parsed["global"]["country"].as_str(), Some("at"));
+        assert_eq!(parsed["global"]["timezone"].as_str(), Some("Europe/Vienna"));
+        assert_eq!(parsed["global"]["keyboard"].as_str(), Some("de"));
+        assert!(parsed["global"]["root_password_hashed"]
+            .as_str()
+            .unwrap()
+            .starts_with("$y$"));
+        assert_eq!(parsed["network"]["source"].as_str(), Some("from-dhcp"));
+        assert_eq!(
+            parsed["disk-setup"]["zfs"]["raid"].as_str(),
+            Some("raidz-1")
+        );
+
+        // invalid values are asked for again, ext4 needs exactly one disk
+        let answer = run(
+            "3\nmail\nmail.example.com\nroot@example.com\nxx\nch\n\n\n1234\n123456\n654321\n\
+             123456\n123456\n\n2\n192.0.2.10\n192.0.2.10/24\n192.0.2.1\n\n\
+             AA:BB:CC:DD:EE:FF\n1\nsda sdb\n/dev/sda\n",
+        )
+        .unwrap();
+        let parsed: toml::Table = toml::from_str(&answer).unwrap();
+        assert_eq!(parsed["global"]["fqdn"].as_str(), Some("mail.example.com"));
+        assert_eq!(parsed["global"]["timezone"].as_str(), Some("Europe/Zurich"));
+        assert_eq!(parsed["global"]["keyboard"].as_str(), Some("de-ch"));
+        assert_eq!(parsed["network"]["cidr"].as_str(), Some("192.0.2.10/24"));
+        assert_eq!(parsed["network"]["dns"].as_str(), Some("192.0.2.1"));
+        assert_eq!(
+            parsed["network"]["filter"]["ID_NET_NAME_MAC"].as_str(),
+            Some("enxaabbccddeeff")
+        );
+        assert_eq!(parsed["disk-setup"]["disk_list"][0].as_str(), Some("sda"));
+        assert!(answer
+            .starts_with("# Answer file for the automated installation of Proxmox Mail Gateway"));
+
+        assert!(run("\n\nroot@example.com\n").is_err());
+    }
+}
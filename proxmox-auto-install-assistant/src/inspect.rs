@@ -0,0 +1,150 @@
+//! Showing the answer file and settings of prepared installation ISOs.
+
+use anyhow::{bail, format_err, Result};
+use clap::Args;
+use std::{collections::BTreeMap, path::PathBuf};
+
+use proxmox_auto_installer::{
+    answer::Answer,
+    template,
+    utils::{AutoInstSettings, FetchAnswerFrom, ISO_EXTRA_FILES_DIR},
+};
+
+use crate::{iso::IsoImage, PROXMOX_ISO_FLAG};
+
+/// Information about the ISO release, in the format of a shell variable assignment per line.
+const CD_INFO_FILE: &str = "/.cd-info";
+const AUTO_INSTALL_MODE_FILE: &str = "/auto-installer-mode.toml";
+const ANSWER_FILE: &str = "/answer.toml";
+
+/// Show how a prepared ISO is configured for the automated installation.
+///
+/// The answer sources and the embedded answer file are read from the ISO, without the need to
+/// mount it. The embedded answer file is validated with the current answer file format. Answer
+/// file templates are only checked for syntax errors.
+#[derive(Args, Debug)]
+pub struct CommandInspectIso {
+    /// Path to the ISO
+    input: PathBuf,
+}
+
+pub fn inspect_iso(args: &CommandInspectIso) -> Result<()> {
+    let image = IsoImage::open(&args.input)?;
+
+    match image.read_file(CD_INFO_FILE)? {
+        Some(cd_info) => {
+            let cd_info = parse_cd_info(&String::from_utf8_lossy(&cd_info));
+            let value = |key| cd_info.get(key).map_or("", String::as_str);
+            let name = cd_info
+                .get("productlong")
+                .or(cd_info.get("product"))
+                .map_or("unknown product", String::as_str);
+            println!("ISO: {name} {}-{}", value("release"), value("isorelease"));
+        }
+        None => println!("ISO: no release information found"),
+    }
+
+    if !image.contains(PROXMOX_ISO_FLAG)? {
+        println!("The ISO does not support automated installations.");
+        return Ok(());
+    }
+    let Some(settings) = image.read_file(AUTO_INSTALL_MODE_FILE)? else {
+        println!("The ISO is not prepared for automated installations.");
+        return Ok(());
+    };
+    let settings: AutoInstSettings = toml::from_str(&String::from_utf8_lossy(&settings))
+        .map_err(|err| format_err!("Error parsing '{AUTO_INSTALL_MODE_FILE}': {err}"))?;
+
+    println!("Answer sources:");
+    for (i, source) in settings.sources.iter().enumerate() {
+        println!("  {}. {}", i + 1, mode_name(&source.mode));
+        if source.mode != FetchAnswerFrom::Http {
+            continue;
+        }
+        let http = &source.http;
+        println!(
+            "     URL: {}",
+            http.url.as_deref().unwrap_or("from DHCP or DNS")
+        );
+        println!(
+            "     Certificate fingerprint: {}",
+            http.cert_fingerprint
+                .as_deref()
+                .unwrap_or("from DHCP or DNS")
+        );
+        if http.client_cert.is_some() {
+            println!("     Client certificate: embedded");
+        }
+        if !http.headers.is_empty() {
+            // only the names, the values are usually secrets like tokens
+            let names: Vec<&str> = http.headers.keys().map(String::as_str).collect();
+            println!("     HTTP headers: {}", names.join(", "));
+        }
+        if let Some(url) = &http.error_report_url {
+            println!("     Error report URL: {url}");
+        }
+    }
+
+    println!(
+        "Extra files: {}",
+        match image.contains(ISO_EXTRA_FILES_DIR)? {
+            true => "present",
+            false => "none",
+        }
+    );
+
+    let Some(answer) = image.read_file(ANSWER_FILE)? else {
+        println!("Embedded answer file: none");
+        return Ok(());
+    };
+    let answer = String::from_utf8(answer)
+        .map_err(|_| format_err!("The embedded answer file is not valid UTF-8."))?;
+    if template::is_template(&answer) {
+        template::check(&answer)
+            .map_err(|err| format_err!("Error in the embedded answer file template: {err}"))?;
+        println!("Embedded answer file: template, rendered during the installation");
+    } else {
+        if let Err(err) = toml::from_str::<Answer>(&answer) {
+            bail!("The embedded answer file is invalid: {err}");
+        }
+        println!("Embedded answer file: valid");
+    }
+    Ok(())
+}
+
+fn mode_name(mode: &FetchAnswerFrom) -> &'static str {
+    match mode {
+        FetchAnswerFrom::Iso => "iso",
+        FetchAnswerFrom::Http => "http",
+        FetchAnswerFrom::Partition => "partition",
+    }
+}
+
+/// Parses the lines of the CD info file, like `RELEASE='8.2'`, into lower case keys and values.
+fn parse_cd_info(content: &str) -> BTreeMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim().trim_matches(|c| c == '\'' || c == '"');
+            (key.trim().to_lowercase(), value.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cd_info() {
+        let info = parse_cd_info(
+            "PRODUCT='pve'\nPRODUCTLONG='Proxmox VE'\nRELEASE='8.2'\nISORELEASE='1'\n\
+             ISONAME=\"proxmox-ve\"\n",
+        );
+        assert_eq!(info["productlong"], "Proxmox VE");
+        assert_eq!(info["release"], "8.2");
+        assert_eq!(info["isorelease"], "1");
+        assert_eq!(info["isoname"], "proxmox-ve");
+    }
+}
@@ -0,0 +1,1597 @@
+//! Reading and extending ISO 9660 images, like the installation ISOs.
+//!
+//! Files are added the same way xorriso grows an image: the file data and a complete new directory
+//! tree, including the path tables, are appended to the image, and the volume descriptors are
+//! updated to point to the new tree. All existing data, like the boot images and the boot catalog,
+//! stays where it is. The volume dates, from which the UUID of the ISO is derived, are kept as
+//! well.
+//!
+//! Both the primary tree, with its Rock Ridge names, and the Joliet tree are updated. The backup
+//! GPT of hybrid images is moved to the new end of the image, and the MBR and GPT partitions
+//! covering the ISO 9660 data are extended to the appended data.
+//!
+//! The source image is not modified, the extended images are written as copies of it. The source
+//! is only read once, also if several copies with different files are written.
+//!
+//! The original content of all overwritten blocks and the original size are stored in the image
+//! as well. Copies of such an image are based on the original image, so that the files of earlier
+//! runs do not pile up.
+
+use anyhow::{bail, format_err, Result};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+const SECTOR_SIZE: u64 = 2048;
+/// Size of the chunks the source image is copied in.
+const COPY_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+const FIRST_DESCRIPTOR_SECTOR: u64 = 16;
+const ROOT_RECORD: std::ops::Range<usize> = 156..190;
+const JOLIET_ESCAPE_SEQUENCES: [&[u8]; 3] = [b"%/@", b"%/C", b"%/E"];
+
+const RECORD_FLAG_DIRECTORY: u8 = 0x02;
+const RECORD_FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// Limits to not run in circles or out of memory on broken images.
+const MAX_DEPTH: usize = 64;
+const MAX_DIRECTORY_SIZE: u32 = 16 * 1024 * 1024;
+const MAX_CONTINUATION_AREAS: usize = 16;
+
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+const GPT_BLOCK_SIZE: u64 = 512;
+
+/// The original state of an image which files were added to.
+const ORIGINAL_STATE_FILE: &str = "/.auto-installer-original";
+const ORIGINAL_STATE_MAGIC: &[u8] = b"PMXORIG1";
+
+/// A file to add to an image.
+#[derive(Clone)]
+pub struct IsoFile {
+    /// Absolute path within the image
+    pub path: String,
+    pub source: PathBuf,
+}
+
+/// A copy of an image to write, with files added to it.
+pub struct IsoOutput {
+    pub path: PathBuf,
+    pub files: Vec<IsoFile>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TreeKind {
+    Primary,
+    Joliet,
+}
+
+struct VolumeDescriptor {
+    sector: u64,
+    kind: TreeKind,
+    data: Vec<u8>,
+}
+
+impl VolumeDescriptor {
+    fn root_record(&self) -> &[u8] {
+        &self.data[ROOT_RECORD]
+    }
+}
+
+/// An opened ISO 9660 image.
+pub struct IsoImage {
+    file: File,
+    /// Size of the image, or of the original image if files were added to it
+    size: u64,
+    /// Blocks of the original image, which replace the current content on reads
+    overlay: Vec<(u64, Vec<u8>)>,
+    /// The primary volume descriptor, followed by the Joliet one, if any
+    descriptors: Vec<VolumeDescriptor>,
+    /// Bytes to skip at the start of each System Use area, from the Rock Ridge 'SP' entry
+    susp_skip: Option<usize>,
+}
+
+/// A directory of one of the trees, with all its records.
+struct Directory {
+    /// The '.' and '..' records
+    current: Record,
+    parent: Record,
+    entries: Vec<Entry>,
+    /// Location and size of the directory in the new tree
+    location: u32,
+    size: u32,
+}
+
+struct Entry {
+    /// The name as shown by Linux, from Rock Ridge or Joliet if available
+    name: String,
+    /// Files larger than 4 GiB are made up of multiple records, one for each extent
+    records: Vec<Record>,
+    directory: Option<Directory>,
+}
+
+/// A directory record, with the continuation areas of its Rock Ridge entries.
+#[derive(Clone)]
+struct Record {
+    data: Vec<u8>,
+    /// The chain of continuation areas, each one referenced by a 'CE' entry in the previous one
+    continuation: Vec<Vec<u8>>,
+    /// Offset of the 'CE' entry in the record
+    continuation_entry: usize,
+}
+
+impl Record {
+    fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            continuation: Vec::new(),
+            continuation_entry: 0,
+        }
+    }
+
+    fn ident(&self) -> &[u8] {
+        record_ident(&self.data)
+    }
+
+    fn is_directory(&self) -> bool {
+        self.data[25] & RECORD_FLAG_DIRECTORY != 0
+    }
+
+    /// Iterates over the SUSP entries of the record, including the ones in continuation areas.
+    fn susp_entries(&self, skip: usize) -> impl Iterator<Item = &[u8]> {
+        std::iter::once(system_use(&self.data).get(skip..).unwrap_or_default())
+            .chain(self.continuation.iter().map(Vec::as_slice))
+            .flat_map(susp_entries)
+    }
+}
+
+impl IsoImage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).map_err(|err| format_err!("Opening ISO {path:?} failed: {err}"))?;
+        Self::from_file(file).map_err(|err| format_err!("Reading ISO {path:?} failed: {err}"))
+    }
+
+    fn from_file(file: File) -> Result<Self> {
+        let size = file.metadata()?.len();
+        Self::load(file, size, Vec::new())
+    }
+
+    /// Reads the image as if it had the given `size` and the `overlay` blocks.
+    fn load(file: File, size: u64, overlay: Vec<(u64, Vec<u8>)>) -> Result<Self> {
+        let mut image = Self {
+            file,
+            size,
+            overlay,
+            descriptors: Vec::new(),
+            susp_skip: None,
+        };
+
+        for sector in FIRST_DESCRIPTOR_SECTOR.. {
+            let data = image.read_at(sector * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+            if &data[1..6] != b"CD001" {
+                bail!("not an ISO 9660 image");
+            }
+            let kind = match data[0] {
+                1 if image.descriptors.is_empty() => TreeKind::Primary,
+                2 if JOLIET_ESCAPE_SEQUENCES.contains(&&data[88..91]) => TreeKind::Joliet,
+                255 => break,
+                _ => continue,
+            };
+            image
+                .descriptors
+                .push(VolumeDescriptor { sector, kind, data });
+        }
+        if image.descriptors.first().map(|descriptor| descriptor.kind) != Some(TreeKind::Primary) {
+            bail!("no primary volume descriptor found");
+        }
+        image.descriptors.truncate(2);
+
+        // the Rock Ridge extensions are announced by an 'SP' entry in the '.' record of the root
+        let root = image.read_directory(image.descriptors[0].root_record(), TreeKind::Primary)?;
+        let system_use = root.current.data.get(34..).unwrap_or_default();
+        if system_use.len() >= 7 && &system_use[..2] == b"SP" && system_use[4..6] == [0xbe, 0xef] {
+            image.susp_skip = Some(system_use[6] as usize);
+        }
+
+        Ok(image)
+    }
+
+    /// Opens the image as it was before any files were added to it.
+    pub fn open_original(path: &Path) -> Result<Self> {
+        let image = Self::open(path)?;
+        let state = image
+            .read_file(ORIGINAL_STATE_FILE)?
+            .map(|state| OriginalState::parse(&state))
+            .transpose()
+            .map_err(|err| format_err!("Reading ISO {path:?} failed: {err}"))?;
+        match state {
+            // the old trees are still there, only the overwritten blocks need to be replaced
+            Some(state) => {
+                if state.size > image.size {
+                    bail!("original size of the ISO {path:?} is larger than the current one");
+                }
+                Self::load(image.file, state.size, state.blocks)
+                    .map_err(|err| format_err!("Reading original ISO {path:?} failed: {err}"))
+            }
+            None => Ok(image),
+        }
+    }
+
+    /// Reads from the image, with the overlay blocks in place of the current content.
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut data = read_at(&self.file, offset, len)?;
+        for (block_offset, block) in &self.overlay {
+            patch(&mut data, offset, *block_offset, block);
+        }
+        Ok(data)
+    }
+
+    /// Returns whether there is a file or directory at the absolute `path`.
+    pub fn contains(&self, path: &str) -> Result<bool> {
+        Ok(self.find(path)?.is_some())
+    }
+
+    /// Returns the content of the file at the absolute `path`, or `None` if there is no such file.
+    pub fn read_file(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let Some(record) = self.find(path)? else {
+            return Ok(None);
+        };
+        if record[25] & RECORD_FLAG_DIRECTORY != 0 {
+            bail!("'{path}' is a directory");
+        }
+        let (location, size) = record_extent(&record);
+        Ok(Some(
+            self.read_at(location as u64 * SECTOR_SIZE, size as usize)?,
+        ))
+    }
+
+    /// Returns the record of the file or directory at the absolute `path` in the primary tree.
+    fn find(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let mut record = self.descriptors[0].root_record().to_vec();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            if record[25] & RECORD_FLAG_DIRECTORY == 0 {
+                return Ok(None);
+            }
+            let directory = self.read_directory(&record, TreeKind::Primary)?;
+            match directory
+                .entries
+                .into_iter()
+                .find(|entry| entry.name == component)
+            {
+                Some(entry) => record = entry.records.into_iter().next().unwrap().data,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(record))
+    }
+
+    /// Reads the directory of the `record`, without its subdirectories.
+    fn read_directory(&self, record: &[u8], kind: TreeKind) -> Result<Directory> {
+        let (location, size) = record_extent(record);
+        if size > MAX_DIRECTORY_SIZE {
+            bail!("directory at sector {location} is too large");
+        }
+        let data = self.read_at(location as u64 * SECTOR_SIZE, size as usize)?;
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let len = data[pos] as usize;
+            if len == 0 {
+                // records do not cross sector boundaries, the rest of the sector is padding
+                pos = (pos / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+                continue;
+            }
+            if len < 34 || pos + len > data.len() || 33 + data[pos + 32] as usize > len {
+                bail!("invalid directory record in directory at sector {location}");
+            }
+            records.push(self.read_record(data[pos..pos + len].to_vec(), kind)?);
+            pos += len;
+        }
+
+        let mut records = records.into_iter();
+        let (Some(current), Some(parent)) = (records.next(), records.next()) else {
+            bail!("directory at sector {location} is missing the '.' and '..' records");
+        };
+
+        let mut entries: Vec<Entry> = Vec::new();
+        for record in records {
+            if let Some(last) = entries.last_mut() {
+                let previous = last.records.last().unwrap();
+                if previous.data[25] & RECORD_FLAG_MULTI_EXTENT != 0
+                    && previous.ident() == record.ident()
+                {
+                    last.records.push(record);
+                    continue;
+                }
+            }
+            entries.push(Entry {
+                name: self.record_name(&record, kind),
+                records: vec![record],
+                directory: None,
+            });
+        }
+
+        Ok(Directory {
+            current,
+            parent,
+            entries,
+            location: 0,
+            size: 0,
+        })
+    }
+
+    /// Reads the directory of the `record` with all its subdirectories.
+    fn read_tree(&self, record: &[u8], kind: TreeKind, depth: usize) -> Result<Directory> {
+        if depth > MAX_DEPTH {
+            bail!("directory tree is too deep");
+        }
+        let mut directory = self.read_directory(record, kind)?;
+        for entry in &mut directory.entries {
+            if entry.records[0].is_directory() {
+                entry.directory = Some(self.read_tree(&entry.records[0].data, kind, depth + 1)?);
+            }
+        }
+        Ok(directory)
+    }
+
+    /// Reads the record and the continuation areas of its Rock Ridge entries.
+    fn read_record(&self, data: Vec<u8>, kind: TreeKind) -> Result<Record> {
+        let mut record = Record::new(data);
+        let Some(skip) = self.susp_skip.filter(|_| kind == TreeKind::Primary) else {
+            return Ok(record);
+        };
+
+        let start = record.data.len() - system_use(&record.data).len() + skip;
+        let Some(offset) = continuation_offset(record.data.get(start..).unwrap_or_default()) else {
+            return Ok(record);
+        };
+        record.continuation_entry = start + offset;
+
+        let mut entry = &record.data[record.continuation_entry..];
+        loop {
+            let (sector, offset, len) = (
+                le_u32(&entry[4..]),
+                le_u32(&entry[12..]),
+                le_u32(&entry[20..]),
+            );
+            if record.continuation.len() == MAX_CONTINUATION_AREAS
+                || offset as u64 + len as u64 > SECTOR_SIZE
+            {
+                bail!("invalid continuation area at sector {sector}");
+            }
+            let area = self.read_at(sector as u64 * SECTOR_SIZE + offset as u64, len as usize)?;
+            let next = continuation_offset(&area);
+            record.continuation.push(area);
+            match next {
+                Some(offset) => entry = &record.continuation.last().unwrap()[offset..],
+                None => break,
+            }
+        }
+        Ok(record)
+    }
+
+    fn record_name(&self, record: &Record, kind: TreeKind) -> String {
+        let ident = record.ident();
+        let name = match kind {
+            TreeKind::Joliet => {
+                let chars = ident
+                    .chunks_exact(2)
+                    .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]));
+                char::decode_utf16(chars)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+            TreeKind::Primary => {
+                if let Some(name) = self.rock_ridge_name(record) {
+                    return name;
+                }
+                String::from_utf8_lossy(ident).to_lowercase()
+            }
+        };
+        // strip the version and the trailing dot of file names without extension
+        let name = name.split_once(';').map_or(name.as_str(), |(name, _)| name);
+        name.strip_suffix('.').unwrap_or(name).to_string()
+    }
+
+    /// Returns the name from the Rock Ridge 'NM' entries of the `record`, if there are any.
+    fn rock_ridge_name(&self, record: &Record) -> Option<String> {
+        let mut name: Option<Vec<u8>> = None;
+        for entry in record.susp_entries(self.susp_skip?) {
+            // names of '.' and '..' have the CURRENT or PARENT flags set instead
+            if &entry[..2] == b"NM" && entry.len() >= 5 && entry[4] & 0x06 == 0 {
+                name.get_or_insert_with(Vec::new)
+                    .extend_from_slice(&entry[5..]);
+            }
+        }
+        name.map(|name| String::from_utf8_lossy(&name).into_owned())
+    }
+
+    /// Returns the changes for adding the files to the image, replacing existing ones, see the
+    /// module documentation. The `remove` paths are removed beforehand, if they exist.
+    fn changes(&self, files: &[IsoFile], remove: &[&str]) -> Result<Changes> {
+        if self.susp_skip.is_none() {
+            bail!("the ISO has no Rock Ridge extensions, which are required to add files");
+        }
+
+        let paths = files
+            .iter()
+            .map(|file| split_path(&file.path))
+            .collect::<Result<Vec<_>>>()?;
+        let remove = remove
+            .iter()
+            .map(|path| split_path(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut trees = Vec::new();
+        for descriptor in &self.descriptors {
+            let mut tree = self.read_tree(descriptor.root_record(), descriptor.kind, 0)?;
+            let records = NewRecords::new(descriptor.kind, &tree, self.susp_skip);
+            for path in &remove {
+                tree.remove(path, &records);
+            }
+            trees.push((tree, records));
+        }
+
+        let gpt = Gpt::read(self)?;
+        let volume_size = le_u32(&self.descriptors[0].data[80..]) as u64;
+        let mut next = volume_size.max(self.size.div_ceil(SECTOR_SIZE));
+        let mut changes = Changes::default();
+
+        let state = self.original_state(gpt.as_ref())?.to_bytes();
+        let (location, size) = changes.append(&mut next, Data::Bytes(state))?;
+        let state_path = split_path(ORIGINAL_STATE_FILE)?;
+        for (tree, records) in &mut trees {
+            tree.insert(&state_path, records, location, size)?;
+        }
+
+        for (file, path) in files.iter().zip(&paths) {
+            let size = fs::metadata(&file.source)
+                .map_err(|err| format_err!("Opening {:?} failed: {err}", file.source))?
+                .len();
+            let (location, size) = changes
+                .append(&mut next, Data::File(file.source.clone(), size))
+                .map_err(|err| format_err!("Adding {:?} to ISO failed: {err}", file.source))?;
+
+            for (tree, records) in &mut trees {
+                tree.insert(path, records, location, size)?;
+            }
+        }
+
+        let mut descriptors: Vec<Vec<u8>> = self
+            .descriptors
+            .iter()
+            .map(|descriptor| descriptor.data.clone())
+            .collect();
+        for ((tree, _), data) in trees.iter_mut().zip(&mut descriptors) {
+            tree.assign_locations(&mut next)?;
+            tree.write(&mut changes, (tree.location, tree.size));
+
+            let (little, big) = path_tables(tree)?;
+            let path_table_size = little.len() as u32;
+            let (little_location, _) = changes.append(&mut next, Data::Bytes(little))?;
+            let (big_location, _) = changes.append(&mut next, Data::Bytes(big))?;
+
+            set_record_extent(&mut data[ROOT_RECORD], tree.location, tree.size);
+            data[132..140].copy_from_slice(&both_u32(path_table_size));
+            data[140..144].copy_from_slice(&little_location.to_le_bytes());
+            data[144..148].fill(0);
+            data[148..152].copy_from_slice(&big_location.to_be_bytes());
+            data[152..156].fill(0);
+        }
+
+        for (descriptor, mut data) in self.descriptors.iter().zip(descriptors) {
+            data[80..88].copy_from_slice(&both_u32(u32::try_from(next)?));
+            changes.write(descriptor.sector * SECTOR_SIZE, data);
+        }
+        changes.size = next * SECTOR_SIZE;
+
+        // the ISO 9660 data ended with the volume, in blocks of the partition tables
+        let data_end = volume_size * SECTOR_SIZE / GPT_BLOCK_SIZE;
+        if let Some(mut gpt) = gpt {
+            gpt.move_backup(&mut changes, data_end)?;
+        }
+        extend_mbr_partitions(self, &mut changes, data_end)?;
+        Ok(changes)
+    }
+
+    /// Writes copies of the image with their changes applied to the `targets`. The image is read
+    /// once, in chunks which are written to all targets.
+    fn copy_to(&self, targets: &[(File, Changes)]) -> Result<()> {
+        let mut offset = 0;
+        while offset < self.size {
+            let len = COPY_CHUNK_SIZE.min(self.size - offset);
+            let data = self.read_at(offset, len as usize)?;
+            for (file, changes) in targets {
+                let mut data = data.clone();
+                for (block_offset, write) in &changes.writes {
+                    if let Data::Bytes(block) = write {
+                        patch(&mut data, offset, *block_offset, block);
+                    }
+                }
+                file.write_all_at(&data, offset)?;
+            }
+            offset += len;
+        }
+
+        // the appended data, in order, as later writes replace earlier ones
+        for (file, changes) in targets {
+            for (offset, write) in &changes.writes {
+                match write {
+                    Data::Bytes(block) if offset + block.len() as u64 > self.size => {
+                        let skip = self.size.saturating_sub(*offset);
+                        file.write_all_at(&block[skip as usize..], offset + skip)?;
+                    }
+                    Data::Bytes(_) => (),
+                    Data::File(path, size) => {
+                        let source = File::open(path)
+                            .map_err(|err| format_err!("Opening {path:?} failed: {err}"))?;
+                        let mut target = file;
+                        target.seek(SeekFrom::Start(*offset))?;
+                        let written = io::copy(&mut source.take(size + 1), &mut target)
+                            .map_err(|err| format_err!("Writing {path:?} to ISO failed: {err}"))?;
+                        if written != *size {
+                            bail!("{path:?} changed while writing it to the ISO");
+                        }
+                    }
+                }
+            }
+            file.set_len(changes.size)?;
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the blocks that adding files overwrites, with the size of the image.
+    fn original_state(&self, gpt: Option<&Gpt>) -> Result<OriginalState> {
+        let mut blocks: Vec<(u64, Vec<u8>)> = self
+            .descriptors
+            .iter()
+            .map(|descriptor| (descriptor.sector * SECTOR_SIZE, descriptor.data.clone()))
+            .collect();
+        // the MBR and the primary GPT header
+        let mbr_len = 2 * GPT_BLOCK_SIZE as usize;
+        blocks.push((0, self.read_at(0, mbr_len)?));
+        if let Some(gpt) = gpt {
+            // the primary partition entries and the old backup header
+            blocks.push((gpt.entries_lba() * GPT_BLOCK_SIZE, gpt.entries.clone()));
+            let backup = gpt.backup_lba() * GPT_BLOCK_SIZE;
+            let backup_len = GPT_BLOCK_SIZE as usize;
+            blocks.push((backup, self.read_at(backup, backup_len)?));
+        }
+        Ok(OriginalState {
+            size: self.size,
+            blocks,
+        })
+    }
+}
+
+/// Data written to a copy of an image.
+enum Data {
+    Bytes(Vec<u8>),
+    /// A file, with the size it had when the changes were planned
+    File(PathBuf, u64),
+}
+
+/// The changes to an image for adding files, applied while copying it.
+#[derive(Default)]
+struct Changes {
+    /// Offsets and data to write, later writes replace earlier ones where they overlap
+    writes: Vec<(u64, Data)>,
+    /// Size of the new image
+    size: u64,
+}
+
+impl Changes {
+    fn write(&mut self, offset: u64, data: Vec<u8>) {
+        self.writes.push((offset, Data::Bytes(data)));
+    }
+
+    /// Appends the data at sector `next` and returns its location and size.
+    fn append(&mut self, next: &mut u64, data: Data) -> Result<(u32, u32)> {
+        let location = u32::try_from(*next)?;
+        let size = match &data {
+            Data::Bytes(bytes) => bytes.len() as u64,
+            Data::File(_, size) => *size,
+        };
+        let size = u32::try_from(size).map_err(|_| format_err!("file is too large"))?;
+        self.writes.push((*next * SECTOR_SIZE, data));
+        *next += (size as u64).div_ceil(SECTOR_SIZE);
+        Ok((location, size))
+    }
+}
+
+/// The content of blocks before they were overwritten, and the size of the image before files
+/// were added, stored in the image itself.
+struct OriginalState {
+    size: u64,
+    /// Offsets and content of the overwritten blocks
+    blocks: Vec<(u64, Vec<u8>)>,
+}
+
+impl OriginalState {
+    fn parse(mut data: &[u8]) -> Result<Self> {
+        let invalid = || format_err!("invalid '{ORIGINAL_STATE_FILE}' file");
+
+        data = data
+            .strip_prefix(ORIGINAL_STATE_MAGIC)
+            .ok_or_else(invalid)?;
+        let size = le_u64(data.get(..8).ok_or_else(invalid)?);
+        data = &data[8..];
+
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let header = data.get(..12).ok_or_else(invalid)?;
+            let offset = le_u64(header);
+            let len = le_u32(&header[8..]) as usize;
+            let block = data.get(12..12 + len).ok_or_else(invalid)?;
+            if offset.saturating_add(len as u64) > size {
+                return Err(invalid());
+            }
+            blocks.push((offset, block.to_vec()));
+            data = &data[12 + len..];
+        }
+        Ok(Self { size, blocks })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = ORIGINAL_STATE_MAGIC.to_vec();
+        data.extend(self.size.to_le_bytes());
+        for (offset, block) in &self.blocks {
+            data.extend(offset.to_le_bytes());
+            data.extend((block.len() as u32).to_le_bytes());
+            data.extend(block);
+        }
+        data
+    }
+}
+
+/// Splits an absolute path within the image into its components.
+fn split_path(path: &str) -> Result<Vec<String>> {
+    let components: Vec<String> = path
+        .strip_prefix('/')
+        .unwrap_or_default()
+        .split('/')
+        .map(String::from)
+        .collect();
+    if components
+        .iter()
+        .any(|component| component.is_empty() || component == "." || component == "..")
+    {
+        bail!("invalid path '{path}' in ISO");
+    }
+    Ok(components)
+}
+
+/// Writes copies of the ISO at `source` to the `outputs`, each with its files added, replacing
+/// existing ones. The `remove` paths are removed from the copies, if they exist and are not added
+/// again. The source ISO is only read once.
+///
+/// If files were added to the source ISO before, the copies are based on its original state.
+pub fn write_copies(source: &Path, outputs: &[IsoOutput], remove: &[&str]) -> Result<()> {
+    let image = IsoImage::open_original(source)?;
+    let targets = outputs
+        .iter()
+        .map(|output| {
+            let changes = image.changes(&output.files, remove).map_err(|err| {
+                format_err!("Adding files to ISO {:?} failed: {err}", output.path)
+            })?;
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&output.path)
+                .map_err(|err| format_err!("Creating ISO {:?} failed: {err}", output.path))?;
+            Ok((file, changes))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    image
+        .copy_to(&targets)
+        .map_err(|err| format_err!("Writing copies of ISO {source:?} failed: {err}"))
+}
+
+impl Directory {
+    fn insert(
+        &mut self,
+        path: &[String],
+        records: &NewRecords,
+        location: u32,
+        size: u32,
+    ) -> Result<()> {
+        let Some((name, rest)) = path.split_first() else {
+            bail!("empty path");
+        };
+        let name = records.name(name);
+        let position = self.entries.iter().position(|entry| entry.name == name);
+
+        if rest.is_empty() {
+            match position {
+                Some(index) if self.entries[index].directory.is_some() => {
+                    bail!("'{name}' already exists as directory");
+                }
+                Some(index) => {
+                    let entry = &mut self.entries[index];
+                    let ident = entry.records[0].ident().to_vec();
+                    entry.records = vec![records.file(&ident, &name, location, size)?];
+                }
+                None => {
+                    let ident = records.identifier(&name, false, &self.entries);
+                    let record = records.file(&ident, &name, location, size)?;
+                    self.insert_entry(Entry {
+                        name,
+                        records: vec![record],
+                        directory: None,
+                    });
+                }
+            }
+            return Ok(());
+        }
+
+        let index = match position {
+            Some(index) if self.entries[index].directory.is_some() => index,
+            Some(_) => bail!("'{name}' already exists and is not a directory"),
+            None => {
+                let ident = records.identifier(&name, true, &self.entries);
+                let (current, parent) = records.dot_records()?;
+                self.insert_entry(Entry {
+                    records: vec![records.directory(&ident, &name)?],
+                    name,
+                    directory: Some(Directory {
+                        current,
+                        parent,
+                        entries: Vec::new(),
+                        location: 0,
+                        size: 0,
+                    }),
+                })
+            }
+        };
+        let directory = self.entries[index].directory.as_mut().unwrap();
+        directory.insert(rest, records, location, size)
+    }
+
+    /// Removes the file or directory at the path, if it exists.
+    fn remove(&mut self, path: &[String], records: &NewRecords) {
+        let Some((name, rest)) = path.split_first() else {
+            return;
+        };
+        let name = records.name(name);
+        let Some(index) = self.entries.iter().position(|entry| entry.name == name) else {
+            return;
+        };
+        match (rest.is_empty(), &mut self.entries[index].directory) {
+            (true, _) => {
+                self.entries.remove(index);
+            }
+            (false, Some(directory)) => directory.remove(rest, records),
+            (false, None) => {}
+        }
+    }
+
+    /// Inserts the entry sorted by its identifier and returns its index.
+    fn insert_entry(&mut self, entry: Entry) -> usize {
+        let ident = entry.records[0].ident();
+        let index = self
+            .entries
+            .iter()
+            .position(|other| other.records[0].ident() > ident)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(index, entry);
+        index
+    }
+
+    /// Returns the records of the directory, with the extents of the new tree.
+    fn records(&self, parent: (u32, u32)) -> Vec<Record> {
+        let mut current = self.current.clone();
+        set_record_extent(&mut current.data, self.location, self.size);
+        let mut parent_record = self.parent.clone();
+        set_record_extent(&mut parent_record.data, parent.0, parent.1);
+
+        let mut records = vec![current, parent_record];
+        for entry in &self.entries {
+            for record in &entry.records {
+                let mut record = record.clone();
+                if let Some(directory) = &entry.directory {
+                    set_record_extent(&mut record.data, directory.location, directory.size);
+                }
+                records.push(record);
+            }
+        }
+        records
+    }
+
+    /// Assigns the locations of this directory and all below it, starting at sector `next`. The
+    /// continuation areas of the records are placed right after the directory.
+    fn assign_locations(&mut self, next: &mut u64) -> Result<()> {
+        let (data, continuation) = pack_directory(self.records((0, 0)), 0);
+        self.size = data.len() as u32;
+        self.location = u32::try_from(*next)?;
+        *next += (data.len() + continuation.len()) as u64 / SECTOR_SIZE;
+        for entry in &mut self.entries {
+            if let Some(directory) = &mut entry.directory {
+                directory.assign_locations(next)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, changes: &mut Changes, parent: (u32, u32)) {
+        let continuation_location = self.location + self.size / SECTOR_SIZE as u32;
+        let (data, continuation) = pack_directory(self.records(parent), continuation_location);
+        changes.write(self.location as u64 * SECTOR_SIZE, data);
+        changes.write(continuation_location as u64 * SECTOR_SIZE, continuation);
+        for entry in &self.entries {
+            if let Some(directory) = &entry.directory {
+                directory.write(changes, (self.location, self.size));
+            }
+        }
+    }
+}
+
+/// Creates the records of new files and directories for one of the trees.
+struct NewRecords {
+    kind: TreeKind,
+    /// The recording date of the root directory, which keeps the output reproducible
+    date: Vec<u8>,
+    /// Length of the Rock Ridge 'PX' entries, which differs between versions
+    px_len: Option<usize>,
+}
+
+impl NewRecords {
+    fn new(kind: TreeKind, root: &Directory, susp_skip: Option<usize>) -> Self {
+        let px_len = match (kind, susp_skip) {
+            (TreeKind::Primary, Some(_)) => Some(
+                root.current
+                    .susp_entries(0)
+                    .find(|entry| &entry[..2] == b"PX")
+                    .map_or(44, |entry| entry.len().min(44)),
+            ),
+            _ => None,
+        };
+        Self {
+            kind,
+            date: root.current.data[18..25].to_vec(),
+            px_len,
+        }
+    }
+
+    /// Returns the name as it will be shown for this tree.
+    fn name(&self, name: &str) -> String {
+        match self.kind {
+            TreeKind::Primary => name.to_string(),
+            // Joliet names are limited to 64 characters
+            TreeKind::Joliet => char::decode_utf16(name.encode_utf16().take(64))
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+        }
+    }
+
+    /// Returns a unique identifier for a new entry, the file name is taken from Rock Ridge.
+    fn identifier(&self, name: &str, directory: bool, siblings: &[Entry]) -> Vec<u8> {
+        let version = if directory { "" } else { ";1" };
+        if self.kind == TreeKind::Joliet {
+            let name: String = name
+                .chars()
+                .map(|c| if "*/:;?\\".contains(c) { '_' } else { c })
+                .collect();
+            return format!("{name}{version}")
+                .encode_utf16()
+                .flat_map(u16::to_be_bytes)
+                .collect();
+        }
+
+        let (base, extension) = match name.rsplit_once('.') {
+            Some((base, extension)) if !directory && !base.is_empty() => (base, extension),
+            _ => (name, ""),
+        };
+        let d_characters = |value: &str, max_len| -> String {
+            value
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() {
+                    true => c.to_ascii_uppercase(),
+                    false => '_',
+                })
+                .take(max_len)
+                .collect()
+        };
+        let base = d_characters(base, 8);
+        let extension = d_characters(extension, 3);
+
+        for counter in 0.. {
+            let base = match counter {
+                0 => base.clone(),
+                _ => {
+                    let suffix = format!("_{counter}");
+                    format!("{}{suffix}", &base[..base.len().min(8 - suffix.len())])
+                }
+            };
+            let ident = match directory {
+                true => base,
+                false => format!("{base}.{extension}{version}"),
+            };
+            // 'NAME' and 'NAME.;1' would be shown the same way
+            if !siblings
+                .iter()
+                .any(|entry| plain_ident(entry.records[0].ident()) == plain_ident(ident.as_bytes()))
+            {
+                return ident.into_bytes();
+            }
+        }
+        unreachable!()
+    }
+
+    fn system_use(&self, name: Option<&str>, directory: bool, serial: u32) -> Result<Vec<u8>> {
+        let Some(px_len) = self.px_len else {
+            return Ok(Vec::new());
+        };
+        let (mode, links) = match directory {
+            true => (0o40555, 2),
+            false => (0o100444, 1),
+        };
+        let mut data = vec![b'P', b'X', px_len as u8, 1];
+        for value in [mode, links, 0, 0, serial].iter().take((px_len - 4) / 8) {
+            data.extend(both_u32(*value));
+        }
+        if let Some(name) = name {
+            if name.len() > 250 {
+                bail!("name '{name}' is too long");
+            }
+            data.extend([b'N', b'M', 5 + name.len() as u8, 1, 0]);
+            data.extend(name.as_bytes());
+        }
+        Ok(data)
+    }
+
+    fn file(&self, ident: &[u8], name: &str, location: u32, size: u32) -> Result<Record> {
+        let mut record = self.record(ident, 0, &self.system_use(Some(name), false, location)?)?;
+        set_record_extent(&mut record.data, location, size);
+        Ok(record)
+    }
+
+    fn directory(&self, ident: &[u8], name: &str) -> Result<Record> {
+        let system_use = self.system_use(Some(name), true, 0)?;
+        self.record(ident, RECORD_FLAG_DIRECTORY, &system_use)
+    }
+
+    /// Returns the '.' and '..' records of a new directory.
+    fn dot_records(&self) -> Result<(Record, Record)> {
+        let system_use = self.system_use(None, true, 0)?;
+        Ok((
+            self.record(&[0], RECORD_FLAG_DIRECTORY, &system_use)?,
+            self.record(&[1], RECORD_FLAG_DIRECTORY, &system_use)?,
+        ))
+    }
+
+    fn record(&self, ident: &[u8], flags: u8, system_use: &[u8]) -> Result<Record> {
+        let padding = (ident.len() + 1) % 2;
+        let len = 33 + ident.len() + padding + system_use.len();
+        if len > u8::MAX as usize {
+            bail!(
+                "directory record for '{}' is too long",
+                String::from_utf8_lossy(ident)
+            );
+        }
+        let mut record = vec![0; len];
+        record[0] = len as u8;
+        record[18..25].copy_from_slice(&self.date);
+        record[25] = flags;
+        record[28..32].copy_from_slice(&both_u16(1));
+        record[32] = ident.len() as u8;
+        record[33..33 + ident.len()].copy_from_slice(ident);
+        record[33 + ident.len() + padding..].copy_from_slice(system_use);
+        Ok(Record::new(record))
+    }
+}
+
+/// Returns the little and big endian path tables of the tree.
+fn path_tables(root: &Directory) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut little = Vec::new();
+    let mut big = Vec::new();
+
+    // the directories are numbered in breadth-first order, with the root being number 1
+    let mut queue = VecDeque::from([(root, &[0u8][..], 1u16)]);
+    let mut number = 0u16;
+    while let Some((directory, ident, parent)) = queue.pop_front() {
+        number = number
+            .checked_add(1)
+            .ok_or_else(|| format_err!("too many directories"))?;
+
+        for (table, location, parent) in [
+            (
+                &mut little,
+                directory.location.to_le_bytes(),
+                parent.to_le_bytes(),
+            ),
+            (
+                &mut big,
+                directory.location.to_be_bytes(),
+                parent.to_be_bytes(),
+            ),
+        ] {
+            table.extend([ident.len() as u8, 0]);
+            table.extend(location);
+            table.extend(parent);
+            table.extend(ident);
+            if ident.len() % 2 == 1 {
+                table.push(0);
+            }
+        }
+
+        for entry in &directory.entries {
+            if let Some(child) = &entry.directory {
+                queue.push_back((child, entry.records[0].ident(), number));
+            }
+        }
+    }
+    Ok((little, big))
+}
+
+/// Packs the directory records into whole sectors, records must not cross sector boundaries.
+/// Returns the directory and the continuation areas, which are placed starting at the sector
+/// `continuation_location`.
+fn pack_directory(mut records: Vec<Record>, continuation_location: u32) -> (Vec<u8>, Vec<u8>) {
+    let sector_size = SECTOR_SIZE as usize;
+    let align = |data: &mut Vec<u8>, len: usize| {
+        if data.len() % sector_size + len > sector_size {
+            data.resize(data.len().next_multiple_of(sector_size), 0);
+        }
+    };
+
+    let mut continuation = Vec::new();
+    for record in &mut records {
+        let mut entry = record.continuation_entry;
+        for index in 0..record.continuation.len() {
+            align(&mut continuation, record.continuation[index].len());
+            let sector = continuation_location + (continuation.len() / sector_size) as u32;
+            let offset = (continuation.len() % sector_size) as u32;
+            let target = match index {
+                0 => &mut record.data,
+                _ => &mut record.continuation[index - 1],
+            };
+            target[entry + 4..entry + 12].copy_from_slice(&both_u32(sector));
+            target[entry + 12..entry + 20].copy_from_slice(&both_u32(offset));
+
+            let area = &record.continuation[index];
+            entry = continuation_offset(area).unwrap_or_default();
+            continuation.extend(area);
+        }
+    }
+    continuation.resize(continuation.len().next_multiple_of(sector_size), 0);
+
+    let mut data = Vec::new();
+    for record in &records {
+        align(&mut data, record.data.len());
+        data.extend(&record.data);
+    }
+    data.resize(data.len().next_multiple_of(sector_size).max(sector_size), 0);
+    (data, continuation)
+}
+
+/// The GUID partition table of a hybrid image.
+struct Gpt {
+    header: Vec<u8>,
+    entries: Vec<u8>,
+}
+
+impl Gpt {
+    fn read(image: &IsoImage) -> Result<Option<Self>> {
+        let header = image.read_at(GPT_BLOCK_SIZE, GPT_BLOCK_SIZE as usize)?;
+        if &header[..8] != GPT_SIGNATURE {
+            return Ok(None);
+        }
+        let header_size = le_u32(&header[12..]) as usize;
+        let entries_lba = le_u64(&header[72..]);
+        let entries_len = le_u32(&header[80..]) as usize * le_u32(&header[84..]) as usize;
+        if !(92..=GPT_BLOCK_SIZE as usize).contains(&header_size) || entries_len > 1024 * 1024 {
+            bail!("invalid GPT header");
+        }
+        let entries = image.read_at(entries_lba * GPT_BLOCK_SIZE, entries_len)?;
+        Ok(Some(Self { header, entries }))
+    }
+
+    fn backup_lba(&self) -> u64 {
+        le_u64(&self.header[32..])
+    }
+
+    fn entries_lba(&self) -> u64 {
+        le_u64(&self.header[72..])
+    }
+
+    /// Writes the backup GPT to the new end of the image and updates the primary one. Partitions
+    /// which cover the end of the ISO 9660 data at `data_end` are extended to the new end.
+    fn move_backup(&mut self, changes: &mut Changes, data_end: u64) -> Result<()> {
+        // the old backup header is now somewhere in the middle of the image
+        let old_backup_lba = self.backup_lba();
+        changes.write(
+            old_backup_lba * GPT_BLOCK_SIZE,
+            vec![0; GPT_BLOCK_SIZE as usize],
+        );
+
+        let image_end = changes.size;
+        let entries_blocks = (self.entries.len() as u64).div_ceil(GPT_BLOCK_SIZE);
+        let new_end =
+            (image_end + (entries_blocks + 1) * GPT_BLOCK_SIZE).next_multiple_of(SECTOR_SIZE);
+        let backup_lba = new_end / GPT_BLOCK_SIZE - 1;
+        let backup_entries_lba = backup_lba - entries_blocks;
+
+        let last_usable_lba = backup_entries_lba - 1;
+        let entry_size = le_u32(&self.header[84..]) as usize;
+        if entry_size < 128 {
+            bail!("invalid GPT partition entry size {entry_size}");
+        }
+        for entry in self.entries.chunks_exact_mut(entry_size) {
+            let (first_lba, last_lba) = (le_u64(&entry[32..]), le_u64(&entry[40..]));
+            let unused = entry[..16].iter().all(|byte| *byte == 0);
+            if !unused && first_lba < data_end && last_lba + 1 >= data_end {
+                entry[40..48].copy_from_slice(&last_usable_lba.to_le_bytes());
+            }
+        }
+        let entries_crc = crc32fast::hash(&self.entries);
+        self.header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+
+        self.header[32..40].copy_from_slice(&backup_lba.to_le_bytes());
+        self.header[48..56].copy_from_slice(&last_usable_lba.to_le_bytes());
+        let mut backup = self.header.clone();
+        backup[24..32].copy_from_slice(&backup_lba.to_le_bytes());
+        backup[32..40].copy_from_slice(&1u64.to_le_bytes());
+        backup[72..80].copy_from_slice(&backup_entries_lba.to_le_bytes());
+        update_gpt_crc(&mut self.header);
+        update_gpt_crc(&mut backup);
+
+        changes.size = new_end;
+        changes.write(backup_entries_lba * GPT_BLOCK_SIZE, self.entries.clone());
+        changes.write(backup_lba * GPT_BLOCK_SIZE, backup);
+        changes.write(self.entries_lba() * GPT_BLOCK_SIZE, self.entries.clone());
+        changes.write(GPT_BLOCK_SIZE, self.header.clone());
+        Ok(())
+    }
+}
+
+/// Extends the MBR partitions which cover the end of the ISO 9660 data at `data_end` to the end
+/// of the image, like the protective MBR partition of a GPT or the isohybrid partition.
+fn extend_mbr_partitions(image: &IsoImage, changes: &mut Changes, data_end: u64) -> Result<()> {
+    let mut mbr = image.read_at(0, GPT_BLOCK_SIZE as usize)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        return Ok(());
+    }
+    let image_end = changes.size / GPT_BLOCK_SIZE;
+    for offset in (446..510).step_by(16) {
+        let start = le_u32(&mbr[offset + 8..]) as u64;
+        let size = le_u32(&mbr[offset + 12..]) as u64;
+        if size > 0 && start < data_end && start + size >= data_end {
+            let size = u32::try_from(image_end - start).unwrap_or(u32::MAX);
+            mbr[offset + 12..offset + 16].copy_from_slice(&size.to_le_bytes());
+        }
+    }
+    changes.write(0, mbr);
+    Ok(())
+}
+
+fn update_gpt_crc(header: &mut [u8]) {
+    let header_size = le_u32(&header[12..]) as usize;
+    header[16..20].fill(0);
+    let crc = crc32fast::hash(&header[..header_size]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Copies the part of the `block` at `block_offset` which overlaps `data` at `offset` into it.
+fn patch(data: &mut [u8], offset: u64, block_offset: u64, block: &[u8]) {
+    let start = offset.max(block_offset);
+    let end = (offset + data.len() as u64).min(block_offset + block.len() as u64);
+    if start < end {
+        data[(start - offset) as usize..(end - offset) as usize].copy_from_slice(
+            &block[(start - block_offset) as usize..(end - block_offset) as usize],
+        );
+    }
+}
+
+fn read_at(file: &File, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let mut data = vec![0; len];
+    file.read_exact_at(&mut data, offset)
+        .map_err(|err| format_err!("reading {len} bytes at offset {offset} failed: {err}"))?;
+    Ok(data)
+}
+
+fn record_ident(record: &[u8]) -> &[u8] {
+    &record[33..33 + record[32] as usize]
+}
+
+/// Returns the identifier without the version and the trailing dot of names without extension.
+fn plain_ident(ident: &[u8]) -> &[u8] {
+    let ident = ident.split(|c| *c == b';').next().unwrap_or_default();
+    ident.strip_suffix(b".").unwrap_or(ident)
+}
+
+fn record_extent(record: &[u8]) -> (u32, u32) {
+    (le_u32(&record[2..]), le_u32(&record[10..]))
+}
+
+fn set_record_extent(record: &mut [u8], location: u32, size: u32) {
+    record[2..10].copy_from_slice(&both_u32(location));
+    record[10..18].copy_from_slice(&both_u32(size));
+}
+
+/// Returns the System Use area of the record, where the Rock Ridge entries are stored.
+fn system_use(record: &[u8]) -> &[u8] {
+    let ident_len = record[32] as usize;
+    record
+        .get(33 + ident_len + (ident_len + 1) % 2..)
+        .unwrap_or_default()
+}
+
+/// Returns the offset of the 'CE' entry in the System Use area, if there is one.
+fn continuation_offset(area: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    for entry in susp_entries(area) {
+        if &entry[..2] == b"CE" && entry.len() >= 28 {
+            return Some(offset);
+        }
+        offset += entry.len();
+    }
+    None
+}
+
+/// Iterates over the System Use Sharing Protocol entries of a System Use area.
+fn susp_entries(mut area: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        if area.len() < 4 || area[2] < 4 || area[2] as usize > area.len() || &area[..2] == b"ST" {
+            return None;
+        }
+        let (entry, rest) = area.split_at(area[2] as usize);
+        area = rest;
+        Some(entry)
+    })
+}
+
+fn le_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[..4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8]) -> u64 {
+    u64::from_le_bytes(data[..8].try_into().unwrap())
+}
+
+/// Encodes the value in both byte orders, as used by most ISO 9660 fields.
+fn both_u32(value: u32) -> [u8; 8] {
+    let mut data = [0; 8];
+    data[..4].copy_from_slice(&value.to_le_bytes());
+    data[4..].copy_from_slice(&value.to_be_bytes());
+    data
+}
+
+fn both_u16(value: u16) -> [u8; 4] {
+    let mut data = [0; 4];
+    data[..2].copy_from_slice(&value.to_le_bytes());
+    data[2..].copy_from_slice(&value.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proxmox_auto_installer::testing::TempDir;
+
+    const DATE: [u8; 7] = [124, 4, 26, 12, 0, 0, 0];
+
+    /// Adds the files to the image at `path`, by writing a copy and replacing the image with it.
+    fn add_files(path: &Path, files: &[IsoFile], remove: &[&str]) -> Result<()> {
+        let output = IsoOutput {
+            path: path.with_extension("new"),
+            files: files.to_vec(),
+        };
+        write_copies(path, std::slice::from_ref(&output), remove)?;
+        fs::rename(&output.path, path)?;
+        Ok(())
+    }
+
+    fn record(location: u32, size: u32, flags: u8, ident: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let mut record = NewRecords {
+            kind: TreeKind::Primary,
+            date: DATE.to_vec(),
+            px_len: None,
+        }
+        .record(ident, flags, system_use)
+        .unwrap()
+        .data;
+        set_record_extent(&mut record, location, size);
+        record
+    }
+
+    /// Writes a minimal image with Rock Ridge extensions and a single file.
+    fn write_image(path: &Path) {
+        let mut image = vec![0; 22 * SECTOR_SIZE as usize];
+        let sector = |number: usize| number * SECTOR_SIZE as usize;
+
+        let pvd = &mut image[sector(16)..sector(17)];
+        pvd[0] = 1;
+        pvd[1..7].copy_from_slice(b"CD001\x01");
+        pvd[80..88].copy_from_slice(&both_u32(22));
+        pvd[132..140].copy_from_slice(&both_u32(10));
+        pvd[140..144].copy_from_slice(&19u32.to_le_bytes());
+        pvd[148..152].copy_from_slice(&20u32.to_be_bytes());
+        pvd[ROOT_RECORD].copy_from_slice(&record(18, 2048, RECORD_FLAG_DIRECTORY, &[0], &[]));
+        pvd[813..830].copy_from_slice(b"2024042612000000\0");
+        image[sector(17)..sector(17) + 7].copy_from_slice(b"\xffCD001\x01");
+
+        let mut root_system_use = vec![b'S', b'P', 7, 1, 0xbe, 0xef, 0];
+        root_system_use.extend([b'P', b'X', 44, 1]);
+        root_system_use.extend([0o40555, 2, 0, 0, 0].map(both_u32).concat());
+        let mut flag_system_use = vec![b'N', b'M', 27, 1, 0];
+        flag_system_use.extend(b"auto-installer-capable");
+        let records = [
+            record(18, 2048, RECORD_FLAG_DIRECTORY, &[0], &root_system_use),
+            record(18, 2048, RECORD_FLAG_DIRECTORY, &[1], &[]),
+            record(21, 5, 0, b"AUTO_INS.;1", &flag_system_use),
+        ]
+        .concat();
+        image[sector(18)..sector(18) + records.len()].copy_from_slice(&records);
+
+        image[sector(19)..sector(19) + 10].copy_from_slice(&[1, 0, 18, 0, 0, 0, 1, 0, 0, 0]);
+        image[sector(20)..sector(20) + 10].copy_from_slice(&[1, 0, 0, 0, 0, 18, 0, 1, 0, 0]);
+        image[sector(21)..sector(21) + 5].copy_from_slice(b"flag\n");
+        fs::write(path, image).unwrap();
+    }
+
+    /// Turns the image into a hybrid one, as xorriso creates them with '-isohybrid-gpt-basdat':
+    /// an MBR partition spanning the image, a GPT partition covering the ISO 9660 data and an
+    /// EFI system partition within the data, in both tables.
+    fn make_hybrid(path: &Path) {
+        let mut image = fs::read(path).unwrap();
+        let blocks = 128;
+        image.resize(blocks * GPT_BLOCK_SIZE as usize, 0);
+        let block = |number: usize| number * GPT_BLOCK_SIZE as usize;
+
+        let mbr = &mut image[..block(1)];
+        mbr[446..462]
+            .copy_from_slice(&[0x80, 0, 1, 0, 0, 0xfe, 0xff, 0xff, 0, 0, 0, 0, 128, 0, 0, 0]);
+        mbr[462..478].copy_from_slice(&[
+            0, 0xfe, 0xff, 0xff, 0xef, 0xfe, 0xff, 0xff, 80, 0, 0, 0, 4, 0, 0, 0,
+        ]);
+        mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        let mut entries = vec![0; 128 * 128];
+        let basic_data = [
+            0xa2, 0xa0, 0xd0, 0xeb, 0xe5, 0xb9, 0x33, 0x44, 0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26,
+            0x99, 0xc7,
+        ];
+        let efi_system = [
+            0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e,
+            0xc9, 0x3b,
+        ];
+        for (entry, (kind, first, last)) in entries
+            .chunks_exact_mut(128)
+            .zip([(basic_data, 64u64, 87u64), (efi_system, 80, 83)])
+        {
+            entry[..16].copy_from_slice(&kind);
+            entry[16] = 1;
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+        }
+
+        let mut header = vec![0; GPT_BLOCK_SIZE as usize];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x10000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        for (offset, value) in [(24, 1), (32, 127), (40, 34), (48, 94), (72, 2)] {
+            header[offset..offset + 8].copy_from_slice(&(value as u64).to_le_bytes());
+        }
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32fast::hash(&entries).to_le_bytes());
+        let mut backup = header.clone();
+        backup[24..32].copy_from_slice(&127u64.to_le_bytes());
+        backup[32..40].copy_from_slice(&1u64.to_le_bytes());
+        backup[72..80].copy_from_slice(&95u64.to_le_bytes());
+        update_gpt_crc(&mut header);
+        update_gpt_crc(&mut backup);
+
+        image[block(1)..block(2)].copy_from_slice(&header);
+        image[block(2)..block(34)].copy_from_slice(&entries);
+        image[block(95)..block(127)].copy_from_slice(&entries);
+        image[block(127)..].copy_from_slice(&backup);
+        fs::write(path, image).unwrap();
+    }
+
+    /// Returns the first and last LBA of the GPT partitions, after checking the checksums.
+    fn gpt_partitions(image: &[u8], header_lba: u64) -> Vec<(u64, u64)> {
+        let block = |lba: u64| (lba * GPT_BLOCK_SIZE) as usize;
+        let mut header = image[block(header_lba)..block(header_lba + 1)].to_vec();
+        let crc = le_u32(&header[16..]);
+        update_gpt_crc(&mut header);
+        assert_eq!(le_u32(&header[16..]), crc);
+        assert_eq!(le_u64(&header[24..]), header_lba);
+
+        let entries_lba = le_u64(&header[72..]);
+        let entries = &image[block(entries_lba)..block(entries_lba + 32)];
+        assert_eq!(crc32fast::hash(entries), le_u32(&header[88..]));
+        let backup_lba = header_lba.max(le_u64(&header[32..]));
+        assert_eq!(le_u64(&header[48..]), backup_lba - 33);
+        entries
+            .chunks_exact(128)
+            .take_while(|entry| entry[..16].iter().any(|byte| *byte != 0))
+            .map(|entry| (le_u64(&entry[32..]), le_u64(&entry[40..])))
+            .collect()
+    }
+
+    #[test]
+    fn add_files_to_hybrid_image() {
+        let dir = TempDir::new("iso-hybrid");
+        let iso_path = dir.join("test.iso");
+        write_image(&iso_path);
+        make_hybrid(&iso_path);
+        let original = fs::read(&iso_path).unwrap();
+        assert_eq!(gpt_partitions(&original, 1), [(64, 87), (80, 83)]);
+        assert_eq!(gpt_partitions(&original, 127), [(64, 87), (80, 83)]);
+
+        fs::write(dir.join("answer.toml"), "[global]\n").unwrap();
+        let files = [IsoFile {
+            path: "/answer.toml".into(),
+            source: dir.join("answer.toml"),
+        }];
+        add_files(&iso_path, &files, &[]).unwrap();
+
+        let image = fs::read(&iso_path).unwrap();
+        let blocks = image.len() as u64 / GPT_BLOCK_SIZE;
+        assert!(blocks > 128);
+        // the partitions covering the ISO 9660 data reach to the new end, the EFI one is kept
+        let partitions = [(64, blocks - 34), (80, 83)];
+        assert_eq!(gpt_partitions(&image, 1), partitions);
+        assert_eq!(gpt_partitions(&image, blocks - 1), partitions);
+        assert_eq!(le_u32(&image[454..]), 0);
+        assert_eq!(le_u32(&image[458..]) as u64, blocks);
+        assert_eq!(&image[470..478], &[80, 0, 0, 0, 4, 0, 0, 0]);
+        assert_eq!(
+            IsoImage::open(&iso_path)
+                .unwrap()
+                .read_file("/answer.toml")
+                .unwrap()
+                .unwrap(),
+            b"[global]\n"
+        );
+
+        // the original image can be restored from the stored state
+        let image = IsoImage::open_original(&iso_path).unwrap();
+        assert!(image.read_at(0, image.size as usize).unwrap() == original);
+    }
+
+    #[test]
+    fn add_files_to_image() {
+        let dir = TempDir::new("iso-add-files");
+        let iso_path = dir.join("test.iso");
+        write_image(&iso_path);
+        fs::write(dir.join("answer.toml"), "[global]\n").unwrap();
+        fs::write(dir.join("ca.crt"), "certificate\n").unwrap();
+
+        let image = IsoImage::open(&iso_path).unwrap();
+        assert!(image.contains("/auto-installer-capable").unwrap());
+        assert!(!image.contains("/answer.toml").unwrap());
+
+        let files = [
+            ("/answer.toml", "answer.toml"),
+            ("/auto-installer-files/certs/ca.crt", "ca.crt"),
+        ]
+        .map(|(path, source)| IsoFile {
+            path: path.into(),
+            source: dir.join(source),
+        });
+        add_files(&iso_path, &files, &[]).unwrap();
+
+        let image = IsoImage::open(&iso_path).unwrap();
+        assert_eq!(
+            image.read_file("/answer.toml").unwrap().unwrap(),
+            b"[global]\n"
+        );
+        assert_eq!(
+            image
+                .read_file("/auto-installer-files/certs/ca.crt")
+                .unwrap()
+                .unwrap(),
+            b"certificate\n"
+        );
+        assert_eq!(
+            image.read_file("/auto-installer-capable").unwrap().unwrap(),
+            b"flag\n"
+        );
+        let root = image
+            .read_tree(image.descriptors[0].root_record(), TreeKind::Primary, 0)
+            .unwrap();
+        let names: Vec<&str> = root
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "answer.toml",
+                "auto-installer-capable",
+                "auto-installer-files",
+                ".auto-installer-original"
+            ]
+        );
+        let idents: Vec<&[u8]> = root
+            .entries
+            .iter()
+            .map(|entry| entry.records[0].ident())
+            .collect();
+        assert_eq!(
+            idents,
+            [
+                &b"ANSWER.TOM;1"[..],
+                b"AUTO_INS.;1",
+                b"AUTO_I_1",
+                b"_AUTO_IN.;1"
+            ]
+        );
+
+        // the volume dates, and with them the UUID, are kept
+        let pvd = &image.descriptors[0].data;
+        assert_eq!(&pvd[813..829], b"2024042612000000");
+        let image_size = fs::metadata(&iso_path).unwrap().len();
+        assert_eq!(le_u32(&pvd[80..]) as u64 * SECTOR_SIZE, image_size);
+
+        // the original image is still readable
+        let original = IsoImage::open_original(&iso_path).unwrap();
+        assert!(!original.contains("/answer.toml").unwrap());
+        assert!(original.contains("/auto-installer-capable").unwrap());
+
+        // adding files again starts from the original image
+        fs::write(dir.join("answer.toml"), "[network]\n").unwrap();
+        add_files(&iso_path, &files[..1], &[]).unwrap();
+        let image = IsoImage::open(&iso_path).unwrap();
+        assert_eq!(
+            image.read_file("/answer.toml").unwrap().unwrap(),
+            b"[network]\n"
+        );
+        assert!(!image.contains("/auto-installer-files").unwrap());
+
+        let fresh_path = dir.join("fresh.iso");
+        write_image(&fresh_path);
+        add_files(&fresh_path, &files[..1], &[]).unwrap();
+        assert!(fs::read(&fresh_path).unwrap() == fs::read(&iso_path).unwrap());
+
+        // several copies with different files can be written at once
+        let outputs = ["a", "b"].map(|name| {
+            let source = dir.join(format!("{name}.toml"));
+            fs::write(&source, format!("# {name}\n")).unwrap();
+            IsoOutput {
+                path: dir.join(format!("{name}.iso")),
+                files: vec![IsoFile {
+                    path: "/answer.toml".into(),
+                    source,
+                }],
+            }
+        });
+        write_copies(&iso_path, &outputs, &[]).unwrap();
+        for (name, output) in ["a", "b"].iter().zip(&outputs) {
+            let image = IsoImage::open(&output.path).unwrap();
+            assert_eq!(
+                image.read_file("/answer.toml").unwrap().unwrap(),
+                format!("# {name}\n").as_bytes()
+            );
+        }
+        // and are the same as written one by one
+        let written = fs::read(&outputs[1].path).unwrap();
+        write_copies(&fresh_path, &outputs[1..], &[]).unwrap();
+        assert!(fs::read(&outputs[1].path).unwrap() == written);
+
+        // existing files can be removed
+        add_files(&fresh_path, &[], &["/auto-installer-capable", "/missing"]).unwrap();
+        let image = IsoImage::open(&fresh_path).unwrap();
+        assert!(!image.contains("/auto-installer-capable").unwrap());
+        assert!(!image.contains("/answer.toml").unwrap());
+
+        assert!(add_files(
+            &iso_path,
+            &[IsoFile {
+                path: "/auto-installer-capable/answer.toml".into(),
+                source: dir.join("answer.toml"),
+            }],
+            &[]
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,349 @@
+//! Checks of answer files for settings which are valid, but likely not what was intended.
+
+use anyhow::{format_err, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use proxmox_auto_installer::answer::{
+    Answer, DiskSelection, FsOptions, NetworkSettings, ZfsOptions,
+};
+use proxmox_installer_common::{
+    options::{default_zfs_arc_max, ZfsRaidLevel, ZFS_ARC_MIN_SIZE_MIB},
+    setup::ProxmoxProduct,
+};
+
+/// A warning about a setting of the answer file.
+#[derive(Debug, Serialize)]
+pub struct Warning {
+    /// Identifier of the check, stable for use in scripts.
+    pub lint: &'static str,
+    pub level: Level,
+    /// The key of the answer file the warning refers to.
+    pub key: &'static str,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Warning,
+    /// Information about a check that could not be done, for example because the needed system
+    /// information is missing.
+    Note,
+}
+
+/// The parts of the system information, as output by 'system-info', the checks depend on.
+#[derive(Debug, Deserialize)]
+pub struct TargetSystem {
+    product: TargetProduct,
+    /// Total memory in MiB.
+    total_memory: usize,
+    disks: Vec<TargetDisk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TargetProduct {
+    product: ProxmoxProduct,
+}
+
+#[derive(Debug, Deserialize)]
+struct TargetDisk {
+    name: String,
+    block_size: Option<u64>,
+}
+
+impl TargetSystem {
+    pub fn read(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .map_err(|err| format_err!("Reading system information {path:?} failed: {err}"))?;
+        serde_json::from_str(&raw)
+            .map_err(|err| format_err!("Error parsing system information {path:?}: {err}"))
+    }
+}
+
+/// Checks the answer file against best practices. `raw` is the content of the answer file,
+/// needed to tell apart unset keys from ones set to their default. The checks which depend on
+/// the hardware are only done if the `system` to install is known. Notes about skipped checks
+/// are returned after the warnings.
+pub fn lint(raw: &str, answer: &Answer, system: Option<&TargetSystem>) -> Result<Vec<Warning>> {
+    let raw: toml::Table = toml::from_str(raw)?;
+    let mut warnings = Vec::new();
+    let mut notes = Vec::new();
+    let mut warn = |lint, key, message: String| {
+        warnings.push(Warning {
+            lint,
+            level: Level::Warning,
+            key,
+            message,
+        })
+    };
+    let mut note = |lint, key, message: String| {
+        notes.push(Warning {
+            lint,
+            level: Level::Note,
+            key,
+            message,
+        })
+    };
+
+    let global = &answer.global;
+    if global.root_password.is_some() {
+        warn(
+            "plain-root-password",
+            "global.root_password",
+            "The root password is stored in plain text, use 'root_password_hashed' instead.".into(),
+        );
+    }
+    if global.root_ssh_keys.is_empty() {
+        warn(
+            "no-root-ssh-keys",
+            "global.root_ssh_keys",
+            "No SSH keys are set, logging in as root is only possible with the password.".into(),
+        );
+    }
+    if is_example_address(&global.mailto) {
+        warn(
+            "example-mailto",
+            "global.mailto",
+            format!(
+                "The address '{}' is an example, notifications will not be delivered.",
+                global.mailto
+            ),
+        );
+    }
+    let reboot_on_error = raw
+        .get("global")
+        .and_then(|global| global.get("reboot_on_error"));
+    if reboot_on_error.is_none() {
+        warn(
+            "reboot-on-error-unset",
+            "global.reboot_on_error",
+            "Not set, a failed unattended installation waits for manual intervention instead \
+             of rebooting."
+                .into(),
+        );
+    }
+
+    if let NetworkSettings::Manual(network) = &answer.network.network_settings {
+        if has_devname(&network.filter) {
+            warn(
+                "devname-filter",
+                "network.filter",
+                "Matching by 'DEVNAME' is unstable, kernel names can change between boots.".into(),
+            );
+        }
+    }
+    if let DiskSelection::Filter(filter) = &answer.disks.disk_selection {
+        if has_devname(filter) {
+            warn(
+                "devname-filter",
+                "disk-setup.filter",
+                "Matching by 'DEVNAME' is unstable, kernel names can change between boots.".into(),
+            );
+        }
+    }
+
+    if let FsOptions::ZFS(zfs) = &answer.disks.fs_options {
+        lint_zfs(
+            zfs,
+            &answer.disks.disk_selection,
+            system,
+            &mut warn,
+            &mut note,
+        );
+    }
+
+    warnings.append(&mut notes);
+    Ok(warnings)
+}
+
+fn lint_zfs(
+    zfs: &ZfsOptions,
+    selection: &DiskSelection,
+    system: Option<&TargetSystem>,
+    warn: &mut impl FnMut(&'static str, &'static str, String),
+    note: &mut impl FnMut(&'static str, &'static str, String),
+) {
+    let copies = zfs.copies.unwrap_or(1);
+    if copies > 1 && matches!(zfs.raid, Some(ZfsRaidLevel::Raid1 | ZfsRaidLevel::Raid10)) {
+        warn(
+            "copies-on-mirror",
+            "disk-setup.zfs.copies",
+            format!(
+                "Storing {copies} copies on a mirror multiplies the used space, the mirror \
+                 already provides redundancy."
+            ),
+        );
+    }
+
+    match zfs.arc_max {
+        Some(arc_max) if arc_max > 0 && arc_max < ZFS_ARC_MIN_SIZE_MIB => warn(
+            "arc-max",
+            "disk-setup.zfs.arc_max",
+            format!("{arc_max} MiB is raised to the minimum of {ZFS_ARC_MIN_SIZE_MIB} MiB."),
+        ),
+        _ => (),
+    }
+
+    let Some(system) = system else {
+        return;
+    };
+    if let Some(arc_max) = zfs.arc_max {
+        let default = default_zfs_arc_max(system.product.product, system.total_memory);
+        if arc_max > system.total_memory {
+            warn(
+                "arc-max",
+                "disk-setup.zfs.arc_max",
+                format!(
+                    "{arc_max} MiB exceeds the memory of the system and is lowered to {} MiB.",
+                    system.total_memory
+                ),
+            );
+        } else if default == 0 && arc_max != 0 {
+            warn(
+                "arc-max",
+                "disk-setup.zfs.arc_max",
+                format!(
+                    "{} uses the ZFS default (0) for the ARC size, set to {arc_max} MiB.",
+                    system.product.product
+                ),
+            );
+        }
+    }
+
+    let disks = match selection {
+        DiskSelection::Selection(disks) => disks,
+        DiskSelection::Filter(_) => {
+            // the system information has no udev properties to match the filter against
+            note(
+                "ashift",
+                "disk-setup.zfs.ashift",
+                "Skipped, the disks matching 'disk-setup.filter' are not known from the system \
+                 information."
+                    .into(),
+            );
+            return;
+        }
+    };
+    let ashift = zfs.ashift.unwrap_or(12);
+    let block_size = disks
+        .iter()
+        .filter_map(|name| system.disks.iter().find(|disk| disk.name == *name))
+        .filter_map(|disk| disk.block_size)
+        .max();
+    match block_size {
+        Some(block_size) if ashift < 64 && (1u64 << ashift) < block_size => warn(
+            "ashift",
+            "disk-setup.zfs.ashift",
+            format!(
+                "An ashift of {ashift} is lower than the block size of {block_size} bytes \
+                 of the disks, which degrades performance."
+            ),
+        ),
+        _ => (),
+    }
+}
+
+/// Whether the address uses a domain reserved for examples and documentation.
+fn is_example_address(address: &str) -> bool {
+    let domain = address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain)
+        .unwrap_or(address)
+        .to_lowercase();
+    ["example.com", "example.net", "example.org"]
+        .iter()
+        .any(|example| domain == *example || domain.ends_with(&format!(".{example}")))
+        || [".example", ".invalid", ".test", ".localhost"]
+            .iter()
+            .any(|tld| domain.ends_with(tld))
+        || domain == "localhost"
+}
+
+fn has_devname(filter: &BTreeMap<String, String>) -> bool {
+    filter.keys().any(|key| key == "DEVNAME")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proxmox_auto_installer::{sysinfo::SysInfo, sysroot::SysRoot};
+
+    fn lints(raw: &str, system: Option<&TargetSystem>) -> Vec<&'static str> {
+        let answer: Answer = toml::from_str(raw).unwrap();
+        lint(raw, &answer, system)
+            .unwrap()
+            .into_iter()
+            .map(|warning| warning.lint)
+            .collect()
+    }
+
+    #[test]
+    fn lints_answers() {
+        let answer = "[global]\nkeyboard = \"de\"\ncountry = \"at\"\nfqdn = \"pve.example.com\"\n\
+            mailto = \"mail@no.invalid\"\ntimezone = \"Europe/Vienna\"\n\
+            root_password = \"123456\"\n\n[network]\nsource = \"from-dhcp\"\n\n\
+            [disk-setup]\nfilesystem = \"zfs\"\nzfs.raid = \"raid1\"\nzfs.copies = 2\n\
+            zfs.arc_max = 32\nfilter.DEVNAME = \"/dev/sd*\"\n";
+        assert_eq!(
+            lints(answer, None),
+            [
+                "plain-root-password",
+                "no-root-ssh-keys",
+                "example-mailto",
+                "reboot-on-error-unset",
+                "devname-filter",
+                "copies-on-mirror",
+                "arc-max",
+            ]
+        );
+
+        let answer = "[global]\nkeyboard = \"de\"\ncountry = \"at\"\nfqdn = \"pve.example.com\"\n\
+            mailto = \"admin@proxmox.com\"\ntimezone = \"Europe/Vienna\"\n\
+            root_password_hashed = \"$y$j9T$salt$hash\"\nroot_ssh_keys = [\"ssh-ed25519 AAAA\"]\n\
+            reboot_on_error = false\n\n[network]\nsource = \"from-dhcp\"\n\n\
+            [disk-setup]\nfilesystem = \"zfs\"\nzfs.raid = \"raid1\"\nzfs.arc_max = 4096\n\
+            disk_list = [\"sda\", \"nvme0n1\"]\n";
+        assert!(lints(answer, None).is_empty());
+
+        let system: TargetSystem = serde_json::from_str(
+            r#"{"product": {"fullname": "Proxmox Backup Server", "product": "pbs",
+                "enable_btrfs": true}, "total_memory": 2048,
+                "disks": [{"name": "sda", "block_size": 512},
+                          {"name": "nvme0n1", "block_size": 8192}]}"#,
+        )
+        .unwrap();
+        assert_eq!(lints(answer, Some(&system)), ["arc-max", "ashift"]);
+        let answer = answer.replace("arc_max = 4096", "arc_max = 1024");
+        assert_eq!(lints(&answer, Some(&system)), ["arc-max", "ashift"]);
+        let answer = answer.replace("zfs.arc_max = 1024", "zfs.ashift = 13\nzfs.arc_max = 0");
+        assert!(lints(&answer, Some(&system)).is_empty());
+
+        // sda of the fixture has 512 byte logical, but 4096 byte physical sectors
+        let root = SysRoot::new("../proxmox-auto-installer/tests/resources/sysroot");
+        let info = serde_json::to_value(SysInfo::get_from(&root).unwrap()).unwrap();
+        let system: TargetSystem = serde_json::from_value(info).unwrap();
+        let answer = answer.replace("zfs.ashift = 13", "zfs.ashift = 9");
+        assert_eq!(lints(&answer, Some(&system)), ["ashift"]);
+        let answer = answer.replace("zfs.ashift = 9", "zfs.ashift = 12");
+        assert!(lints(&answer, Some(&system)).is_empty());
+
+        // disks selected by a filter are not known from the system information
+        let answer = answer.replace(
+            "disk_list = [\"sda\", \"nvme0n1\"]",
+            "filter.ID_SERIAL_SHORT = \"S4EVNF0M*\"",
+        );
+        let answer = answer.replace("zfs.ashift = 12", "zfs.ashift = 9");
+        let parsed: Answer = toml::from_str(&answer).unwrap();
+        let warnings = lint(&answer, &parsed, Some(&system)).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            (warnings[0].lint, warnings[0].level),
+            ("ashift", Level::Note)
+        );
+        assert!(lints(&answer, None).is_empty());
+
+        assert!(!is_example_address("root@example.community"));
+        assert!(is_example_address("root@mail.example.org"));
+    }
+}
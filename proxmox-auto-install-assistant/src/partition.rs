@@ -0,0 +1,249 @@
+//! Creating FAT images with an answer file, for the 'partition' fetch-from mode.
+
+use anyhow::{bail, format_err, Result};
+use clap::Args;
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::check_answer_file;
+
+// must match the label the automatic installer is looking for, FAT labels are upper case
+const PARTITION_LABEL: &[u8; 11] = b"PROXMOX-AIS";
+const ANSWER_FILE: &str = "answer.toml";
+const HOST_ANSWERS_DIR: &str = "answers";
+const MIB: u64 = 1024 * 1024;
+const MIN_IMAGE_SIZE: u64 = 16 * MIB;
+
+/// Prepare a FAT image for the 'partition' fetch-from mode.
+///
+/// The image uses 'PROXMOX-AIS' as file system label, as expected by the automatic installer, and
+/// has no partition table. No root privileges are needed to create it. It can be written to a USB
+/// flash drive, for example with 'dd if=ais.img of=/dev/sdX bs=4M conv=fsync', or attached as
+/// virtual media via the BMC.
+///
+/// Host specific answer files from the '--host-answers' directory are placed in the 'answers'
+/// directory of the image. They need to be named after a MAC address, with dashes instead of
+/// colons, the DMI serial number or the DMI product UUID of the host, for example
+/// 'bc-24-11-00-00-01.toml'.
+///
+/// Additional files, like lookup tables for answer file templates, can be placed in the root
+/// directory of the image with '--file'.
+#[derive(Args, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct CommandPreparePartition {
+    /// Path to the default answer file
+    #[arg(long)]
+    answer: PathBuf,
+
+    /// Directory containing host specific answer files
+    #[arg(long)]
+    host_answers: Option<PathBuf>,
+
+    /// Additional file to place in the root directory of the image. Can be specified multiple
+    /// times.
+    #[arg(long = "file")]
+    files: Vec<PathBuf>,
+
+    /// Path to store the image to
+    #[arg(long)]
+    output: PathBuf,
+
+    /// Size of the image in MiB, defaults to the smallest sensible size for the contained files
+    #[arg(long)]
+    size: Option<u64>,
+}
+
+/// A file to copy into the image.
+struct ImageFile {
+    /// Path within the image
+    target: String,
+    source: PathBuf,
+}
+
+pub fn prepare_partition(args: &CommandPreparePartition) -> Result<()> {
+    println!("Checking provided answer files...");
+    check_answer_file(&args.answer)?;
+    let mut files = vec![ImageFile {
+        target: ANSWER_FILE.into(),
+        source: args.answer.clone(),
+    }];
+
+    if let Some(dir) = &args.host_answers {
+        files.extend(collect_host_answers(dir)?);
+    }
+
+    for path in &args.files {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            bail!("Invalid file name {path:?}.");
+        };
+        if !path.is_file() {
+            bail!("{path:?} is not a file.");
+        }
+        if files.iter().any(|file| file.target == name) {
+            bail!("The file '{name}' is added more than once.");
+        }
+        files.push(ImageFile {
+            target: name.into(),
+            source: path.clone(),
+        });
+    }
+
+    let size = match args.size {
+        Some(size) => size * MIB,
+        None => image_size(&files)?,
+    };
+
+    if let Ok(metadata) = fs::metadata(&args.output) {
+        if !metadata.is_file() {
+            bail!("Output {:?} exists and is not a regular file.", args.output);
+        }
+    }
+
+    println!("Creating image {:?}...", args.output);
+    write_image(&args.output, size, &files)
+        .map_err(|err| format_err!("Creating image {:?} failed: {err}", args.output))?;
+
+    for file in &files {
+        println!("  {}", file.target);
+    }
+    println!(
+        "Wrote {} MiB image with label '{}' to {:?}.",
+        size / MIB,
+        String::from_utf8_lossy(PARTITION_LABEL),
+        args.output
+    );
+    Ok(())
+}
+
+fn collect_host_answers(dir: &Path) -> Result<Vec<ImageFile>> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format_err!("Reading directory {dir:?} failed: {err}"))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            bail!("Invalid file name {path:?}.");
+        };
+        if !path.is_file() || !name.ends_with(".toml") {
+            println!("Skipping {path:?}, not an answer file.");
+            continue;
+        }
+        if name.contains(':') {
+            bail!(
+                "Host answer file {path:?} contains a ':', which is not allowed on FAT file \
+                 systems. Use dashes to separate the parts of MAC addresses."
+            );
+        }
+        check_answer_file(&path)?;
+
+        files.push(ImageFile {
+            target: format!("{HOST_ANSWERS_DIR}/{name}"),
+            source: path,
+        });
+    }
+    files.sort_by(|a, b| a.target.cmp(&b.target));
+    Ok(files)
+}
+
+/// Returns an image size, in whole MiB, leaving enough room for the file system structures.
+fn image_size(files: &[ImageFile]) -> Result<u64> {
+    let mut content_size = 0;
+    for file in files {
+        let size = fs::metadata(&file.source)?.len();
+        // round up to the cluster size
+        content_size += size.div_ceil(4096) * 4096;
+    }
+    let size = (content_size * 2 + MIB).div_ceil(MIB) * MIB;
+    Ok(size.max(MIN_IMAGE_SIZE))
+}
+
+fn write_image(path: &Path, size: u64, files: &[ImageFile]) -> Result<()> {
+    let image = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    image.set_len(size)?;
+
+    // the volume ID is used as file system UUID, it is usually derived from the current time
+    let volume_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as u32 ^ time.subsec_nanos())
+        .unwrap_or_default();
+    fatfs::format_volume(
+        &image,
+        FormatVolumeOptions::new()
+            .volume_label(*PARTITION_LABEL)
+            .volume_id(volume_id),
+    )?;
+    let fat = FileSystem::new(&image, FsOptions::new())?;
+    {
+        let root = fat.root_dir();
+        for file in files {
+            if let Some((dir, _)) = file.target.rsplit_once('/') {
+                root.create_dir(dir)?;
+            }
+            let mut source = fs::File::open(&file.source)
+                .map_err(|err| format_err!("Opening {:?} failed: {err}", file.source))?;
+            let mut target = root.create_file(&file.target)?;
+            target.truncate()?;
+            io::copy(&mut source, &mut target)
+                .map_err(|err| format_err!("Writing '{}' failed: {err}", file.target))?;
+        }
+    }
+    fat.unmount()?;
+    image.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proxmox_auto_installer::testing::TempDir;
+    use std::io::Read;
+
+    #[test]
+    fn write_fat_image() {
+        let dir = TempDir::new("prepare-partition");
+        fs::write(dir.join("answer.toml"), "[global]\n").unwrap();
+        fs::write(dir.join("host.toml"), "[network]\n").unwrap();
+        fs::write(dir.join("hosts.csv"), "bc:24:11:00:00:01, pve1\n").unwrap();
+
+        let files = [
+            (ANSWER_FILE, "answer.toml"),
+            ("answers/bc-24-11-00-00-01.toml", "host.toml"),
+            ("answers/CZ1234.toml", "host.toml"),
+            ("hosts.csv", "hosts.csv"),
+        ]
+        .map(|(target, source)| ImageFile {
+            target: target.into(),
+            source: dir.join(source),
+        });
+        let image_path = dir.join("ais.img");
+        let size = image_size(&files).unwrap();
+        assert_eq!(size, MIN_IMAGE_SIZE);
+        write_image(&image_path, size, &files).unwrap();
+
+        let image = fs::File::open(&image_path).unwrap();
+        assert_eq!(image.metadata().unwrap().len(), size);
+        let fat = FileSystem::new(image, FsOptions::new()).unwrap();
+        assert_eq!(fat.volume_label(), "PROXMOX-AIS");
+
+        let read = |path: &str| {
+            let mut content = String::new();
+            let mut file = fat.root_dir().open_file(path).unwrap();
+            file.read_to_string(&mut content).unwrap();
+            content
+        };
+        assert_eq!(read("answer.toml"), "[global]\n");
+        assert_eq!(read("answers/bc-24-11-00-00-01.toml"), "[network]\n");
+        assert_eq!(read("answers/CZ1234.toml"), "[network]\n");
+        assert_eq!(read("hosts.csv"), "bc:24:11:00:00:01, pve1\n");
+    }
+}
@@ -0,0 +1,160 @@
+//! Line based prompts for the interactive commands.
+
+use anyhow::{bail, Result};
+use nix::sys::termios::{self, LocalFlags, SetArg};
+use std::{
+    io::{self, BufRead, IsTerminal, StdinLock, Write},
+    os::fd::AsRawFd,
+};
+
+/// Line based prompts on a terminal, or any other input.
+pub struct Prompt<R, W> {
+    input: R,
+    output: W,
+    /// Whether the input is a terminal, on which echo is turned off while reading passwords.
+    terminal: bool,
+}
+
+impl<R, W> Prompt<R, W> {
+    /// Reads the answers from `input`, the prompts are written to `output`. Passwords are only
+    /// hidden if the input is a `terminal`, which has to be the standard input then.
+    pub fn new(input: R, output: W, terminal: bool) -> Self {
+        Self {
+            input,
+            output,
+            terminal,
+        }
+    }
+}
+
+impl<W: Write> Prompt<StdinLock<'static>, W> {
+    /// Reads the answers from the standard input, the prompts are written to `output`.
+    pub fn stdin(output: W) -> Self {
+        let stdin = io::stdin();
+        Self::new(stdin.lock(), output, stdin.is_terminal())
+    }
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    pub fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    /// Writes an empty line, to separate groups of questions.
+    pub fn separator(&mut self) -> Result<()> {
+        writeln!(self.output)?;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            bail!("Aborted, end of input reached.");
+        }
+        Ok(line.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Asks until a valid value is entered, an empty line selects the default.
+    pub fn ask<T>(
+        &mut self,
+        question: &str,
+        default: Option<&str>,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> Result<T> {
+        loop {
+            match default {
+                Some(default) => write!(self.output, "{question} [{default}]: ")?,
+                None => write!(self.output, "{question}: ")?,
+            }
+            self.output.flush()?;
+
+            let line = self.read_line()?;
+            let value = match (line.trim(), default) {
+                ("", Some(default)) => default,
+                ("", None) => {
+                    writeln!(self.output, "A value is required.")?;
+                    continue;
+                }
+                (value, _) => value,
+            };
+            match parse(value) {
+                Ok(value) => return Ok(value),
+                Err(err) => writeln!(self.output, "Invalid value: {err}")?,
+            }
+        }
+    }
+
+    pub fn text(&mut self, question: &str, default: Option<&str>) -> Result<String> {
+        self.ask(question, default, |value| Ok(value.to_string()))
+    }
+
+    pub fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let default = if default { "y" } else { "n" };
+        self.ask(question, Some(default), |value| {
+            match value.to_lowercase().as_str() {
+                "y" | "yes" => Ok(true),
+                "n" | "no" => Ok(false),
+                _ => Err("answer 'y' or 'n'".to_string()),
+            }
+        })
+    }
+
+    /// Shows a numbered list of the choices and returns the selected one.
+    pub fn choose<T: Clone>(
+        &mut self,
+        question: &str,
+        choices: &[(T, String)],
+        default: usize,
+    ) -> Result<T> {
+        writeln!(self.output, "\n{question}")?;
+        for (i, (_, label)) in choices.iter().enumerate() {
+            writeln!(self.output, "{:>4}) {label}", i + 1)?;
+        }
+        let index = self.ask(
+            "Choice",
+            Some(&(default + 1).to_string()),
+            |value| match value.parse::<usize>() {
+                Ok(i) if (1..=choices.len()).contains(&i) => Ok(i - 1),
+                _ => Err(format!("enter a number from 1 to {}", choices.len())),
+            },
+        )?;
+        Ok(choices[index].0.clone())
+    }
+
+    pub fn password(&mut self, question: &str) -> Result<String> {
+        write!(self.output, "{question}: ")?;
+        self.output.flush()?;
+        if !self.terminal {
+            return self.read_line();
+        }
+
+        let fd = io::stdin().as_raw_fd();
+        let original = termios::tcgetattr(fd)?;
+        let mut hidden = original.clone();
+        hidden.local_flags.remove(LocalFlags::ECHO);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &hidden)?;
+        let password = self.read_line();
+        termios::tcsetattr(fd, SetArg::TCSANOW, &original)?;
+        writeln!(self.output)?;
+        password
+    }
+
+    /// Reads a new password, which has to be entered twice if `confirm` is set.
+    pub fn new_password(&mut self, question: &str, confirm: bool) -> Result<String> {
+        loop {
+            let password = self.password(question)?;
+            if password.len() < 5 {
+                writeln!(
+                    self.output,
+                    "The password must be at least 5 characters long."
+                )?;
+            } else if confirm
+                && password != self.password(&format!("Confirm {}", question.to_lowercase()))?
+            {
+                writeln!(self.output, "The passwords do not match.")?;
+            } else {
+                return Ok(password);
+            }
+        }
+    }
+}
@@ -0,0 +1,557 @@
+//! HTTP(S) server providing answer files, selected by rules matching the system information.
+
+use anyhow::{bail, format_err, Result};
+use clap::Args;
+use glob::{MatchOptions, Pattern};
+use proxmox_auto_installer::template;
+use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection, StreamOwned};
+use rustls_pemfile::Item;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+// the SysInfo sent by proxmox-fetch-answer is only a few KiB
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+// see 'prepare-iso --error-report-url'
+const ERROR_REPORT_PATH: &str = "/error-report";
+
+/// Serve answer files for the 'http' fetch-from mode.
+///
+/// Every request by the automatic installer contains the system information of the host, as shown
+/// by the 'system-info' command. It is matched against the rules, in the order they are defined,
+/// and the answer file of the first matching rule is returned. Answer file templates are rendered
+/// with the system information of the requesting host.
+///
+/// Hosts can report invalid answer files on the '/error-report' path, see the
+/// '--error-report-url' option of the 'prepare-iso' command. Reports are logged to stderr.
+///
+/// The rules file has the following format:
+///
+/// [[rule]]
+/// answer = "node1.toml"
+/// [rule.match]
+/// serial = "CZ1234*"
+///
+/// [[rule]]
+/// answer = "default.toml"
+///
+/// Possible match keys are 'serial' (DMI system serial number), 'mac' (MAC address of any network
+/// interface), 'product' (DMI product name) and 'uuid' (DMI product UUID). The values are
+/// case-insensitive glob patterns and all keys of a rule need to match. A rule without any match
+/// keys matches every host.
+///
+/// Unless '--no-tls' is set, the server uses HTTPS. If the certificate and key files do not exist,
+/// a self-signed certificate is generated and saved. The printed fingerprint can be passed to
+/// 'prepare-iso --cert-fingerprint'.
+#[derive(Args, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct CommandServe {
+    /// Path to the rules file
+    #[arg(long)]
+    rules: PathBuf,
+
+    /// Directory containing the answer files referenced in the rules
+    #[arg(long)]
+    answers: PathBuf,
+
+    /// Address and port to listen on
+    #[arg(long, default_value = "[::]:8443")]
+    listen: SocketAddr,
+
+    /// Path to the PEM encoded TLS certificate
+    #[arg(long, default_value = "proxmox-auto-install-server.pem")]
+    cert: PathBuf,
+
+    /// Path to the PEM encoded private key of the TLS certificate
+    #[arg(long, default_value = "proxmox-auto-install-server.key")]
+    key: PathBuf,
+
+    /// Serve plain HTTP, without TLS
+    #[arg(long, default_value_t = false)]
+    no_tls: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    answer: PathBuf,
+    #[serde(default, rename = "match")]
+    filter: RuleFilter,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFilter {
+    serial: Option<String>,
+    mac: Option<String>,
+    product: Option<String>,
+    uuid: Option<String>,
+}
+
+struct ServerState {
+    rules: Vec<Rule>,
+    answers_dir: PathBuf,
+}
+
+struct Rule {
+    answer: PathBuf,
+    serial: Option<Pattern>,
+    mac: Option<Pattern>,
+    product: Option<Pattern>,
+    uuid: Option<Pattern>,
+}
+
+/// The properties of a host that rules can match against.
+#[derive(Debug, Default)]
+struct HostProperties {
+    serial: Option<String>,
+    macs: Vec<String>,
+    product: Option<String>,
+    uuid: Option<String>,
+}
+
+impl HostProperties {
+    fn from_sysinfo(sysinfo: &Value) -> Self {
+        let dmi_value = |key: &str| {
+            sysinfo
+                .pointer(&format!("/dmi/system/{key}"))
+                .and_then(Value::as_str)
+                .map(String::from)
+        };
+        let macs = sysinfo
+            .get("network_interfaces")
+            .and_then(Value::as_array)
+            .map(|nics| {
+                nics.iter()
+                    .filter_map(|nic| nic.get("mac")?.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            serial: dmi_value("serial"),
+            macs,
+            product: dmi_value("name"),
+            uuid: dmi_value("uuid"),
+        }
+    }
+}
+
+impl Rule {
+    fn matches(&self, host: &HostProperties) -> bool {
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..Default::default()
+        };
+        let matches = |pattern: &Option<Pattern>, value: &Option<String>| match pattern {
+            Some(pattern) => value
+                .as_deref()
+                .map(|value| pattern.matches_with(value, options))
+                .unwrap_or(false),
+            None => true,
+        };
+
+        let mac_matches = match &self.mac {
+            Some(pattern) => host
+                .macs
+                .iter()
+                .any(|mac| pattern.matches_with(mac, options)),
+            None => true,
+        };
+
+        mac_matches
+            && matches(&self.serial, &host.serial)
+            && matches(&self.product, &host.product)
+            && matches(&self.uuid, &host.uuid)
+    }
+}
+
+fn parse_rules(content: &str, answers_dir: &Path) -> Result<Vec<Rule>> {
+    let rules: RulesFile = toml::from_str(content)?;
+
+    let compile = |pattern: Option<String>| -> Result<Option<Pattern>> {
+        pattern
+            .map(|pattern| {
+                Pattern::new(&pattern)
+                    .map_err(|err| format_err!("invalid pattern '{pattern}' - {err}"))
+            })
+            .transpose()
+    };
+
+    rules
+        .rule
+        .into_iter()
+        .map(|entry| {
+            let answer = answers_dir.join(&entry.answer);
+            if !answer.is_file() {
+                bail!("answer file {answer:?} does not exist");
+            }
+            Ok(Rule {
+                answer,
+                serial: compile(entry.filter.serial)?,
+                mac: compile(entry.filter.mac)?,
+                product: compile(entry.filter.product)?,
+                uuid: compile(entry.filter.uuid)?,
+            })
+        })
+        .collect()
+}
+
+pub fn serve(args: &CommandServe) -> Result<()> {
+    let content = fs::read_to_string(&args.rules)
+        .map_err(|err| format_err!("Reading rules file {:?} failed: {err}", args.rules))?;
+    let rules = parse_rules(&content, &args.answers)
+        .map_err(|err| format_err!("Error parsing rules file: {err}"))?;
+    if rules.is_empty() {
+        bail!("No rules defined in {:?}.", args.rules);
+    }
+    let state = Arc::new(ServerState {
+        rules,
+        answers_dir: args.answers.clone(),
+    });
+
+    let tls_config = if args.no_tls {
+        None
+    } else {
+        Some(Arc::new(load_tls_config(&args.cert, &args.key)?))
+    };
+
+    let listener = TcpListener::bind(args.listen)
+        .map_err(|err| format_err!("Could not listen on {}: {err}", args.listen))?;
+    let scheme = if args.no_tls { "http" } else { "https" };
+    println!("Serving answer files on {scheme}://{}/", args.listen);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Accepting connection failed: {err}");
+                continue;
+            }
+        };
+        let state = Arc::clone(&state);
+        let tls_config = tls_config.clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|_| "unknown".into());
+            if let Err(err) = handle_connection(stream, tls_config, &state, &peer) {
+                eprintln!("{peer}: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    tls_config: Option<Arc<ServerConfig>>,
+    state: &ServerState,
+    peer: &str,
+) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    match tls_config {
+        Some(config) => {
+            let connection = ServerConnection::new(config)?;
+            let mut stream = StreamOwned::new(connection, stream);
+            handle_request(&mut stream, state, peer)?;
+            stream.conn.send_close_notify();
+            stream.flush()?;
+        }
+        None => {
+            let mut stream = stream;
+            handle_request(&mut stream, state, peer)?;
+        }
+    }
+    Ok(())
+}
+
+fn handle_request<S: Read + Write>(stream: &mut S, state: &ServerState, peer: &str) -> Result<()> {
+    let (method, path, body) = match read_request(stream) {
+        Ok(request) => request,
+        Err(err) => {
+            write_response(stream, 400, "Bad Request", &format!("{err}\n"))?;
+            bail!("invalid request - {err}");
+        }
+    };
+    if method != "POST" {
+        write_response(
+            stream,
+            405,
+            "Method Not Allowed",
+            "only POST is supported\n",
+        )?;
+        bail!("unsupported method '{method}'");
+    }
+
+    if path == ERROR_REPORT_PATH {
+        return handle_error_report(stream, &body, peer);
+    }
+
+    let sysinfo: Value = match serde_json::from_slice(&body) {
+        Ok(sysinfo) => sysinfo,
+        Err(err) => {
+            write_response(stream, 400, "Bad Request", "invalid system information\n")?;
+            bail!("could not parse system information - {err}");
+        }
+    };
+    let host = HostProperties::from_sysinfo(&sysinfo);
+
+    let Some(rule) = state.rules.iter().find(|rule| rule.matches(&host)) else {
+        write_response(stream, 404, "Not Found", "no matching rule\n")?;
+        bail!("no rule matched host {host:?}");
+    };
+
+    let answer = fs::read_to_string(&rule.answer)
+        .map_err(|err| format_err!("reading answer file {:?} failed - {err}", rule.answer))
+        .and_then(|answer| {
+            if !template::is_template(&answer) {
+                return Ok(answer);
+            }
+            let context = template::sysinfo_context(sysinfo);
+            template::render(&answer, &context, std::slice::from_ref(&state.answers_dir))
+                .map_err(|err| format_err!("rendering {:?} failed - {err}", rule.answer))
+        });
+
+    match answer {
+        Ok(answer) => {
+            println!("{peer}: serving {:?} to host {host:?}", rule.answer);
+            write_response(stream, 200, "OK", &answer)
+        }
+        Err(err) => {
+            write_response(stream, 500, "Internal Server Error", "\n")?;
+            bail!("{err}");
+        }
+    }
+}
+
+/// Logs an invalid answer file reported by the automatic installer of a host.
+fn handle_error_report<S: Write>(stream: &mut S, body: &[u8], peer: &str) -> Result<()> {
+    let report: Value = match serde_json::from_slice(body) {
+        Ok(report) => report,
+        Err(err) => {
+            write_response(stream, 400, "Bad Request", "invalid error report\n")?;
+            bail!("could not parse error report - {err}");
+        }
+    };
+    let host = HostProperties::from_sysinfo(report.get("system_info").unwrap_or(&Value::Null));
+    let error = report
+        .get("error")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown error");
+
+    eprintln!("{peer}: host {host:?} reported an invalid answer file: {error}");
+    write_response(stream, 200, "OK", "\n")
+}
+
+/// Reads a HTTP/1.x request, returning the method, the path and the body.
+fn read_request<S: Read>(stream: &mut S) -> Result<(String, String, Vec<u8>)> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let (method, path) = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        [method, path, version] if version.starts_with("HTTP/1.") => {
+            (method.to_string(), path.to_string())
+        }
+        _ => bail!("malformed request line"),
+    };
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("unexpected end of request");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            } else if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+                bail!("chunked transfer encoding is not supported");
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        bail!("request body too large");
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok((method, path, body))
+}
+
+fn write_response<S: Write>(stream: &mut S, status: u16, reason: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Loads the certificate and key, generating a self-signed certificate if both files are missing.
+fn load_tls_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig> {
+    if !cert_path.exists() && !key_path.exists() {
+        println!("Generating self-signed certificate {cert_path:?}...");
+        let hostname = fs::read_to_string("/etc/hostname")
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default();
+        let names = [hostname, "localhost".to_string()]
+            .into_iter()
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        let cert = rcgen::generate_simple_self_signed(names)?;
+        fs::write(cert_path, cert.serialize_pem()?)?;
+        write_private_file(key_path, &cert.serialize_private_key_pem())?;
+    }
+
+    let mut reader = BufReader::new(
+        fs::File::open(cert_path)
+            .map_err(|err| format_err!("Opening certificate {cert_path:?} failed: {err}"))?,
+    );
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let Some(end_entity) = certs.first() else {
+        bail!("No certificate found in {cert_path:?}.");
+    };
+
+    let mut reader = BufReader::new(
+        fs::File::open(key_path)
+            .map_err(|err| format_err!("Opening private key {key_path:?} failed: {err}"))?,
+    );
+    let key = rustls_pemfile::read_all(&mut reader)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format_err!("No private key found in {key_path:?}."))?;
+
+    println!(
+        "Certificate fingerprint (SHA256): {}",
+        cert_fingerprint(end_entity)
+    );
+
+    Ok(ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?)
+}
+
+fn write_private_file(path: &Path, content: &str) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+/// Formats the fingerprint as colon separated hex bytes, as accepted by '--cert-fingerprint'.
+fn cert_fingerprint(cert: &Certificate) -> String {
+    Sha256::digest(&cert.0)
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proxmox_auto_installer::testing::TempDir;
+    use serde_json::json;
+
+    #[test]
+    fn match_rules() {
+        let dir = TempDir::new("serve-rules");
+        for file in ["node1.toml", "lab.toml", "default.toml"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let rules = parse_rules(
+            r#"
+            [[rule]]
+            answer = "node1.toml"
+            [rule.match]
+            serial = "cz1234*"
+
+            [[rule]]
+            answer = "lab.toml"
+            [rule.match]
+            mac = "52:54:00:*"
+            product = "Standard PC*"
+
+            [[rule]]
+            answer = "default.toml"
+            "#,
+            &dir,
+        )
+        .unwrap();
+
+        let find = |sysinfo: Value| {
+            let host = HostProperties::from_sysinfo(&sysinfo);
+            let rule = rules.iter().find(|rule| rule.matches(&host)).unwrap();
+            rule.answer
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        assert_eq!(
+            find(json!({"dmi": {"system": {"serial": "CZ12345678"}}})),
+            "node1.toml"
+        );
+        assert_eq!(
+            find(json!({
+                "dmi": {"system": {"name": "Standard PC (Q35 + ICH9, 2009)"}},
+                "network_interfaces": [
+                    {"link": "eth0", "mac": "bc:24:11:00:00:01"},
+                    {"link": "eth1", "mac": "52:54:00:12:34:56"},
+                ],
+            })),
+            "lab.toml"
+        );
+        assert_eq!(
+            find(json!({"dmi": {"system": {"name": "Standard PC"}}})),
+            "default.toml"
+        );
+
+        assert!(parse_rules("[[rule]]\nanswer = \"missing.toml\"", &dir).is_err());
+    }
+}
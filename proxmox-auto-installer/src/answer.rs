@@ -16,6 +16,20 @@ pub struct Answer {
     pub network: Network,
     #[serde(rename = "disk-setup")]
     pub disks: Disks,
+    #[serde(default)]
+    pub extra_files: Vec<ExtraFile>,
+}
+
+/// A file from the extra files directory of the ISO, to be copied into the installed system.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraFile {
+    /// Path relative to the extra files directory of the ISO
+    pub source: String,
+    /// Absolute path in the installed system
+    pub target: String,
+    /// Octal file mode, defaults to '0644'
+    pub mode: Option<String>,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -272,7 +286,7 @@ pub struct BtrfsOptions {
     pub raid: Option<BtrfsRaidLevel>,
 }
 
-#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, ValueEnum)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub enum KeyboardLayout {
     De,
@@ -3,7 +3,7 @@ use log::{error, info, LevelFilter};
 use std::{
     env,
     io::{BufRead, BufReader, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::ExitCode,
 };
 
@@ -14,6 +14,7 @@ use proxmox_installer_common::setup::{
 use proxmox_auto_installer::{
     answer::Answer,
     log::AutoInstLogger,
+    template,
     udevinfo::UdevInfo,
     utils::{parse_answer, LowLevelMessage},
 };
@@ -49,6 +50,16 @@ fn auto_installer_setup(in_test_mode: bool) -> Result<(Answer, UdevInfo)> {
         buffer.push('\n');
     }
 
+    if template::is_template(&buffer) {
+        info!("Rendering answer file template");
+        let context = template::local_context()
+            .map_err(|err| format_err!("Failed to gather system information: {err}"))?;
+        // the mount points of the answer partition and the ISO
+        let lookup_dirs = [PathBuf::from("/mnt/answer"), PathBuf::from("/cdrom")];
+        buffer = template::render(&buffer, &context, &lookup_dirs)
+            .map_err(|err| format_err!("Failed rendering answer file template: {err}"))?;
+    }
+
     let answer: Answer =
         toml::from_str(&buffer).map_err(|err| format_err!("Failed parsing answer file: {err}"))?;
 
@@ -114,6 +125,11 @@ fn run_installation(
     setup_info: &SetupInfo,
 ) -> Result<()> {
     let config = parse_answer(answer, udevadm_info, runtime_info, locales, setup_info)?;
+    for file in &config.extra_files {
+        if !Path::new(&file.source).is_file() {
+            bail!("Extra file '{}' not found on the ISO", file.source);
+        }
+    }
     info!("Calling low-level installer");
 
     let mut child = match spawn_low_level_installer(false) {
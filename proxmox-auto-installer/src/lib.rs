@@ -1,5 +1,9 @@
 pub mod answer;
 pub mod log;
 pub mod sysinfo;
+pub mod sysroot;
+pub mod template;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod udevinfo;
 pub mod utils;
@@ -1,14 +1,27 @@
 use anyhow::{bail, Result};
 use proxmox_installer_common::{
-    setup::{IsoInfo, ProductConfig, SetupInfo},
+    setup::{BootType, IsoInfo, ProductConfig, SetupInfo},
     RUNTIME_DIR,
 };
 use serde::Serialize;
-use std::{collections::HashMap, fs, io, path::PathBuf};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::Path,
+};
 
-use crate::utils::get_nic_list;
+use crate::sysroot::SysRoot;
 
 const DMI_PATH: &str = "/sys/devices/virtual/dmi/id";
+const CPU_PATH: &str = "/sys/devices/system/cpu";
+const BLOCK_PATH: &str = "/sys/block";
+const NET_PATH: &str = "/sys/class/net";
+const PCI_PATH: &str = "/sys/bus/pci/devices";
+const EFI_PATH: &str = "/sys/firmware/efi";
+const SECURE_BOOT_VAR: &str =
+    "/sys/firmware/efi/efivars/SecureBoot-8be4df61-93ca-11d2-aa0d-00e098032b8c";
+const CPUINFO_PATH: &str = "/proc/cpuinfo";
+const MEMINFO_PATH: &str = "/proc/meminfo";
 
 #[derive(Debug, Serialize)]
 pub struct SysInfo {
@@ -16,11 +29,23 @@ pub struct SysInfo {
     iso: IsoInfo,
     dmi: SystemDMI,
     network_interfaces: Vec<NetdevWithMac>,
+    cpu: CpuInfo,
+    /// Total memory of the system in MiB.
+    total_memory: usize,
+    boot_type: BootType,
+    secure_boot: bool,
+    disks: Vec<DiskInfo>,
+    pci_devices: Vec<PciDevice>,
 }
 
 impl SysInfo {
     pub fn get() -> Result<Self> {
-        let path = PathBuf::from(RUNTIME_DIR).join("iso-info.json").to_owned();
+        Self::get_from(&SysRoot::host())
+    }
+
+    /// Gathers the system information below the given root, e.g. a fixture directory tree.
+    pub fn get_from(root: &SysRoot) -> Result<Self> {
+        let path = root.path(RUNTIME_DIR).join("iso-info.json");
         let setup_info: SetupInfo = match fs::File::open(path) {
             Ok(iso_info_file) => {
                 let reader = io::BufReader::new(iso_info_file);
@@ -33,8 +58,14 @@ impl SysInfo {
         Ok(Self {
             product: setup_info.config,
             iso: setup_info.iso_info,
-            network_interfaces: NetdevWithMac::get_all()?,
-            dmi: SystemDMI::get()?,
+            network_interfaces: NetdevWithMac::get_all(root)?,
+            dmi: SystemDMI::get(root)?,
+            cpu: CpuInfo::get(root)?,
+            total_memory: get_total_memory(root)?,
+            boot_type: get_boot_type(root),
+            secure_boot: get_secure_boot(root),
+            disks: DiskInfo::get_all(root)?,
+            pci_devices: PciDevice::get_all(root)?,
         })
     }
 
@@ -47,6 +78,21 @@ impl SysInfo {
         let info = Self::get()?;
         Ok(serde_json::to_string(&info)?)
     }
+
+    /// Returns the MAC addresses of all network interfaces.
+    pub fn macs(&self) -> impl Iterator<Item = &str> {
+        self.network_interfaces.iter().map(|nic| nic.mac.as_str())
+    }
+
+    /// Returns the system serial number from the DMI, if available.
+    pub fn product_serial(&self) -> Option<&str> {
+        self.dmi.system.get("serial").map(String::as_str)
+    }
+
+    /// Returns the system UUID from the DMI, if available.
+    pub fn product_uuid(&self) -> Option<&str> {
+        self.dmi.system.get("uuid").map(String::as_str)
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -55,22 +101,253 @@ struct NetdevWithMac {
     pub link: String,
     /// The MAC address of the network device
     pub mac: String,
+    /// The operational state of the link, e.g. 'up' or 'down'
+    pub state: Option<String>,
+    /// The link speed in Mbit/s, only known if the link is up
+    pub speed: Option<u32>,
+    /// The kernel driver of the network device
+    pub driver: Option<String>,
 }
 
 impl NetdevWithMac {
-    fn get_all() -> Result<Vec<Self>> {
+    fn get_all(root: &SysRoot) -> Result<Vec<Self>> {
         let mut result: Vec<Self> = Vec::new();
 
-        let links = get_nic_list()?;
+        let links = root.nic_list()?;
         for link in links {
-            let mac = fs::read_to_string(format!("/sys/class/net/{link}/address"))?;
+            let path = root.path(NET_PATH).join(&link);
+            let mac = fs::read_to_string(path.join("address"))?;
             let mac = String::from(mac.trim());
-            result.push(Self { link, mac });
+            result.push(Self {
+                state: read_sysfs_string(path.join("operstate")),
+                // reading the speed fails with EINVAL if the link is down, -1 means unknown
+                speed: read_sysfs_string(path.join("speed")).and_then(|speed| speed.parse().ok()),
+                driver: read_link_name(path.join("device/driver")),
+                link,
+                mac,
+            });
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct CpuInfo {
+    /// The model name of the first CPU
+    model: Option<String>,
+    /// Number of physical sockets
+    sockets: usize,
+    /// Number of physical cores, over all sockets
+    cores: usize,
+    /// Number of logical CPUs (threads), over all sockets
+    threads: usize,
+}
+
+impl CpuInfo {
+    fn get(root: &SysRoot) -> Result<Self> {
+        let model = fs::read_to_string(root.path(CPUINFO_PATH))
+            .ok()
+            .and_then(|cpuinfo| parse_cpu_model(&cpuinfo));
+
+        let mut sockets = BTreeSet::new();
+        let mut cores = BTreeSet::new();
+        let mut threads = 0;
+        for entry in fs::read_dir(root.path(CPU_PATH))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let is_cpu = name
+                .strip_prefix("cpu")
+                .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()));
+            // offline CPUs have no topology information
+            let topology = entry.path().join("topology");
+            if !is_cpu || !topology.exists() {
+                continue;
+            }
+
+            let package = read_sysfs_string(topology.join("physical_package_id"));
+            let core = read_sysfs_string(topology.join("core_id"));
+            sockets.insert(package.clone());
+            cores.insert((package, core));
+            threads += 1;
+        }
+
+        Ok(Self {
+            model,
+            sockets: sockets.len(),
+            cores: cores.len(),
+            threads,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DiskInfo {
+    /// The kernel name of the disk, e.g. 'sda' or 'nvme0n1'
+    name: String,
+    /// Size of the disk in bytes
+    size: u64,
+    model: Option<String>,
+    serial: Option<String>,
+    /// Whether the disk is a rotational one (HDD)
+    rotational: bool,
+    /// The physical block size in bytes, which the ZFS ashift should match
+    block_size: Option<u64>,
+}
+
+impl DiskInfo {
+    fn get_all(root: &SysRoot) -> Result<Vec<Self>> {
+        let mut result = Vec::new();
+        for entry in fs::read_dir(root.path(BLOCK_PATH))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path();
+
+            // only physical disks have a device, this skips loop, ram, zram and dm devices
+            let device = path.join("device");
+            if !device.exists() || name.starts_with("sr") {
+                continue;
+            }
+
+            // the size is always in 512 byte sectors
+            let sectors: u64 = read_sysfs_string(path.join("size"))
+                .and_then(|size| size.parse().ok())
+                .unwrap_or(0);
+            let serial = read_sysfs_string(device.join("serial")).or_else(|| {
+                fs::read(device.join("vpd_pg80"))
+                    .ok()
+                    .and_then(|vpd| parse_vpd_serial(&vpd))
+            });
+
+            result.push(Self {
+                size: sectors * 512,
+                model: read_sysfs_string(device.join("model")),
+                serial,
+                rotational: read_sysfs_string(path.join("queue/rotational")).as_deref()
+                    == Some("1"),
+                block_size: read_sysfs_string(path.join("queue/physical_block_size"))
+                    .and_then(|size| size.parse().ok()),
+                name,
+            });
+        }
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PciDevice {
+    /// The PCI address, e.g. '0000:00:1f.2'
+    slot: String,
+    class: Option<String>,
+    vendor: Option<String>,
+    device: Option<String>,
+    subsystem_vendor: Option<String>,
+    subsystem_device: Option<String>,
+    /// The kernel driver bound to the device
+    driver: Option<String>,
+}
+
+impl PciDevice {
+    fn get_all(root: &SysRoot) -> Result<Vec<Self>> {
+        let entries = match fs::read_dir(root.path(PCI_PATH)) {
+            Ok(entries) => entries,
+            // e.g. virtualized systems without PCI
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => bail!("failed to read '{PCI_PATH}' - {err}"),
+        };
+
+        let mut result = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            // IDs are in the '0x8086' format
+            let id = |file: &str| {
+                read_sysfs_string(path.join(file)).map(|id| id.trim_start_matches("0x").to_string())
+            };
+            result.push(Self {
+                slot: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                class: id("class"),
+                vendor: id("vendor"),
+                device: id("device"),
+                subsystem_vendor: id("subsystem_vendor"),
+                subsystem_device: id("subsystem_device"),
+                driver: read_link_name(path.join("driver")),
+            });
         }
+        result.sort_by(|a, b| a.slot.cmp(&b.slot));
         Ok(result)
     }
 }
 
+/// Returns the total memory of the system in MiB.
+fn get_total_memory(root: &SysRoot) -> Result<usize> {
+    let meminfo = fs::read_to_string(root.path(MEMINFO_PATH))?;
+    match parse_total_memory(&meminfo) {
+        Some(memory) => Ok(memory),
+        None => bail!("could not find total memory in /proc/meminfo"),
+    }
+}
+
+fn get_boot_type(root: &SysRoot) -> BootType {
+    if root.path(EFI_PATH).is_dir() {
+        BootType::Efi
+    } else {
+        BootType::Bios
+    }
+}
+
+fn get_secure_boot(root: &SysRoot) -> bool {
+    // the first four bytes are the attributes of the EFI variable
+    fs::read(root.path(SECURE_BOOT_VAR)).is_ok_and(|content| content.get(4) == Some(&1))
+}
+
+fn parse_cpu_model(cpuinfo: &str) -> Option<String> {
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        // 'model name' on x86, 'Model' on some other architectures
+        match key.trim() {
+            "model name" | "Model" => Some(value.trim().to_string()),
+            _ => None,
+        }
+    })
+}
+
+fn parse_total_memory(meminfo: &str) -> Option<usize> {
+    let line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let kib: usize = line
+        .trim_start_matches("MemTotal:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib / 1024)
+}
+
+/// Parses the unit serial number from the SCSI VPD page 0x80.
+fn parse_vpd_serial(vpd: &[u8]) -> Option<String> {
+    let len = *vpd.get(3)? as usize;
+    let serial = String::from_utf8_lossy(vpd.get(4..4 + len)?);
+    let serial = serial.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    (!serial.is_empty()).then(|| serial.to_string())
+}
+
+/// Reads a sysfs attribute, returning `None` if it does not exist, cannot be read or is empty.
+fn read_sysfs_string(path: impl AsRef<Path>) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let content = content.trim();
+    (!content.is_empty()).then(|| content.to_string())
+}
+
+/// Returns the file name of the target of a symlink, e.g. of the `driver` link of a device.
+fn read_link_name(path: impl AsRef<Path>) -> Option<String> {
+    let target = fs::read_link(path).ok()?;
+    Some(target.file_name()?.to_string_lossy().to_string())
+}
+
 #[derive(Debug, Serialize)]
 struct SystemDMI {
     system: HashMap<String, String>,
@@ -79,7 +356,7 @@ struct SystemDMI {
 }
 
 impl SystemDMI {
-    pub(crate) fn get() -> Result<Self> {
+    pub(crate) fn get(root: &SysRoot) -> Result<Self> {
         let system_files = [
             "product_serial",
             "product_sku",
@@ -89,25 +366,21 @@ impl SystemDMI {
         let baseboard_files = ["board_asset_tag", "board_serial", "board_name"];
         let chassis_files = ["chassis_serial", "chassis_sku", "chassis_asset_tag"];
 
+        let dmi_path = root.path(DMI_PATH);
         Ok(Self {
-            system: Self::get_dmi_infos(&system_files)?,
-            baseboard: Self::get_dmi_infos(&baseboard_files)?,
-            chassis: Self::get_dmi_infos(&chassis_files)?,
+            system: Self::get_dmi_infos(&dmi_path, &system_files)?,
+            baseboard: Self::get_dmi_infos(&dmi_path, &baseboard_files)?,
+            chassis: Self::get_dmi_infos(&dmi_path, &chassis_files)?,
         })
     }
 
-    fn get_dmi_infos(files: &[&str]) -> Result<HashMap<String, String>> {
+    fn get_dmi_infos(dmi_path: &Path, files: &[&str]) -> Result<HashMap<String, String>> {
         let mut res: HashMap<String, String> = HashMap::new();
 
         for file in files {
-            let path = format!("{DMI_PATH}/{file}");
-            let content = match fs::read_to_string(&path) {
-                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => continue,
-                Err(ref err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
-                    bail!("Could not read data. Are you running as root or with sudo?")
-                }
-                Err(err) => bail!("Error: '{err}' on '{path}'"),
-                Ok(content) => content.trim().into(),
+            let path = dmi_path.join(file);
+            let Some(content) = Self::dmi_content(&path, fs::read_to_string(&path))? else {
+                continue;
             };
             let key = file.splitn(2, '_').last().unwrap();
             res.insert(key.into(), content);
@@ -115,4 +388,61 @@ impl SystemDMI {
 
         Ok(res)
     }
+
+    /// Maps the result of reading a DMI file, missing files are skipped.
+    fn dmi_content(path: &Path, content: io::Result<String>) -> Result<Option<String>> {
+        match content {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                bail!("Could not read data. Are you running as root or with sudo?")
+            }
+            Err(err) => bail!("Error: '{err}' on '{}'", path.display()),
+            Ok(content) => Ok(Some(content.trim().into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_proc_files() {
+        let cpuinfo = "processor\t: 0\n\
+                       vendor_id\t: GenuineIntel\n\
+                       model name\t: Intel(R) Xeon(R) Silver 4310 CPU @ 2.10GHz\n\
+                       flags\t\t: fpu vme vmx\n";
+        assert_eq!(
+            parse_cpu_model(cpuinfo).as_deref(),
+            Some("Intel(R) Xeon(R) Silver 4310 CPU @ 2.10GHz")
+        );
+        assert_eq!(parse_cpu_model("processor\t: 0\n"), None);
+
+        let meminfo = "MemTotal:       65536000 kB\nMemFree:        1024 kB\n";
+        assert_eq!(parse_total_memory(meminfo), Some(64000));
+        assert_eq!(parse_total_memory("MemFree: 1024 kB\n"), None);
+
+        let vpd = [
+            0x00, 0x80, 0x00, 0x0a, b' ', b' ', b'Z', b'A', b'1', b'2', b'3', b'4', 0, 0,
+        ];
+        assert_eq!(parse_vpd_serial(&vpd).as_deref(), Some("ZA1234"));
+        assert_eq!(parse_vpd_serial(&[0x00, 0x80, 0x00, 0x10]), None);
+    }
+
+    #[test]
+    fn dmi_read_errors() {
+        let path = Path::new("/sys/devices/virtual/dmi/id/product_serial");
+        let content = |result| SystemDMI::dmi_content(path, result);
+
+        assert_eq!(
+            content(Ok("CZ1234\n".to_string())).unwrap().as_deref(),
+            Some("CZ1234")
+        );
+        assert_eq!(content(Err(io::ErrorKind::NotFound.into())).unwrap(), None);
+
+        let err = content(Err(io::ErrorKind::PermissionDenied.into())).unwrap_err();
+        assert!(err.to_string().contains("running as root"));
+        let err = content(Err(io::ErrorKind::InvalidData.into())).unwrap_err();
+        assert!(err.to_string().contains("product_serial"));
+    }
 }
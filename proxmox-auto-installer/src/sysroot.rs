@@ -0,0 +1,280 @@
+//! Access to the system paths used for hardware discovery.
+//!
+//! All reads of `/sys`, `/proc` and the udev database go through [`SysRoot`], so that the
+//! discovery can run against a fixture directory tree instead of the real host. For such a tree,
+//! the network interfaces are taken from `sys/class/net` instead of `ip link`.
+
+use anyhow::{bail, format_err, Result};
+use glob::Pattern;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The udev database, with one file per device.
+const UDEV_DATA_PATH: &str = "/run/udev/data";
+
+/// Block devices that are never considered as disks.
+const UNWANTED_BLOCK_DEVS: &[&str] = &[
+    "ram[0-9]*",
+    "loop[0-9]*",
+    "md[0-9]*",
+    "dm-*",
+    "fd[0-9]*",
+    "sr[0-9]*",
+];
+
+#[derive(Clone, Debug, Deserialize)]
+struct IpLinksUdevInfo {
+    ifname: String,
+}
+
+/// The root directory system information is read from, `/` for the running host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SysRoot {
+    root: PathBuf,
+}
+
+impl Default for SysRoot {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+impl SysRoot {
+    /// The root of the running host.
+    pub fn host() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+        }
+    }
+
+    /// A fixture directory tree, laid out like the root file system of a host.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Whether this is the root of the running host.
+    pub fn is_host(&self) -> bool {
+        self.root == Path::new("/")
+    }
+
+    /// Returns the absolute `path` relative to the root, e.g. `/sys/block`.
+    pub fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let path = path.as_ref();
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// Returns the names of all network interfaces, except the loopback one.
+    pub fn nic_list(&self) -> Result<Vec<String>> {
+        let links = if self.is_host() {
+            let ip_output = Command::new("/usr/sbin/ip")
+                .arg("-j")
+                .arg("link")
+                .output()?;
+            let parsed_links: Vec<IpLinksUdevInfo> = serde_json::from_slice(&ip_output.stdout)?;
+            parsed_links.into_iter().map(|link| link.ifname).collect()
+        } else {
+            let mut links = Vec::new();
+            for entry in fs::read_dir(self.path("/sys/class/net"))? {
+                links.push(entry?.file_name().to_string_lossy().to_string());
+            }
+            links.sort();
+            links
+        };
+
+        Ok(links.into_iter().filter(|link| link != "lo").collect())
+    }
+
+    /// Returns the udev properties of the device at the sysfs `path`, like `udevadm info` would.
+    ///
+    /// The properties are read from the kernel `uevent` file and the udev database. Only if the
+    /// database is not available, `udevadm` is called on the host.
+    pub fn udev_properties(&self, path: &Path) -> Result<BTreeMap<String, String>> {
+        let udev_data = self.path(UDEV_DATA_PATH);
+        if udev_data.is_dir() {
+            self.read_udev_properties(&udev_data, path)
+        } else if self.is_host() {
+            udevadm_properties(path)
+        } else {
+            bail!("udev database {udev_data:?} not found")
+        }
+    }
+
+    fn read_udev_properties(
+        &self,
+        udev_data: &Path,
+        path: &Path,
+    ) -> Result<BTreeMap<String, String>> {
+        let uevent_path = path.join("uevent");
+        let uevent = fs::read_to_string(&uevent_path)
+            .map_err(|err| format_err!("could not read {uevent_path:?} - {err}"))?;
+
+        let mut props: BTreeMap<String, String> = uevent
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        if let Some(name) = props.get_mut("DEVNAME") {
+            if !name.starts_with('/') {
+                *name = format!("/dev/{name}");
+            }
+        }
+        let sys_path = fs::canonicalize(self.path("/sys"))?;
+        if let Ok(devpath) = fs::canonicalize(path)?.strip_prefix(sys_path) {
+            props.insert("DEVPATH".into(), format!("/{}", devpath.display()));
+        }
+        let subsystem = fs::read_link(path.join("subsystem"))
+            .ok()
+            .and_then(|link| Some(link.file_name()?.to_string_lossy().to_string()));
+        if let Some(subsystem) = &subsystem {
+            props.insert("SUBSYSTEM".into(), subsystem.clone());
+        }
+
+        // the database files are named after the device id, e.g. 'b8:0' or 'n2'
+        let id = match (props.get("MAJOR"), props.get("MINOR"), props.get("IFINDEX")) {
+            (Some(major), Some(minor), _) => {
+                let kind = if subsystem.as_deref() == Some("block") {
+                    'b'
+                } else {
+                    'c'
+                };
+                format!("{kind}{major}:{minor}")
+            }
+            (_, _, Some(ifindex)) => format!("n{ifindex}"),
+            _ => bail!("could not determine udev id of {path:?}"),
+        };
+
+        // devices not (yet) processed by udev only have the kernel properties
+        let data = match fs::read_to_string(udev_data.join(&id)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(props),
+            Err(err) => bail!("could not read udev data of {path:?} - {err}"),
+        };
+
+        let mut links = Vec::new();
+        let mut tags = Vec::new();
+        let mut current_tags = Vec::new();
+        for line in data.lines() {
+            match line.split_once(':') {
+                Some(("E", property)) => {
+                    if let Some((key, value)) = property.split_once('=') {
+                        props.insert(key.to_string(), value.to_string());
+                    }
+                }
+                Some(("S", link)) => links.push(format!("/dev/{link}")),
+                Some(("G", tag)) => tags.push(tag),
+                Some(("Q", tag)) => current_tags.push(tag),
+                Some(("I", usec)) => {
+                    props.insert("USEC_INITIALIZED".into(), usec.to_string());
+                }
+                _ => {}
+            }
+        }
+        if !links.is_empty() {
+            props.insert("DEVLINKS".into(), links.join(" "));
+        }
+        if !tags.is_empty() {
+            props.insert("TAGS".into(), format!(":{}:", tags.join(":")));
+        }
+        if !current_tags.is_empty() {
+            props.insert(
+                "CURRENT_TAGS".into(),
+                format!(":{}:", current_tags.join(":")),
+            );
+        }
+
+        Ok(props)
+    }
+
+    /// Returns the udev properties of all disks, keyed by their device name.
+    ///
+    /// RAM, loop, MD, device mapper, floppy and CD-ROM devices are skipped, as well as devices
+    /// with an ISO9660 file system, like the installation medium.
+    pub fn udev_disks(&self) -> Result<BTreeMap<String, BTreeMap<String, String>>> {
+        let unwanted_block_devs = UNWANTED_BLOCK_DEVS
+            .iter()
+            .map(|pattern| Pattern::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut disks: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+
+        for entry in fs::read_dir(self.path("/sys/block"))? {
+            let entry = entry?;
+            let filename = entry.file_name().to_string_lossy().to_string();
+
+            if unwanted_block_devs
+                .iter()
+                .any(|pattern| pattern.matches(&filename))
+            {
+                continue;
+            }
+
+            let props = match self.udev_properties(&entry.path()) {
+                Ok(props) => props,
+                Err(err) => {
+                    eprintln!("{err}");
+                    continue;
+                }
+            };
+
+            if props.get("DEVTYPE").map(String::as_str) != Some("disk")
+                || props.keys().any(|key| key.starts_with("ID_CDROM"))
+                || props.get("ID_FS_TYPE").map(String::as_str) == Some("iso9660")
+            {
+                continue;
+            }
+
+            let name = props
+                .get("DEVNAME")
+                .and_then(|name| name.strip_prefix("/dev/"))
+                .map(String::from)
+                .unwrap_or(filename);
+
+            disks.insert(name, props);
+        }
+        Ok(disks)
+    }
+
+    /// Returns the udev properties of all network interfaces, keyed by their name.
+    pub fn udev_nics(&self) -> Result<BTreeMap<String, BTreeMap<String, String>>> {
+        let mut nics: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+
+        for link in self.nic_list()? {
+            let path = self.path("/sys/class/net").join(&link);
+
+            match self.udev_properties(&path) {
+                Ok(props) => nics.insert(link, props),
+                Err(err) => {
+                    eprintln!("{err}");
+                    continue;
+                }
+            };
+        }
+        Ok(nics)
+    }
+}
+
+/// Queries the udev properties of the device at the sysfs `path` with `udevadm`.
+fn udevadm_properties(path: &Path) -> Result<BTreeMap<String, String>> {
+    let udev_output = Command::new("udevadm")
+        .arg("info")
+        .arg("--path")
+        .arg(path)
+        .arg("--query")
+        .arg("property")
+        .output()?;
+    if !udev_output.status.success() {
+        bail!("could not run udevadm successfully for {path:?}");
+    }
+
+    Ok(String::from_utf8(udev_output.stdout)?
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect())
+}
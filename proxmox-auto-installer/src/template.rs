@@ -0,0 +1,400 @@
+//! Simple templating for answer files, to fill in values from the system information.
+//!
+//! Expressions are enclosed in `{{ ... }}` and can either be:
+//!
+//! * a path into the system information, e.g. `{{ dmi.system.serial }}` or
+//!   `{{ network_interfaces.0.mac }}`. Additionally, `mac` holds the list of all MAC addresses.
+//! * a lookup in a CSV file, e.g. `{{ lookup('hosts.csv', mac) }}` or
+//!   `{{ lookup('hosts.csv', dmi.system.serial, 2) }}`. The first column of the file is the key,
+//!   the value of the given column (default 1, the second one) of the first matching row is
+//!   returned. If the key is a list, like `mac`, the first of its values with a matching row is
+//!   used. Keys are compared case-insensitively.
+//!
+//! The rendered values are escaped to be used within TOML strings.
+
+use anyhow::{bail, format_err, Result};
+use serde_json::Value;
+use std::{fs, path::PathBuf};
+
+use crate::sysinfo::SysInfo;
+
+const EXPR_START: &str = "{{";
+const EXPR_END: &str = "}}";
+
+/// Builds the template context from the system information of the current host.
+pub fn local_context() -> Result<Value> {
+    Ok(sysinfo_context(serde_json::to_value(SysInfo::get()?)?))
+}
+
+/// Builds the template context from system information, as gathered by `SysInfo`.
+pub fn sysinfo_context(mut sysinfo: Value) -> Value {
+    let macs: Vec<Value> = sysinfo
+        .get("network_interfaces")
+        .and_then(Value::as_array)
+        .map(|nics| {
+            nics.iter()
+                .filter_map(|nic| nic.get("mac").cloned())
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(sysinfo) = sysinfo.as_object_mut() {
+        sysinfo.insert("mac".into(), Value::Array(macs));
+    }
+    sysinfo
+}
+
+/// Returns whether the text contains any template expressions.
+pub fn is_template(text: &str) -> bool {
+    text.contains(EXPR_START)
+}
+
+/// Checks the syntax of all template expressions, without rendering them.
+pub fn check(template: &str) -> Result<()> {
+    for_each_expr(template, |_| Ok(String::new())).map(|_| ())
+}
+
+/// Renders the template, looking up CSV files in the given directories in order.
+pub fn render(template: &str, context: &Value, lookup_dirs: &[PathBuf]) -> Result<String> {
+    for_each_expr(template, |expr| {
+        let value = eval(expr, context, lookup_dirs)?;
+        Ok(escape(&display(&value)?))
+    })
+}
+
+fn for_each_expr(
+    template: &str,
+    mut replace: impl FnMut(&Expr) -> Result<String>,
+) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(EXPR_START) {
+        output.push_str(&rest[..start]);
+        let offset = template.len() - rest.len() + start;
+        let line = template[..offset].matches('\n').count() + 1;
+
+        let after_start = &rest[start + EXPR_START.len()..];
+        let end = after_start
+            .find(EXPR_END)
+            .ok_or_else(|| format_err!("line {line}: unterminated template expression"))?;
+        let source = &after_start[..end];
+
+        let result = parse_expr(source).and_then(|expr| replace(&expr));
+        output.push_str(&result.map_err(|err| format_err!("line {line}: '{source}': {err}"))?);
+
+        rest = &after_start[end + EXPR_END.len()..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    String(String),
+    Number(usize),
+    Path(String),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    String(String),
+    Ident(String),
+    OpenParen,
+    CloseParen,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::OpenParen,
+                    ')' => Token::CloseParen,
+                    _ => Token::Comma,
+                });
+            }
+            '\'' | '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some(other) => value.push(other),
+                        None => bail!("unterminated string"),
+                    }
+                }
+                tokens.push(Token::String(value));
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => bail!("unexpected character '{c}'"),
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_expr(source: &str) -> Result<Expr> {
+    let tokens = tokenize(source)?;
+    let mut tokens = tokens.into_iter().peekable();
+    let expr = parse_tokens(&mut tokens)?;
+    if tokens.next().is_some() {
+        bail!("unexpected trailing input");
+    }
+    Ok(expr)
+}
+
+fn parse_tokens(tokens: &mut std::iter::Peekable<std::vec::IntoIter<Token>>) -> Result<Expr> {
+    match tokens.next() {
+        Some(Token::String(value)) => Ok(Expr::String(value)),
+        Some(Token::Ident(ident)) if tokens.peek() == Some(&Token::OpenParen) => {
+            tokens.next();
+            let mut args = Vec::new();
+            if tokens.peek() == Some(&Token::CloseParen) {
+                tokens.next();
+                return Ok(Expr::Call(ident, args));
+            }
+            loop {
+                args.push(parse_tokens(tokens)?);
+                match tokens.next() {
+                    Some(Token::Comma) => continue,
+                    Some(Token::CloseParen) => break,
+                    _ => bail!("expected ',' or ')' in arguments of '{ident}'"),
+                }
+            }
+            Ok(Expr::Call(ident, args))
+        }
+        Some(Token::Ident(ident)) => match ident.parse() {
+            Ok(number) => Ok(Expr::Number(number)),
+            Err(_) => Ok(Expr::Path(ident)),
+        },
+        Some(token) => bail!("unexpected {token:?}"),
+        None => bail!("empty expression"),
+    }
+}
+
+fn eval(expr: &Expr, context: &Value, lookup_dirs: &[PathBuf]) -> Result<Value> {
+    match expr {
+        Expr::String(value) => Ok(Value::String(value.clone())),
+        Expr::Number(number) => Ok(Value::from(*number)),
+        Expr::Path(path) => {
+            let mut value = context;
+            for key in path.split('.') {
+                let next = match value {
+                    Value::Array(list) => key.parse::<usize>().ok().and_then(|i| list.get(i)),
+                    Value::Object(map) => map.get(key),
+                    _ => None,
+                };
+                value = next.ok_or_else(|| format_err!("'{path}' is not defined"))?;
+            }
+            Ok(value.clone())
+        }
+        Expr::Call(name, args) if name == "lookup" => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, context, lookup_dirs))
+                .collect::<Result<Vec<_>>>()?;
+            lookup(&args, lookup_dirs).map(Value::String)
+        }
+        Expr::Call(name, _) => bail!("unknown function '{name}'"),
+    }
+}
+
+fn lookup(args: &[Value], lookup_dirs: &[PathBuf]) -> Result<String> {
+    let (file, key, column) = match args {
+        [Value::String(file), key] => (file, key, 1),
+        [Value::String(file), key, Value::Number(column)] => {
+            let column = column
+                .as_u64()
+                .ok_or_else(|| format_err!("invalid column '{column}'"))?;
+            (file, key, column as usize)
+        }
+        _ => bail!("expected lookup('<file>', <key>[, <column>])"),
+    };
+    if file.contains('/') {
+        bail!("lookup file '{file}' must not contain a path");
+    }
+
+    let path = lookup_dirs
+        .iter()
+        .map(|dir| dir.join(file))
+        .find(|path| path.is_file())
+        .ok_or_else(|| format_err!("lookup file '{file}' not found"))?;
+    let content = fs::read_to_string(&path)
+        .map_err(|err| format_err!("reading lookup file {path:?} failed - {err}"))?;
+
+    let keys = match key {
+        Value::Array(keys) => keys.iter().map(display).collect::<Result<Vec<_>>>()?,
+        key => vec![display(key)?],
+    };
+
+    let rows = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(split_csv_line)
+        .collect::<Result<Vec<_>>>()
+        .map_err(|err| format_err!("error parsing lookup file '{file}' - {err}"))?;
+
+    for key in &keys {
+        if let Some(row) = rows.iter().find(|row| row[0].eq_ignore_ascii_case(key)) {
+            return row
+                .get(column)
+                .map(|value| value.to_string())
+                .ok_or_else(|| {
+                    format_err!("row '{key}' in lookup file '{file}' has no column {column}")
+                });
+        }
+    }
+    bail!("no row for '{}' in lookup file '{file}'", keys.join("', '"));
+}
+
+/// Splits a line of a CSV file into its fields. Fields can be quoted with double quotes, to
+/// contain commas, where a quote itself is escaped by doubling it. Whitespace around fields is
+/// removed, but kept within quotes.
+pub fn split_csv_line(line: &str) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+
+        let mut field = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => bail!("unterminated quote in line '{line}'"),
+                }
+            }
+            while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+            if !matches!(chars.peek(), None | Some(',')) {
+                bail!("unexpected characters after quoted field in line '{line}'");
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                if c == '"' {
+                    bail!("unexpected quote in unquoted field in line '{line}'");
+                }
+                field.push(c);
+            }
+            field.truncate(field.trim_end().len());
+        }
+        fields.push(field);
+
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+fn display(value: &Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        Value::Null => bail!("value is not set"),
+        Value::Array(_) => bail!("value is a list"),
+        Value::Object(_) => bail!("value is an object"),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use serde_json::json;
+
+    fn context() -> Value {
+        sysinfo_context(json!({
+            "dmi": { "system": { "serial": "CZ1234", "name": "Standard \"PC\"" } },
+            "network_interfaces": [
+                { "link": "eno1", "mac": "bc:24:11:00:00:01" },
+                { "link": "eno2", "mac": "BC:24:11:00:00:02" },
+            ],
+        }))
+    }
+
+    #[test]
+    fn render_paths() {
+        let rendered = render(
+            "fqdn = \"pve-{{ dmi.system.serial }}.lab.example\"\n\
+             product = \"{{dmi.system.name}}\"\n\
+             mac = \"{{ network_interfaces.1.mac }}\"",
+            &context(),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            rendered,
+            "fqdn = \"pve-CZ1234.lab.example\"\n\
+             product = \"Standard \\\"PC\\\"\"\n\
+             mac = \"BC:24:11:00:00:02\""
+        );
+
+        assert!(render("{{ dmi.system.uuid }}", &context(), &[]).is_err());
+        assert!(render("{{ mac }}", &context(), &[]).is_err());
+        assert!(render("{{ dmi.system.serial ", &context(), &[]).is_err());
+        assert!(check("{{ lookup('hosts.csv', mac, 2) }} {{ foo( }}").is_err());
+    }
+
+    #[test]
+    fn csv_lines() {
+        assert_eq!(
+            split_csv_line(" a , \"b, \"\"c\"\"\" ,,\"\"").unwrap(),
+            ["a", "b, \"c\"", "", ""]
+        );
+        assert_eq!(split_csv_line("").unwrap(), [""]);
+        assert!(split_csv_line("a,\"b").is_err());
+        assert!(split_csv_line("a,\"b\"c").is_err());
+        assert!(split_csv_line("a,b\"c").is_err());
+    }
+
+    #[test]
+    fn render_lookup() {
+        let dir = TempDir::new("answer-template");
+        fs::write(
+            dir.join("hosts.csv"),
+            "# mac, cidr, hostname\n\
+             bc:24:11:00:00:02, 192.0.2.12/24, pve2\n\
+             CZ1234, 192.0.2.20/24, pve-serial\n",
+        )
+        .unwrap();
+        let dirs = [PathBuf::from("/nonexistent"), dir.to_path_buf()];
+
+        let render = |template| render(template, &context(), &dirs);
+        assert_eq!(
+            render("{{ lookup('hosts.csv', mac) }}").unwrap(),
+            "192.0.2.12/24"
+        );
+        assert_eq!(
+            render("{{ lookup(\"hosts.csv\", dmi.system.serial, 2) }}").unwrap(),
+            "pve-serial"
+        );
+        assert!(render("{{ lookup('hosts.csv', 'unknown') }}").is_err());
+        assert!(render("{{ lookup('missing.csv', mac) }}").is_err());
+        assert!(render("{{ lookup('../hosts.csv', mac) }}").is_err());
+    }
+}
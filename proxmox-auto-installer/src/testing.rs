@@ -0,0 +1,36 @@
+//! Helpers shared by the tests of the installer crates.
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A temporary directory for the files of a test. It is removed when dropped, also if the test
+/// fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates an empty directory, `name` needs to be unique among the tests of a crate.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path)
+            .unwrap_or_else(|err| panic!("creating directory {path:?} failed: {err}"));
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
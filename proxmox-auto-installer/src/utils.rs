@@ -2,20 +2,27 @@ use anyhow::{bail, Context as _, Result};
 use clap::ValueEnum;
 use glob::Pattern;
 use log::info;
-use std::{collections::BTreeMap, process::Command};
+use std::collections::BTreeMap;
 
 use crate::{
     answer::{self, Answer},
+    sysroot::SysRoot,
     udevinfo::UdevInfo,
 };
 use proxmox_installer_common::{
     options::{FsType, NetworkOptions, ZfsChecksumOption, ZfsCompressOption},
     setup::{
-        InstallConfig, InstallRootPassword, InstallZfsOption, LocaleInfo, RuntimeInfo, SetupInfo,
+        InstallConfig, InstallExtraFile, InstallRootPassword, InstallZfsOption, LocaleInfo,
+        RuntimeInfo, SetupInfo,
     },
 };
 use serde::{Deserialize, Serialize};
 
+/// Directory on the ISO holding the files added with `prepare-iso --add-file`.
+pub const ISO_EXTRA_FILES_DIR: &str = "/auto-installer-files";
+/// Mount point of the ISO in the installation environment.
+pub const ISO_MOUNT_POINT: &str = "/cdrom";
+
 pub fn get_network_settings(
     answer: &Answer,
     udev_info: &UdevInfo,
@@ -80,38 +87,100 @@ pub struct HttpOptions {
     pub url: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub cert_fingerprint: Option<String>,
+    /// PEM encoded client certificate (chain) to present to the answer server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    /// PEM encoded private key belonging to `client_cert`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    /// Additional HTTP headers to send with the request, e.g. for authorization.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// URL to report errors of invalid answer files to, together with the system information.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_report_url: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Parses a HTTP header in the format `Name: value`. As header names are case-insensitive, the
+/// name is returned in lowercase, so headers from different sources can be merged.
+pub fn parse_http_header(header: &str) -> Result<(String, String)> {
+    let (name, value) = match header.split_once(':') {
+        Some((name, value)) => (name.trim(), value.trim()),
+        None => bail!("missing ':' separator in HTTP header '{header}'"),
+    };
+    if name.is_empty() {
+        bail!("empty name in HTTP header '{header}'");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+    {
+        bail!("invalid character in HTTP header name '{name}'");
+    }
+    Ok((name.to_ascii_lowercase(), value.to_string()))
+}
+
+/// A single location to fetch the answer file from, together with its options.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase", deny_unknown_fields)]
-pub struct AutoInstSettings {
+pub struct AnswerSource {
     pub mode: FetchAnswerFrom,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "HttpOptions::is_default")]
     pub http: HttpOptions,
 }
 
-#[derive(Deserialize, Debug)]
-struct IpLinksUdevInfo {
-    ifname: String,
+impl HttpOptions {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
-/// Returns vec of usable NICs
-pub fn get_nic_list() -> Result<Vec<String>> {
-    let ip_output = Command::new("/usr/sbin/ip")
-        .arg("-j")
-        .arg("link")
-        .output()?;
-    let parsed_links: Vec<IpLinksUdevInfo> = serde_json::from_slice(&ip_output.stdout)?;
-    let mut links: Vec<String> = Vec::new();
-
-    for link in parsed_links {
-        if link.ifname == *"lo" {
-            continue;
+/// Settings for fetching the answer file, stored in the `auto-installer-mode.toml` file on the ISO.
+///
+/// The sources are tried in the order they are defined, until one of them provides an answer.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(try_from = "RawAutoInstSettings")]
+pub struct AutoInstSettings {
+    #[serde(rename = "source")]
+    pub sources: Vec<AnswerSource>,
+}
+
+/// Accepts both the list of sources and the older format with a single `mode` and `[http]`
+/// section.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+struct RawAutoInstSettings {
+    #[serde(default)]
+    source: Vec<AnswerSource>,
+    mode: Option<FetchAnswerFrom>,
+    http: Option<HttpOptions>,
+}
+
+impl TryFrom<RawAutoInstSettings> for AutoInstSettings {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawAutoInstSettings) -> Result<Self> {
+        let sources = match raw.mode {
+            Some(_) if !raw.source.is_empty() => {
+                bail!("cannot combine 'mode' with a list of sources")
+            }
+            Some(mode) => vec![AnswerSource {
+                mode,
+                http: raw.http.unwrap_or_default(),
+            }],
+            None if raw.http.is_some() => bail!("'http' section requires 'mode' to be set"),
+            None => raw.source,
+        };
+        if sources.is_empty() {
+            bail!("no answer source defined");
         }
-        links.push(link.ifname);
+        Ok(Self { sources })
     }
+}
 
-    Ok(links)
+/// Returns the names of all network interfaces of the host, except the loopback one.
+pub fn get_nic_list() -> Result<Vec<String>> {
+    SysRoot::host().nic_list()
 }
 
 pub fn get_matched_udev_indexes(
@@ -303,15 +372,127 @@ pub fn verify_locale_settings(answer: &Answer, locales: &LocaleInfo) -> Result<(
     Ok(())
 }
 
-fn verify_root_password_settings(answer: &Answer) -> Result<()> {
+pub fn verify_root_password_settings(answer: &Answer) -> Result<()> {
     if answer.global.root_password.is_some() && answer.global.root_password_hashed.is_some() {
         bail!("`global.root_password` and `global.root_password_hashed` cannot be set at the same time");
     } else if answer.global.root_password.is_none() && answer.global.root_password_hashed.is_none()
     {
         bail!("One of `global.root_password` or `global.root_password_hashed` must be set");
-    } else {
-        Ok(())
     }
+
+    if let Some(hash) = &answer.global.root_password_hashed {
+        if !is_valid_crypt_hash(hash) {
+            bail!(
+                "`global.root_password_hashed` is not a valid password hash, supported are \
+                 yescrypt, scrypt, bcrypt, SHA-512 and SHA-256 crypt hashes"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the hash is well-formed, in the crypt(5) format of a supported hashing method.
+/// A malformed hash would be set as is, locking out root from the installed system.
+fn is_valid_crypt_hash(hash: &str) -> bool {
+    let is_base64 = |value: &str| {
+        !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '/')
+    };
+
+    match hash.split('$').collect::<Vec<_>>().as_slice() {
+        // yescrypt and gost-yescrypt
+        ["", "y" | "gy", params, salt, hash] => {
+            is_base64(params) && is_base64(salt) && hash.len() == 43 && is_base64(hash)
+        }
+        // scrypt, the parameters are followed by the salt
+        ["", "7", setting, hash] => {
+            setting.len() > 11 && is_base64(setting) && hash.len() == 43 && is_base64(hash)
+        }
+        // bcrypt, the salt is followed by the hash
+        ["", "2a" | "2b" | "2y", cost, hash] => {
+            matches!(cost.parse::<u32>(), Ok(4..=31))
+                && cost.len() == 2
+                && hash.len() == 53
+                && is_base64(hash)
+        }
+        // SHA-256 and SHA-512 crypt, with optional rounds
+        ["", method @ ("5" | "6"), fields @ ..] => {
+            let fields = match fields {
+                [rounds, fields @ ..] if rounds.starts_with("rounds=") => {
+                    if rounds["rounds=".len()..].parse::<u32>().is_err() {
+                        return false;
+                    }
+                    fields
+                }
+                fields => fields,
+            };
+            let hash_len = if *method == "5" { 43 } else { 86 };
+            match fields {
+                [salt, hash] => {
+                    salt.len() <= 16
+                        && !salt.contains([':', '\n'])
+                        && hash.len() == hash_len
+                        && is_base64(hash)
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Whether the path is relative and names a file, without any empty, '.' or '..' components.
+pub fn is_relative_file_path(path: &str) -> bool {
+    !path.is_empty()
+        && path
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+/// Checks the extra files of the answer and resolves their location on the ISO.
+fn get_extra_files(answer: &Answer) -> Result<Vec<InstallExtraFile>> {
+    let mut files = Vec::new();
+    for file in &answer.extra_files {
+        if !is_relative_file_path(&file.source) {
+            bail!(
+                "extra file source '{}' must be a path relative to the extra files directory",
+                file.source
+            );
+        }
+
+        if !file
+            .target
+            .strip_prefix('/')
+            .is_some_and(is_relative_file_path)
+        {
+            bail!(
+                "extra file target '{}' must be an absolute file path",
+                file.target
+            );
+        }
+
+        let mode = file.mode.as_deref().unwrap_or("0644");
+        let mode = match u32::from_str_radix(mode, 8) {
+            Ok(value) if value <= 0o7777 && mode.bytes().all(|c| c.is_ascii_digit()) => value,
+            _ => bail!("invalid mode '{mode}' for extra file '{}'", file.target),
+        };
+
+        if files
+            .iter()
+            .any(|other: &InstallExtraFile| other.target == file.target)
+        {
+            bail!("extra file target '{}' is used more than once", file.target);
+        }
+
+        files.push(InstallExtraFile {
+            source: format!("{ISO_MOUNT_POINT}{ISO_EXTRA_FILES_DIR}/{}", file.source),
+            target: file.target.clone(),
+            mode: format!("{mode:04o}"),
+        });
+    }
+    Ok(files)
 }
 
 pub fn parse_answer(
@@ -354,6 +535,7 @@ pub fn parse_answer(
         },
         mailto: answer.global.mailto.clone(),
         root_ssh_keys: answer.global.root_ssh_keys.clone(),
+        extra_files: get_extra_files(answer)?,
 
         mngmt_nic: network_settings.ifname,
 
@@ -420,3 +602,120 @@ pub enum LowLevelMessage {
         text: String,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extra_files(files: &str) -> Result<Vec<InstallExtraFile>> {
+        let answer: Answer = toml::from_str(&format!(
+            "[global]\nkeyboard = \"de\"\ncountry = \"at\"\nfqdn = \"pve.example.com\"\n\
+             mailto = \"root@example.com\"\ntimezone = \"Europe/Vienna\"\n\
+             root_password = \"123456\"\n\n[network]\nsource = \"from-dhcp\"\n\n\
+             [disk-setup]\nfilesystem = \"ext4\"\ndisk_list = [\"sda\"]\n\n{files}"
+        ))
+        .unwrap();
+        get_extra_files(&answer)
+    }
+
+    fn extra_file(source: &str, target: &str, mode: &str) -> Result<Vec<InstallExtraFile>> {
+        extra_files(&format!(
+            "[[extra-files]]\nsource = \"{source}\"\ntarget = \"{target}\"\nmode = \"{mode}\"\n"
+        ))
+    }
+
+    #[test]
+    fn auto_inst_settings_formats() {
+        let parse = |raw: &str| toml::from_str::<AutoInstSettings>(raw);
+
+        // the format of ISOs prepared by older versions, with a single mode
+        let settings =
+            parse("mode = \"http\"\n\n[http]\nurl = \"https://pve.example.com/answer\"\n").unwrap();
+        assert_eq!(settings.sources.len(), 1);
+        assert_eq!(settings.sources[0].mode, FetchAnswerFrom::Http);
+        assert_eq!(
+            settings.sources[0].http.url.as_deref(),
+            Some("https://pve.example.com/answer")
+        );
+        let settings = parse("mode = \"iso\"\n").unwrap();
+        assert_eq!(settings.sources[0].mode, FetchAnswerFrom::Iso);
+        assert_eq!(settings.sources[0].http, HttpOptions::default());
+
+        let settings = parse(
+            "[[source]]\nmode = \"partition\"\n\n\
+             [[source]]\nmode = \"http\"\n\n[source.http]\nurl = \"https://pve.example.com\"\n",
+        )
+        .unwrap();
+        let modes: Vec<&FetchAnswerFrom> = settings.sources.iter().map(|s| &s.mode).collect();
+        assert_eq!(modes, [&FetchAnswerFrom::Partition, &FetchAnswerFrom::Http]);
+        assert_eq!(
+            settings.sources[1].http.url.as_deref(),
+            Some("https://pve.example.com")
+        );
+
+        // the current format round-trips
+        let serialized = toml::to_string_pretty(&settings).unwrap();
+        assert_eq!(parse(&serialized).unwrap().sources, settings.sources);
+
+        assert!(parse("mode = \"iso\"\n\n[[source]]\nmode = \"partition\"\n").is_err());
+        assert!(parse("[http]\nurl = \"https://pve.example.com\"\n").is_err());
+        assert!(parse("").is_err());
+        assert!(parse("mode = \"floppy\"\n").is_err());
+    }
+
+    #[test]
+    fn extra_files_are_checked() {
+        assert_eq!(
+            extra_files(
+                "[[extra-files]]\nsource = \"ssh/authorized_keys\"\n\
+                 target = \"/root/.ssh/authorized_keys\"\nmode = \"600\"\n\n\
+                 [[extra-files]]\nsource = \"motd\"\ntarget = \"/etc/motd\"\n"
+            )
+            .unwrap(),
+            [
+                InstallExtraFile {
+                    source: format!("{ISO_MOUNT_POINT}{ISO_EXTRA_FILES_DIR}/ssh/authorized_keys"),
+                    target: "/root/.ssh/authorized_keys".into(),
+                    mode: "0600".into(),
+                },
+                InstallExtraFile {
+                    source: format!("{ISO_MOUNT_POINT}{ISO_EXTRA_FILES_DIR}/motd"),
+                    target: "/etc/motd".into(),
+                    mode: "0644".into(),
+                },
+            ]
+        );
+        assert!(extra_file("motd", "/etc/motd", "4755").is_ok());
+
+        for source in [
+            "",
+            "../motd",
+            "ssh/../../motd",
+            "/etc/motd",
+            "./motd",
+            "ssh/",
+        ] {
+            assert!(extra_file(source, "/etc/motd", "0644").is_err(), "{source}");
+        }
+        for target in [
+            "",
+            "etc/motd",
+            "/etc/",
+            "/etc/.",
+            "/etc/../motd",
+            "/etc//motd",
+            "/",
+        ] {
+            assert!(extra_file("motd", target, "0644").is_err(), "{target}");
+        }
+        for mode in ["", "0800", "10000", "+644", "rw-r--r--"] {
+            assert!(extra_file("motd", "/etc/motd", mode).is_err(), "{mode}");
+        }
+
+        assert!(extra_files(
+            "[[extra-files]]\nsource = \"a\"\ntarget = \"/etc/motd\"\n\n\
+             [[extra-files]]\nsource = \"b\"\ntarget = \"/etc/motd\"\n"
+        )
+        .is_err());
+    }
+}
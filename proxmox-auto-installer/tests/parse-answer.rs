@@ -104,3 +104,36 @@ fn test_parse_answers() {
         }
     }
 }
+
+#[test]
+fn test_root_password_hashes() {
+    let path = get_test_resource_path().unwrap();
+    let (setup_info, locales, runtime_info, udev_info) = setup_test_basic(&path);
+    let mut answer = get_answer(path.join("parse_answer/hashed_root_password.toml")).unwrap();
+    let yescrypt = answer.global.root_password_hashed.clone().unwrap();
+
+    let valid = [
+        "$6$abcdefgh$fsQAa16ibv7N/pkIMTWAm9JGNGOIC4zzMpCqrfg2f6e5sTkTI49pdvKD4Wjy8PO6mzddXEf0oICL6PtgHim/F.",
+        "$6$rounds=10000$rP4M9WIKOtMpZFtw$IfOUFlEn0S83.R2B08Q66pQgiUHN7ut7BwXhMXaQY9xsrit4eX0OKgw7ZcH6Wmau99XsGLbSz2xeKoM9OrrUF0",
+        "$5$sc1CAgdhilRGJsZY$KR2hpKnkyK/UJe44aoxrilKqYLUucTCtGwSNzD.obA.",
+        "$2b$10$GVKFYxpYbxy7Pw6lJ12VFOUyuSufkQQHgLxCfehXaK96YgTfJZYba",
+    ];
+    let invalid = [
+        &yescrypt[..yescrypt.len() - 1],
+        &yescrypt[1..],
+        "$1$abcdefgh$Ou2ocgOUbKCUu4WJTFhUs/",
+        "$6$abcdefgh$fsQAa16ibv7N/pkIMTWAm9JGNGOIC4zzMpCqrfg2f6e5sTkTI49pdvKD4Wjy8PO6mzddXEf0oICL6PtgHim/F",
+        "$y$j9T$VgMv8lsz/TEvzesCZU3xD.$SK.h4QW51Jr/EmjuaTz5Bt4kYiX2Iezz6omzoqVEwj9 ",
+        "123456",
+    ];
+    for (hash, is_valid) in valid
+        .iter()
+        .chain([&yescrypt.as_str()])
+        .map(|hash| (hash, true))
+        .chain(invalid.iter().map(|hash| (hash, false)))
+    {
+        answer.global.root_password_hashed = Some(hash.to_string());
+        let result = parse_answer(&answer, &udev_info, &runtime_info, &locales, &setup_info);
+        assert_eq!(result.is_ok(), is_valid, "hash '{hash}'");
+    }
+}
@@ -0,0 +1,119 @@
+use serde_json::{json, Value};
+
+use proxmox_auto_installer::{sysinfo::SysInfo, sysroot::SysRoot};
+
+fn fixture() -> SysRoot {
+    SysRoot::new(
+        std::env::current_dir()
+            .expect("current dir failed")
+            .join("tests/resources/sysroot"),
+    )
+}
+
+#[test]
+fn test_udev_disks() {
+    let disks = fixture().udev_disks().unwrap();
+
+    // loop0, md0, dm-0 and sr0 are filtered by name, sdb holds the ISO and sdc is a CD-ROM drive
+    let names: Vec<&str> = disks.keys().map(String::as_str).collect();
+    assert_eq!(names, ["nvme0n1", "sda"]);
+    assert_eq!(disks["sda"]["ID_SERIAL_SHORT"], "ZFA1B2C3");
+    assert_eq!(disks["nvme0n1"]["ID_MODEL"], "Samsung SSD 980 PRO 500GB");
+
+    // the kernel properties are merged with the ones from the udev database
+    let sda = &disks["sda"];
+    assert_eq!(sda["DEVNAME"], "/dev/sda");
+    assert_eq!(sda["DEVPATH"], "/block/sda");
+    assert_eq!(sda["SUBSYSTEM"], "block");
+    assert_eq!(
+        sda["DEVLINKS"],
+        "/dev/disk/by-id/ata-ST1000NM0008-2F2100_ZFA1B2C3 /dev/disk/by-path/pci-0000:00:17.0-ata-1"
+    );
+    assert_eq!(sda["TAGS"], ":systemd:");
+}
+
+#[test]
+fn test_udev_nics() {
+    let nics = fixture().udev_nics().unwrap();
+
+    let names: Vec<&str> = nics.keys().map(String::as_str).collect();
+    assert_eq!(names, ["eno1", "enp1s0"]);
+    assert_eq!(nics["eno1"]["ID_NET_DRIVER"], "e1000e");
+    assert_eq!(nics["eno1"]["INTERFACE"], "eno1");
+    assert_eq!(nics["enp1s0"]["ID_NET_NAME_PATH"], "enp1s0");
+}
+
+#[test]
+fn test_sysinfo() {
+    let info = serde_json::to_value(SysInfo::get_from(&fixture()).unwrap()).unwrap();
+
+    assert_eq!(info["dmi"]["system"]["serial"], "CZ1234");
+    assert_eq!(info["dmi"]["baseboard"]["name"], "ProLiant");
+    assert_eq!(
+        info["cpu"],
+        json!({
+            "model": "Intel(R) Xeon(R) E-2224G CPU @ 3.50GHz",
+            "sockets": 1,
+            "cores": 2,
+            "threads": 4,
+        })
+    );
+    assert_eq!(info["total_memory"], 32000);
+    assert_eq!(info["boot_type"], "efi");
+    assert_eq!(info["secure_boot"], true);
+
+    assert_eq!(
+        info["network_interfaces"],
+        json!([
+            {
+                "link": "eno1",
+                "mac": "3c:ec:ef:00:11:22",
+                "state": "up",
+                "speed": 1000,
+                "driver": "e1000e",
+            },
+            {
+                "link": "enp1s0",
+                "mac": "3c:ec:ef:00:11:23",
+                "state": "down",
+                "speed": null,
+                "driver": "igb",
+            },
+        ])
+    );
+
+    // the inventory lists all physical block devices, except optical drives
+    let disks = info["disks"].as_array().unwrap();
+    let names: Vec<&str> = disks.iter().filter_map(|d| d["name"].as_str()).collect();
+    assert_eq!(names, ["nvme0n1", "sda", "sdb", "sdc"]);
+    // sda is a 512e disk, with a logical block size of 512 and a physical one of 4096 bytes
+    assert_eq!(
+        disks[1],
+        json!({
+            "name": "sda",
+            "size": 1_000_204_886_016_u64,
+            "model": "ST1000NM0008-2F2",
+            "serial": "ZFA1B2C3",
+            "rotational": true,
+            "block_size": 4096,
+        })
+    );
+    assert_eq!(disks[0]["serial"], "S5GXNF0R123456");
+    assert_eq!(disks[0]["rotational"], false);
+
+    assert_eq!(
+        info["pci_devices"],
+        json!([{
+            "slot": "0000:00:1f.6",
+            "class": "020000",
+            "vendor": "8086",
+            "device": "15bb",
+            "subsystem_vendor": "1590",
+            "subsystem_device": "00e3",
+            "driver": "e1000e",
+        }])
+    );
+
+    // without iso-info.json, the product information is mocked
+    assert_ne!(info["product"], Value::Null);
+}
@@ -0,0 +1,588 @@
+//! Parsers for the lease files of the supported DHCP clients, used to read the custom DHCP
+//! options pointing to the answer server.
+//!
+//! Supported are the lease files of dhclient, systemd-networkd and dhcpcd.
+
+use anyhow::{bail, format_err, Result};
+use log::info;
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static DHCLIENT_LEASE_DIR: &str = "/var/lib/dhcp";
+static NETWORKD_LEASE_DIR: &str = "/run/systemd/netif/leases";
+static DHCPCD_LEASE_DIR: &str = "/var/lib/dhcpcd";
+
+/// dhclient writes the options with the names defined in dhclient.conf, so map them back to
+/// their codes. Options without a name are written as `unknown-<code>`.
+const DHCLIENT_OPTION_NAMES: &[(&str, u8)] = &[
+    ("proxmox-auto-installer-manifest-url", 250),
+    ("proxmox-auto-installer-cert-fingerprint", 251),
+    ("proxmox-auto-installer-http-headers", 252),
+];
+
+const DHCP_OPTION_PAD: u8 = 0;
+const DHCP_OPTION_LEASE_TIME: u8 = 51;
+const DHCP_OPTION_END: u8 = 255;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+// size of the fixed BOOTP part of a DHCP message, followed by the magic cookie and the options
+const BOOTP_HEADER_SIZE: usize = 236;
+
+/// A single DHCP lease.
+#[derive(Debug, Default, PartialEq)]
+pub struct Lease {
+    pub interface: Option<String>,
+    /// Expiry of the lease as UNIX timestamp, if known.
+    pub expire: Option<i64>,
+    /// Raw option values by their option code.
+    pub options: HashMap<u8, Vec<u8>>,
+}
+
+impl Lease {
+    /// Returns the value of a text option, with trailing NUL bytes removed.
+    pub fn option_text(&self, code: u8) -> Option<String> {
+        let value = self.options.get(&code)?;
+        let value = String::from_utf8_lossy(value);
+        let value = value.trim_end_matches('\0').trim();
+        (!value.is_empty()).then(|| value.to_string())
+    }
+
+    fn is_valid(&self, now: i64) -> bool {
+        self.expire.map(|expire| expire > now).unwrap_or(true)
+    }
+}
+
+/// Reads the leases of all supported DHCP clients and returns the newest valid lease for each
+/// interface. Leases without a known interface are returned as well.
+pub fn find_leases() -> Vec<Lease> {
+    let mut leases = Vec::new();
+
+    for (client, dir, parse) in [
+        (
+            "dhclient",
+            DHCLIENT_LEASE_DIR,
+            parse_dhclient_file as fn(&Path) -> _,
+        ),
+        ("systemd-networkd", NETWORKD_LEASE_DIR, parse_networkd_file),
+        ("dhcpcd", DHCPCD_LEASE_DIR, parse_dhcpcd_file),
+    ] {
+        leases.extend(read_lease_dir(client, Path::new(dir), parse));
+    }
+
+    newest_valid_leases(leases, unix_now())
+}
+
+/// Reads the leases of all files in `dir`, together with the modification time of the file they
+/// were read from.
+fn read_lease_dir(
+    client: &str,
+    dir: &Path,
+    parse: fn(&Path) -> Result<Option<Vec<Lease>>>,
+) -> Vec<(Lease, i64)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .collect();
+    paths.sort();
+
+    let mut leases = Vec::new();
+    for path in paths {
+        match parse(&path) {
+            Ok(Some(found)) => {
+                info!("Found {} {client} lease(s) in {path:?}", found.len());
+                let written = modified_time(&path).unwrap_or(0);
+                leases.extend(found.into_iter().map(|lease| (lease, written)));
+            }
+            Ok(None) => {}
+            Err(err) => info!("Could not parse {client} lease file {path:?}: {err}"),
+        }
+    }
+    leases
+}
+
+/// Keeps only the newest valid lease per interface. Leases are compared by their expiry, or by
+/// the time their file was written if it is not known. On equal times, the later lease wins, as
+/// dhclient appends new leases to the end of its files.
+fn newest_valid_leases(leases: Vec<(Lease, i64)>, now: i64) -> Vec<Lease> {
+    let mut result: Vec<(Lease, i64)> = Vec::new();
+    for (lease, written) in leases.into_iter().filter(|(lease, _)| lease.is_valid(now)) {
+        let time = lease.expire.unwrap_or(written);
+        let existing = result
+            .iter()
+            .position(|(other, _)| lease.interface.is_some() && other.interface == lease.interface);
+        match existing {
+            Some(pos) if result[pos].1 <= time => result[pos] = (lease, time),
+            Some(_) => {}
+            None => result.push((lease, time)),
+        }
+    }
+    result.into_iter().map(|(lease, _)| lease).collect()
+}
+
+fn modified_time(path: &Path) -> Option<i64> {
+    let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+    let time = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some(time.as_secs() as i64)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn parse_dhclient_file(path: &Path) -> Result<Option<Vec<Lease>>> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if !name.starts_with("dhclient") || !name.ends_with(".leases") {
+        return Ok(None);
+    }
+    Ok(Some(parse_dhclient_leases(&fs::read_to_string(path)?)?))
+}
+
+/// Parses the leases of a dhclient lease file, in the order they appear in it. dhclient appends
+/// new leases to the file, so the last ones are the newest.
+pub fn parse_dhclient_leases(content: &str) -> Result<Vec<Lease>> {
+    let mut leases = Vec::new();
+    let mut current: Option<Lease> = None;
+
+    for statement in split_dhclient_statements(content)? {
+        match statement {
+            Statement::BlockStart(name) if name == "lease" => current = Some(Lease::default()),
+            // other blocks, like the DHCPv6 'lease6', are of no interest
+            Statement::BlockStart(_) => current = None,
+            Statement::BlockEnd => leases.extend(current.take()),
+            Statement::Line(line) => {
+                if let Some(lease) = current.as_mut() {
+                    // a single broken statement should not invalidate the whole lease
+                    if let Err(err) = parse_dhclient_statement(lease, &line) {
+                        info!("Ignoring lease statement '{line}': {err}");
+                    }
+                }
+            }
+        }
+    }
+    Ok(leases)
+}
+
+fn parse_dhclient_statement(lease: &mut Lease, statement: &str) -> Result<()> {
+    let (keyword, rest) = statement
+        .split_once(char::is_whitespace)
+        .map(|(keyword, rest)| (keyword, rest.trim()))
+        .unwrap_or((statement, ""));
+
+    match keyword {
+        "interface" => lease.interface = Some(unescape_dhclient_string(rest)?),
+        "expire" => lease.expire = parse_dhclient_time(rest)?,
+        "option" => {
+            let (name, value) = rest
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format_err!("option without value: '{statement}'"))?;
+            let code = DHCLIENT_OPTION_NAMES
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|(_, code)| *code)
+                .or_else(|| name.strip_prefix("unknown-")?.parse().ok());
+            if let Some(code) = code {
+                lease
+                    .options
+                    .insert(code, parse_dhclient_value(value.trim())?);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+enum Statement {
+    BlockStart(String),
+    BlockEnd,
+    Line(String),
+}
+
+/// Splits the file into statements, honoring quoted strings which may contain ';' or braces.
+fn split_dhclient_statements(content: &str) -> Result<Vec<Statement>> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut in_comment = false;
+
+    for c in content.chars() {
+        if in_comment {
+            in_comment = c != '\n';
+            continue;
+        }
+        if in_quotes {
+            current.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_quotes = false;
+            }
+            continue;
+        }
+
+        match c {
+            '"' => {
+                in_quotes = true;
+                current.push(c);
+            }
+            '#' => in_comment = true,
+            ';' => {
+                let line = current.trim();
+                if !line.is_empty() {
+                    statements.push(Statement::Line(line.to_string()));
+                }
+                current.clear();
+            }
+            '{' => {
+                let name = current.split_whitespace().next().unwrap_or_default();
+                statements.push(Statement::BlockStart(name.to_string()));
+                current.clear();
+            }
+            '}' => {
+                statements.push(Statement::BlockEnd);
+                current.clear();
+            }
+            c => current.push(c),
+        }
+    }
+
+    if in_quotes {
+        bail!("unterminated quoted string");
+    }
+    Ok(statements)
+}
+
+/// Option values are either quoted strings or colon separated hex bytes, e.g. `68:74:a`.
+fn parse_dhclient_value(value: &str) -> Result<Vec<u8>> {
+    if value.starts_with('"') {
+        return Ok(unescape_dhclient_string(value)?.into_bytes());
+    }
+    value
+        .split(':')
+        .map(|byte| {
+            u8::from_str_radix(byte, 16).map_err(|_| format_err!("invalid option value '{value}'"))
+        })
+        .collect()
+}
+
+/// Removes the quotes and resolves backslash escapes, including octal ones like `\073`.
+fn unescape_dhclient_string(value: &str) -> Result<String> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| format_err!("expected quoted string, got '{value}'"))?;
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some(digit @ '0'..='7') => {
+                let mut code = digit.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(code as u8);
+            }
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some(c) => {
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            None => bail!("trailing backslash in '{value}'"),
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Parses times in the `<weekday> YYYY/MM/DD HH:MM:SS` (UTC), `epoch <seconds>` or `never`
+/// format.
+fn parse_dhclient_time(value: &str) -> Result<Option<i64>> {
+    let tokens: Vec<&str> = value.split_whitespace().collect();
+    match tokens[..] {
+        ["never"] => Ok(None),
+        ["epoch", seconds, ..] => Ok(Some(seconds.parse()?)),
+        [_weekday, date, time] => {
+            let date: Vec<i64> = date.split('/').map(str::parse).collect::<Result<_, _>>()?;
+            let time: Vec<i64> = time.split(':').map(str::parse).collect::<Result<_, _>>()?;
+            match (&date[..], &time[..]) {
+                ([year, month, day], [hour, min, sec]) => Ok(Some(
+                    days_from_civil(*year, *month, *day) * 86400 + hour * 3600 + min * 60 + sec,
+                )),
+                _ => bail!("invalid time '{value}'"),
+            }
+        }
+        _ => bail!("invalid time '{value}'"),
+    }
+}
+
+/// Days since the UNIX epoch for a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// systemd-networkd names the lease files after the interface index and stores private options
+/// (224-254) as hex encoded `OPTION_<code>` entries.
+fn parse_networkd_file(path: &Path) -> Result<Option<Vec<Lease>>> {
+    let mut lease = parse_networkd_lease(&fs::read_to_string(path)?)?;
+
+    let ifindex = path.file_name().unwrap_or_default().to_string_lossy();
+    lease.interface = interface_by_index(&ifindex).or(Some(ifindex.into_owned()));
+
+    Ok(Some(vec![lease]))
+}
+
+pub fn parse_networkd_lease(content: &str) -> Result<Lease> {
+    let mut lease = Lease::default();
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if let Some(code) = key
+            .strip_prefix("OPTION_")
+            .and_then(|code| code.parse().ok())
+        {
+            let value = hex::decode(value.trim())
+                .map_err(|err| format_err!("invalid value for '{key}' - {err}"))?;
+            lease.options.insert(code, value);
+        }
+    }
+    Ok(lease)
+}
+
+fn interface_by_index(ifindex: &str) -> Option<String> {
+    fs::read_dir("/sys/class/net").ok()?.find_map(|entry| {
+        let entry = entry.ok()?;
+        let index = fs::read_to_string(entry.path().join("ifindex")).ok()?;
+        (index.trim() == ifindex).then(|| entry.file_name().to_string_lossy().into_owned())
+    })
+}
+
+/// dhcpcd stores the DHCP message of the lease as is, in files named `<interface>.lease` or,
+/// with older versions, `dhcpcd-<interface>.lease`.
+fn parse_dhcpcd_file(path: &Path) -> Result<Option<Vec<Lease>>> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let Some(interface) = name.strip_suffix(".lease") else {
+        return Ok(None);
+    };
+    let interface = interface.strip_prefix("dhcpcd-").unwrap_or(interface);
+
+    let mut lease = parse_dhcp_message(&fs::read(path)?)?;
+    lease.interface = Some(interface.to_string());
+
+    // the lease time is relative to when the lease was written
+    if let Some(lease_time) = lease.options.get(&DHCP_OPTION_LEASE_TIME) {
+        if let (Ok(bytes), Some(written)) = (
+            <[u8; 4]>::try_from(lease_time.as_slice()),
+            modified_time(path),
+        ) {
+            let lease_time = u32::from_be_bytes(bytes);
+            // 0xffffffff means infinite
+            if lease_time != u32::MAX {
+                lease.expire = Some(written + lease_time as i64);
+            }
+        }
+    }
+
+    Ok(Some(vec![lease]))
+}
+
+/// Parses the options of a raw DHCP message. Options occurring multiple times are concatenated,
+/// as defined in RFC 3396.
+pub fn parse_dhcp_message(msg: &[u8]) -> Result<Lease> {
+    let options = msg
+        .get(BOOTP_HEADER_SIZE..)
+        .and_then(|rest| rest.strip_prefix(&DHCP_MAGIC_COOKIE))
+        .ok_or_else(|| format_err!("not a DHCP message"))?;
+
+    let mut lease = Lease::default();
+    let mut pos = 0;
+    while pos < options.len() {
+        let code = options[pos];
+        match code {
+            DHCP_OPTION_PAD => pos += 1,
+            DHCP_OPTION_END => break,
+            code => {
+                let len = *options
+                    .get(pos + 1)
+                    .ok_or_else(|| format_err!("truncated option {code}"))?
+                    as usize;
+                let value = options
+                    .get(pos + 2..pos + 2 + len)
+                    .ok_or_else(|| format_err!("truncated option {code}"))?;
+                lease
+                    .options
+                    .entry(code)
+                    .or_default()
+                    .extend_from_slice(value);
+                pos += 2 + len;
+            }
+        }
+    }
+    Ok(lease)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proxmox_auto_installer::testing::TempDir;
+
+    #[test]
+    fn dhclient_newest_valid_lease() {
+        let content = r#"
+default-duid "\000\001\000\001-\243\036\014\000\000\000\000";
+lease {
+  interface "eth0";
+  option proxmox-auto-installer-manifest-url "https://old.example.com";
+  expire 2 2000/01/04 10:00:00;
+}
+lease {
+  interface "eth0";
+  fixed-address 192.0.2.10;
+  option proxmox-auto-installer-manifest-url "https://pve.example.com/answer";
+  option proxmox-auto-installer-http-headers "Authorization: Bearer a\"b\073c; X-Foo: {bar}";
+  option unknown-251 41:42:c;
+  renew 3 2100/01/05 10:00:00;
+  expire never;
+}
+lease {
+  interface "eth1";
+  option proxmox-auto-installer-manifest-url "https://expired.example.com";
+  expire epoch 946980000; # Tue Jan 04 10:00:00 2000
+}
+"#;
+        let leases = parse_dhclient_leases(content).unwrap();
+        assert_eq!(leases.len(), 3);
+        assert_eq!(leases[0].expire, Some(946980000));
+
+        let leases = leases.into_iter().map(|lease| (lease, 0)).collect();
+        let leases = newest_valid_leases(leases, unix_now());
+        assert_eq!(leases.len(), 1);
+        let lease = &leases[0];
+        assert_eq!(lease.interface.as_deref(), Some("eth0"));
+        assert_eq!(
+            lease.option_text(250).as_deref(),
+            Some("https://pve.example.com/answer")
+        );
+        assert_eq!(
+            lease.option_text(252).as_deref(),
+            Some("Authorization: Bearer a\"b;c; X-Foo: {bar}")
+        );
+        assert_eq!(lease.options[&251], b"AB\x0c");
+    }
+
+    #[test]
+    fn newest_lease_across_files() {
+        let dir = TempDir::new("dhcp-lease-files");
+        let lease = |url: &str, expire: &str| {
+            format!(
+                "lease {{\n  interface \"eth0\";\n  \
+                 option proxmox-auto-installer-manifest-url \"{url}\";\n  \
+                 expire {expire};\n}}\n"
+            )
+        };
+        let now = unix_now();
+        // sorted before the file with the stale lease, which must not win by reading order
+        fs::write(
+            dir.join("dhclient.eth0.leases"),
+            lease("https://new.example.com", &format!("epoch {}", now + 7200)),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("dhclient.leases"),
+            lease(
+                "https://stale.example.com",
+                &format!("epoch {}", now + 3600),
+            ),
+        )
+        .unwrap();
+
+        let leases = read_lease_dir("dhclient", &dir, parse_dhclient_file);
+        assert_eq!(leases.len(), 2);
+        let leases = newest_valid_leases(leases, now);
+        assert_eq!(leases.len(), 1);
+        assert_eq!(
+            leases[0].option_text(250).as_deref(),
+            Some("https://new.example.com")
+        );
+
+        // without expiry, the lease of the most recently written file is used
+        fs::write(
+            dir.join("dhclient.leases"),
+            lease("https://stale.example.com", "never"),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("dhclient.eth0.leases"),
+            lease("https://new.example.com", "never"),
+        )
+        .unwrap();
+        let written = SystemTime::now() - std::time::Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(dir.join("dhclient.leases"))
+            .and_then(|file| file.set_modified(written))
+            .unwrap();
+
+        let leases = read_lease_dir("dhclient", &dir, parse_dhclient_file);
+        let leases = newest_valid_leases(leases, now);
+        assert_eq!(
+            leases[0].option_text(250).as_deref(),
+            Some("https://new.example.com")
+        );
+    }
+
+    #[test]
+    fn networkd_lease() {
+        let content = "# This is private data. Do not parse.\n\
+                       ADDRESS=192.0.2.10\n\
+                       OPTION_250=68747470733a2f2f7076652e6578616d706c652e636f6d00\n";
+        let lease = parse_networkd_lease(content).unwrap();
+        assert_eq!(
+            lease.option_text(250).as_deref(),
+            Some("https://pve.example.com")
+        );
+    }
+
+    #[test]
+    fn dhcpcd_message() {
+        let mut msg = vec![0u8; BOOTP_HEADER_SIZE];
+        msg.extend_from_slice(&DHCP_MAGIC_COOKIE);
+        msg.extend_from_slice(&[53, 1, 5, DHCP_OPTION_PAD]);
+        msg.extend_from_slice(&[250, 5]);
+        msg.extend_from_slice(b"https");
+        msg.extend_from_slice(&[250, 11]);
+        msg.extend_from_slice(b"://pve.test");
+        msg.push(DHCP_OPTION_END);
+
+        let lease = parse_dhcp_message(&msg).unwrap();
+        assert_eq!(lease.option_text(250).as_deref(), Some("https://pve.test"));
+        assert!(parse_dhcp_message(&msg[..100]).is_err());
+    }
+}
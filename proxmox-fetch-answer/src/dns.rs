@@ -0,0 +1,476 @@
+//! Minimal DNS stub resolver, only supporting what is needed to discover the answer server.
+//!
+//! Queries are sent via UDP to the configured nameservers, falling back to TCP if the response
+//! was truncated.
+
+use anyhow::{bail, format_err, Result};
+use log::info;
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, UdpSocket},
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+static RESOLV_CONF: &str = "/etc/resolv.conf";
+const DNS_PORT: u16 = 53;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+// the traditional maximum size of a DNS message via UDP, without EDNS
+const MAX_UDP_SIZE: usize = 512;
+
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+const RCODE_NOERROR: u8 = 0;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Nameservers and search domains, as configured in resolv.conf.
+#[derive(Debug, Default, PartialEq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<SocketAddr>,
+    pub search: Vec<String>,
+}
+
+impl ResolvConf {
+    pub fn load() -> Result<Self> {
+        let content = fs::read_to_string(RESOLV_CONF)
+            .map_err(|err| format_err!("failed to read '{RESOLV_CONF}' - {err}"))?;
+        Ok(Self::parse(&content))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut conf = Self::default();
+        for line in content.lines() {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("nameserver") => {
+                    // link-local IPv6 addresses may carry a zone index, e.g. 'fe80::1%eth0'
+                    let addr = tokens.next().and_then(|addr| addr.split('%').next());
+                    if let Some(Ok(ip)) = addr.map(str::parse::<IpAddr>) {
+                        conf.nameservers.push(SocketAddr::new(ip, DNS_PORT));
+                    }
+                }
+                // 'domain' and 'search' are mutually exclusive, the last one wins
+                Some("search") | Some("domain") => {
+                    conf.search = tokens
+                        .map(|domain| domain.trim_end_matches('.').to_string())
+                        .filter(|domain| !domain.is_empty())
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+        conf
+    }
+}
+
+/// A single SRV record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+pub struct Resolver {
+    nameservers: Vec<SocketAddr>,
+    timeout: Duration,
+}
+
+impl Resolver {
+    pub fn new(nameservers: Vec<SocketAddr>) -> Self {
+        Self {
+            nameservers,
+            timeout: QUERY_TIMEOUT,
+        }
+    }
+
+    /// Queries the TXT records of a name. The character-strings of each record are joined, so
+    /// every entry corresponds to one record. Returns an empty list if the name does not exist.
+    pub fn query_txt(&self, name: &str) -> Result<Vec<String>> {
+        info!("Querying TXT record for '{name}'");
+        let (msg, records) = self.query(name, TYPE_TXT)?;
+        records
+            .into_iter()
+            .map(|rdata| parse_txt(&msg[rdata]))
+            .collect()
+    }
+
+    /// Queries the SRV records of a name, sorted by priority and descending weight. Returns an
+    /// empty list if the name does not exist.
+    pub fn query_srv(&self, name: &str) -> Result<Vec<SrvRecord>> {
+        info!("Querying SRV record for '{name}'");
+        let (msg, records) = self.query(name, TYPE_SRV)?;
+        let mut records = records
+            .into_iter()
+            .map(|rdata| parse_srv(&msg, rdata))
+            .collect::<Result<Vec<_>>>()?;
+        records.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+        Ok(records)
+    }
+
+    /// Sends the query to each nameserver in turn, until one answers.
+    ///
+    /// Returns the response message along with the position of the RDATA of all matching answer
+    /// records. The whole message is needed to resolve compressed names within the RDATA.
+    fn query(&self, name: &str, qtype: u16) -> Result<(Vec<u8>, Vec<Range<usize>>)> {
+        if self.nameservers.is_empty() {
+            bail!("no nameserver configured");
+        }
+
+        let id = query_id();
+        let query = encode_query(id, name, qtype)?;
+
+        let mut errors = Vec::new();
+        for server in &self.nameservers {
+            match self.exchange(*server, &query, id) {
+                Ok(msg) => match parse_response(&msg, qtype) {
+                    Ok(records) => return Ok((msg, records)),
+                    Err(err) => errors.push(format!("{server}: {err}")),
+                },
+                Err(err) => errors.push(format!("{server}: {err}")),
+            }
+        }
+        bail!("DNS query for '{name}' failed - {}", errors.join(", "));
+    }
+
+    fn exchange(&self, server: SocketAddr, query: &[u8], id: u16) -> Result<Vec<u8>> {
+        let bind_addr: SocketAddr = match server {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.connect(server)?;
+        socket.send(query)?;
+
+        let mut buf = [0u8; MAX_UDP_SIZE];
+        loop {
+            let len = socket.recv(&mut buf)?;
+            let msg = &buf[..len];
+            // ignore stray responses to earlier queries
+            if msg.len() < 12 || u16::from_be_bytes([msg[0], msg[1]]) != id {
+                continue;
+            }
+            // truncated, retry via TCP to get the full response
+            if msg[2] & 0x02 != 0 {
+                info!("Response from {server} was truncated, retrying via TCP");
+                return self.exchange_tcp(server, query, id);
+            }
+            return Ok(msg.to_vec());
+        }
+    }
+
+    fn exchange_tcp(&self, server: SocketAddr, query: &[u8], id: u16) -> Result<Vec<u8>> {
+        let mut stream = TcpStream::connect_timeout(&server, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut request = (query.len() as u16).to_be_bytes().to_vec();
+        request.extend_from_slice(query);
+        stream.write_all(&request)?;
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len)?;
+        let mut msg = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut msg)?;
+
+        if msg.len() < 12 || u16::from_be_bytes([msg[0], msg[1]]) != id {
+            bail!("invalid response via TCP");
+        }
+        Ok(msg)
+    }
+}
+
+/// The ID only needs to tell apart our own consecutive queries, no need for a proper RNG.
+fn query_id() -> u16 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or(0);
+    (nanos ^ std::process::id()) as u16
+}
+
+fn encode_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(MAX_UDP_SIZE);
+    msg.extend_from_slice(&id.to_be_bytes());
+    // standard query, recursion desired
+    msg.extend_from_slice(&[0x01, 0x00]);
+    // one question, no answer, authority or additional records
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("invalid domain name '{name}'");
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+fn parse_response(msg: &[u8], qtype: u16) -> Result<Vec<Range<usize>>> {
+    if msg.len() < 12 {
+        bail!("response too short");
+    }
+    if msg[2] & 0x80 == 0 {
+        bail!("not a response");
+    }
+    match msg[3] & 0x0f {
+        RCODE_NOERROR => {}
+        RCODE_NXDOMAIN => return Ok(Vec::new()),
+        rcode => bail!("server returned error code {rcode}"),
+    }
+
+    let qdcount = u16::from_be_bytes([msg[4], msg[5]]);
+    let ancount = u16::from_be_bytes([msg[6], msg[7]]);
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = skip_name(msg, pos)? + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..ancount {
+        pos = skip_name(msg, pos)?;
+        let header = msg
+            .get(pos..pos + 10)
+            .ok_or_else(|| format_err!("truncated resource record"))?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let rdlength = u16::from_be_bytes([header[8], header[9]]) as usize;
+        pos += 10;
+        if pos + rdlength > msg.len() {
+            bail!("truncated resource record");
+        }
+
+        // the answer might also contain e.g. CNAME records leading to the actual records
+        if rtype == qtype {
+            records.push(pos..pos + rdlength);
+        }
+        pos += rdlength;
+    }
+    Ok(records)
+}
+
+/// Returns the position after the (possibly compressed) name starting at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> Result<usize> {
+    loop {
+        let len = *msg.get(pos).ok_or_else(|| format_err!("truncated name"))? as usize;
+        match len {
+            0 => return Ok(pos + 1),
+            // a pointer always ends the name
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len => pos += len + 1,
+        }
+    }
+}
+
+/// Reads the (possibly compressed) name starting at `pos`.
+fn read_name(msg: &[u8], mut pos: usize) -> Result<String> {
+    let mut labels = Vec::new();
+    // guard against pointer loops
+    let mut jumps = 0;
+    loop {
+        let len = *msg.get(pos).ok_or_else(|| format_err!("truncated name"))? as usize;
+        if len == 0 {
+            break;
+        } else if len & 0xc0 == 0xc0 {
+            let low = *msg
+                .get(pos + 1)
+                .ok_or_else(|| format_err!("truncated name"))? as usize;
+            jumps += 1;
+            if jumps > 32 {
+                bail!("too many compression pointers in name");
+            }
+            pos = ((len & 0x3f) << 8) | low;
+        } else {
+            let label = msg
+                .get(pos + 1..pos + 1 + len)
+                .ok_or_else(|| format_err!("truncated name"))?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += len + 1;
+        }
+    }
+    Ok(labels.join("."))
+}
+
+/// A TXT record consists of one or more length-prefixed character-strings, which together form
+/// the value.
+fn parse_txt(rdata: &[u8]) -> Result<String> {
+    let mut value = Vec::new();
+    let mut pos = 0;
+    while pos < rdata.len() {
+        let len = rdata[pos] as usize;
+        let part = rdata
+            .get(pos + 1..pos + 1 + len)
+            .ok_or_else(|| format_err!("truncated TXT record"))?;
+        value.extend_from_slice(part);
+        pos += len + 1;
+    }
+    String::from_utf8(value).map_err(|_| format_err!("TXT record is not valid UTF-8"))
+}
+
+/// The target name might point into the rest of the message, so this needs the whole message.
+fn parse_srv(msg: &[u8], rdata: Range<usize>) -> Result<SrvRecord> {
+    let start = rdata.start;
+    let rdata = &msg[rdata];
+    if rdata.len() < 7 {
+        bail!("truncated SRV record");
+    }
+
+    Ok(SrvRecord {
+        priority: u16::from_be_bytes([rdata[0], rdata[1]]),
+        weight: u16::from_be_bytes([rdata[2], rdata[3]]),
+        port: u16::from_be_bytes([rdata[4], rdata[5]]),
+        target: read_name(msg, start + 6)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    /// Builds a response to the query, answering with the given records of the queried type.
+    fn build_response(query: &[u8], answers: &[Vec<u8>], truncated: bool) -> Vec<u8> {
+        let qtype = &query[query.len() - 4..query.len() - 2];
+        let mut msg = query.to_vec();
+        msg[2] = 0x81 | if truncated { 0x02 } else { 0 };
+        msg[3] = 0x80;
+        msg[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for rdata in answers {
+            // pointer to the name in the question
+            msg.extend_from_slice(&[0xc0, 0x0c]);
+            msg.extend_from_slice(qtype);
+            msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+            msg.extend_from_slice(&300u32.to_be_bytes());
+            msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            msg.extend_from_slice(rdata);
+        }
+        msg
+    }
+
+    /// Answers a single query via UDP and, if `truncate` is set, marks the response as truncated
+    /// and answers the retry via TCP on the same port.
+    fn stand_in_server(answers: Vec<Vec<u8>>, truncate: bool) -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; MAX_UDP_SIZE];
+            let (len, peer) = udp.recv_from(&mut buf).unwrap();
+            let query = &buf[..len];
+
+            if !truncate {
+                let response = build_response(query, &answers, false);
+                udp.send_to(&response, peer).unwrap();
+                return;
+            }
+
+            let response = build_response(query, &[], true);
+            udp.send_to(&response, peer).unwrap();
+
+            let (mut stream, _) = tcp.accept().unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut query).unwrap();
+
+            let response = build_response(&query, &answers, false);
+            stream
+                .write_all(&(response.len() as u16).to_be_bytes())
+                .unwrap();
+            stream.write_all(&response).unwrap();
+        });
+
+        addr
+    }
+
+    fn txt_rdata(parts: &[&str]) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        for part in parts {
+            rdata.push(part.len() as u8);
+            rdata.extend_from_slice(part.as_bytes());
+        }
+        rdata
+    }
+
+    #[test]
+    fn parse_resolv_conf() {
+        let conf = ResolvConf::parse(
+            "# generated by dhclient\n\
+             domain old.example\n\
+             search example.com. lab.example.com\n\
+             nameserver 192.0.2.1\n\
+             nameserver fe80::1%eth0\n\
+             nameserver invalid\n",
+        );
+        assert_eq!(
+            conf,
+            ResolvConf {
+                nameservers: vec![
+                    "192.0.2.1:53".parse().unwrap(),
+                    "[fe80::1]:53".parse().unwrap()
+                ],
+                search: vec!["example.com".into(), "lab.example.com".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn query_multi_string_txt() {
+        let server = stand_in_server(
+            vec![
+                txt_rdata(&["https://pve.example.com", "/answer"]),
+                txt_rdata(&["second"]),
+            ],
+            false,
+        );
+        let resolver = Resolver::new(vec![server]);
+        assert_eq!(
+            resolver
+                .query_txt("proxmox-auto-installer.example.com")
+                .unwrap(),
+            ["https://pve.example.com/answer", "second"]
+        );
+    }
+
+    #[test]
+    fn query_srv_via_tcp_fallback() {
+        let mut low_prio = vec![0, 20, 0, 5, 0x1f, 0x90];
+        low_prio.extend_from_slice(&[4, b'b', b'a', b'c', b'k', 0xc0, 0x0c]);
+        let mut high_prio = vec![0, 10, 0, 5, 0x01, 0xbb];
+        high_prio.extend_from_slice(&[4, b'm', b'a', b'i', b'n', 0]);
+
+        let server = stand_in_server(vec![low_prio, high_prio], true);
+        let resolver = Resolver::new(vec![server]);
+        let records = resolver
+            .query_srv("_proxmox-auto-installer._tcp.example.com")
+            .unwrap();
+        assert_eq!(
+            records,
+            [
+                SrvRecord {
+                    priority: 10,
+                    weight: 5,
+                    port: 443,
+                    target: "main".into(),
+                },
+                SrvRecord {
+                    priority: 20,
+                    weight: 5,
+                    port: 8080,
+                    target: "back._proxmox-auto-installer._tcp.example.com".into(),
+                },
+            ]
+        );
+    }
+}
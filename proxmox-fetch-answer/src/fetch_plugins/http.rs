@@ -1,14 +1,20 @@
 use anyhow::{bail, Result};
-use log::info;
-use std::{
-    fs::{self, read_to_string},
-    process::Command,
-};
+use log::{info, warn};
+use std::{collections::BTreeMap, fs};
 
-use proxmox_auto_installer::{sysinfo::SysInfo, utils::HttpOptions};
+use crate::{
+    dhcp,
+    dns::{ResolvConf, Resolver},
+};
+use proxmox_auto_installer::{
+    sysinfo::SysInfo,
+    utils::{parse_http_header, HttpOptions},
+};
 
 static ANSWER_URL_SUBDOMAIN: &str = "proxmox-auto-installer";
 static ANSWER_CERT_FP_SUBDOMAIN: &str = "proxmox-auto-installer-cert-fingerprint";
+static ANSWER_HEADER_SUBDOMAIN: &str = "proxmox-auto-installer-http-header";
+static ANSWER_SRV_SERVICE: &str = "_proxmox-auto-installer._tcp";
 
 // It is possible to set custom DHPC options. Option numbers 224 to 254 [0].
 // To use them with dhclient, we need to configure it to request them and what they should be
@@ -18,18 +24,40 @@ static ANSWER_CERT_FP_SUBDOMAIN: &str = "proxmox-auto-installer-cert-fingerprint
 // ```
 // option proxmox-auto-installer-manifest-url code 250 = text;
 // option proxmox-auto-installer-cert-fingerprint code 251 = text;
-// also request proxmox-auto-installer-manifest-url, proxmox-auto-installer-cert-fingerprint;
+// option proxmox-auto-installer-http-headers code 252 = text;
+// also request proxmox-auto-installer-manifest-url, proxmox-auto-installer-cert-fingerprint,
+//     proxmox-auto-installer-http-headers;
 // ```
 //
-// The results will end up in the /var/lib/dhcp/dhclient.leases file from where we can fetch them
+// The results will end up in the /var/lib/dhcp/dhclient.leases file from where we can fetch them.
+// The lease files of systemd-networkd and dhcpcd are supported as well, see the `dhcp` module.
 //
 // [0] https://www.iana.org/assignments/bootp-dhcp-parameters/bootp-dhcp-parameters.xhtml
-static DHCP_URL_OPTION: &str = "proxmox-auto-installer-manifest-url";
-static DHCP_CERT_FP_OPTION: &str = "proxmox-auto-installer-cert-fingerprint";
-static DHCP_LEASE_FILE: &str = "/var/lib/dhcp/dhclient.leases";
+const DHCP_URL_OPTION: u8 = 250;
+const DHCP_CERT_FP_OPTION: u8 = 251;
+const DHCP_HEADERS_OPTION: u8 = 252;
 
 pub struct FetchFromHTTP;
 
+/// The connection settings used for the answer server, as resolved from the ISO, DHCP or DNS.
+pub struct AnswerServer {
+    fingerprint: Option<String>,
+    client_cert: Option<http_post::ClientCert>,
+    headers: BTreeMap<String, String>,
+}
+
+impl AnswerServer {
+    fn post(&self, url: String, payload: String) -> Result<String> {
+        http_post::call(
+            url,
+            self.fingerprint.as_deref(),
+            self.client_cert.as_ref(),
+            &self.headers,
+            payload,
+        )
+    }
+}
+
 impl FetchFromHTTP {
     /// Will try to fetch the answer.toml by sending a HTTP POST request. The URL can be configured
     /// either via DHCP or DNS or preconfigured in the ISO.
@@ -37,7 +65,9 @@ impl FetchFromHTTP {
     /// needs to be either trusted by the root certs or a SHA256 fingerprint needs to be provided.
     /// The SHA256 SSL fingerprint can either be defined in the ISO, as DHCP option, or as DNS TXT
     /// record. If provided, the fingerprint provided in the ISO has preference.
-    pub fn get_answer(settings: &HttpOptions) -> Result<String> {
+    /// Additional HTTP headers, e.g. for authorization, can be defined in the ISO or, together with
+    /// the URL, via DHCP or DNS. Headers defined in the ISO take precedence.
+    pub fn get_answer(settings: &HttpOptions) -> Result<(String, AnswerServer)> {
         let mut fingerprint: Option<String> = match settings.cert_fingerprint.clone() {
             Some(fp) => {
                 info!("SSL fingerprint provided through ISO.");
@@ -47,146 +77,294 @@ impl FetchFromHTTP {
         };
 
         let answer_url: String;
+        let mut headers = BTreeMap::new();
         if let Some(url) = settings.url.clone() {
             info!("URL specified in ISO");
             answer_url = url;
         } else {
-            (answer_url, fingerprint) = match Self::fetch_dhcp(fingerprint.clone()) {
-                Ok((url, fp)) => (url, fp),
+            (answer_url, fingerprint, headers) = match Self::fetch_dhcp(fingerprint.clone()) {
+                Ok(res) => res,
                 Err(err) => {
                     info!("{err}");
                     Self::fetch_dns(fingerprint.clone())?
                 }
             };
         }
+        Self::merge_headers(&mut headers, &settings.headers);
+        if !headers.is_empty() {
+            // only log the names, the values might contain secrets
+            let names: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+            info!("Sending additional HTTP headers: {}", names.join(", "));
+        }
 
         if let Some(fingerprint) = &fingerprint {
             let _ = fs::write("/tmp/cert_fingerprint", fingerprint);
         }
 
+        let client_cert = match (&settings.client_cert, &settings.client_key) {
+            (Some(cert), Some(key)) => {
+                info!("Client certificate provided through ISO.");
+                Some(http_post::ClientCert::from_pem(cert, key)?)
+            }
+            (None, None) => None,
+            _ => bail!("Client certificate and key must be provided together."),
+        };
+
+        let server = AnswerServer {
+            fingerprint,
+            client_cert,
+            headers,
+        };
+
         info!("Gathering system information.");
         let payload = SysInfo::as_json()?;
         info!("Sending POST request to '{answer_url}'.");
-        let answer = http_post::call(answer_url, fingerprint.as_deref(), payload)?;
-        Ok(answer)
+        let answer = server.post(answer_url, payload)?;
+        Ok((answer, server))
     }
 
-    /// Fetches search domain from resolv.conf file
-    fn get_search_domain() -> Result<String> {
-        info!("Retrieving default search domain.");
-        for line in read_to_string("/etc/resolv.conf")?.lines() {
-            if let Some((key, value)) = line.split_once(' ') {
-                if key == "search" {
-                    return Ok(value.trim().into());
-                }
-            }
-        }
-        bail!("Could not find search domain in resolv.conf.");
+    /// Sends the error of an invalid answer, together with the system information, to the error
+    /// report URL. The same TLS settings and headers as for fetching the answer are used.
+    pub fn report_error(server: &AnswerServer, url: &str, error: &str) -> Result<()> {
+        let payload = serde_json::json!({
+            "error": error,
+            "system_info": SysInfo::get()?,
+        });
+        info!("Reporting invalid answer file to '{url}'.");
+        server.post(url.to_string(), payload.to_string())?;
+        Ok(())
     }
 
-    /// Runs a TXT DNS query on the domain provided
-    fn query_txt_record(query: String) -> Result<String> {
-        info!("Querying TXT record for '{query}'");
-        let url: String;
-        match Command::new("dig")
-            .args(["txt", "+short"])
-            .arg(&query)
-            .output()
-        {
-            Ok(output) => {
-                if output.status.success() {
-                    url = String::from_utf8(output.stdout)?
-                        .replace('"', "")
-                        .trim()
-                        .into();
-                    if url.is_empty() {
-                        bail!("Got empty response.");
-                    }
-                } else {
-                    bail!(
-                        "Error querying DNS record '{query}' : {}",
-                        String::from_utf8(output.stderr)?
-                    );
-                }
-            }
-            Err(err) => bail!("Error querying DNS record '{query}': {err}"),
+    /// Tries to fetch answer URL, SSL fingerprint info and HTTP headers from DNS
+    ///
+    /// All search domains from resolv.conf are tried in order. The URL is taken from a TXT record
+    /// or, if there is none, built from the host and port of a SRV record.
+    fn fetch_dns(
+        mut fingerprint: Option<String>,
+    ) -> Result<(String, Option<String>, BTreeMap<String, String>)> {
+        info!("Checking DNS records.");
+        let resolv_conf = ResolvConf::load()?;
+        if resolv_conf.search.is_empty() {
+            bail!("Could not find search domain in resolv.conf.");
         }
-        info!("Found: '{url}'");
-        Ok(url)
-    }
-
-    /// Tries to fetch answer URL and SSL fingerprint info from DNS
-    fn fetch_dns(mut fingerprint: Option<String>) -> Result<(String, Option<String>)> {
-        let search_domain = Self::get_search_domain()?;
-
-        let answer_url =
-            match Self::query_txt_record(format!("{ANSWER_URL_SUBDOMAIN}.{search_domain}")) {
-                Ok(url) => url,
-                Err(err) => bail!("{err}"),
+        let resolver = Resolver::new(resolv_conf.nameservers);
+
+        for domain in &resolv_conf.search {
+            let answer_url = match Self::lookup_answer_url(&resolver, domain) {
+                Ok(Some(url)) => url,
+                Ok(None) => {
+                    info!("No answer URL found for search domain '{domain}'");
+                    continue;
+                }
+                Err(err) => {
+                    info!("{err}");
+                    continue;
+                }
             };
+            info!("Found answer URL via DNS: '{answer_url}'");
 
-        if fingerprint.is_none() {
-            fingerprint =
-                match Self::query_txt_record(format!("{ANSWER_CERT_FP_SUBDOMAIN}.{search_domain}"))
-                {
-                    Ok(fp) => Some(fp),
+            if fingerprint.is_none() {
+                let fp_record = format!("{ANSWER_CERT_FP_SUBDOMAIN}.{domain}");
+                fingerprint = match resolver.query_txt(&fp_record) {
+                    Ok(records) => records.into_iter().next(),
                     Err(err) => {
                         info!("{err}");
                         None
                     }
                 };
+                if let Some(fp) = fingerprint.as_ref() {
+                    info!("Found SSL Fingerprint via DNS: '{fp}'");
+                }
+            }
+
+            // every TXT record holds a single header
+            let headers = match resolver.query_txt(&format!("{ANSWER_HEADER_SUBDOMAIN}.{domain}")) {
+                Ok(records) => Self::parse_headers(records.iter().map(String::as_str)),
+                Err(err) => {
+                    info!("{err}");
+                    BTreeMap::new()
+                }
+            };
+
+            return Ok((answer_url, fingerprint, headers));
         }
-        Ok((answer_url, fingerprint))
+        bail!("Could not find answer URL via DNS in any search domain.");
     }
 
-    /// Tries to fetch answer URL and SSL fingerprint info from DHCP options
-    fn fetch_dhcp(mut fingerprint: Option<String>) -> Result<(String, Option<String>)> {
-        info!("Checking DHCP options.");
-        let leases = fs::read_to_string(DHCP_LEASE_FILE)?;
+    /// Looks up the answer URL in the TXT record of the domain, or builds it from the SRV record
+    /// with the lowest priority. A failed TXT lookup, e.g. a timeout, still tries the SRV record.
+    fn lookup_answer_url(resolver: &Resolver, domain: &str) -> Result<Option<String>> {
+        match resolver.query_txt(&format!("{ANSWER_URL_SUBDOMAIN}.{domain}")) {
+            Ok(txt) => {
+                if let Some(url) = txt.into_iter().find(|url| !url.trim().is_empty()) {
+                    return Ok(Some(url.trim().to_string()));
+                }
+            }
+            Err(err) => warn!("Looking up answer URL TXT record failed: {err}"),
+        }
 
-        let mut answer_url: Option<String> = None;
+        let srv = resolver.query_srv(&format!("{ANSWER_SRV_SERVICE}.{domain}"))?;
+        // a target of "." means the service is explicitly not available
+        Ok(srv
+            .into_iter()
+            .find(|record| !record.target.is_empty())
+            .map(|record| format!("https://{}:{}/", record.target, record.port)))
+    }
 
-        let url_match = format!("option {DHCP_URL_OPTION}");
-        let fp_match = format!("option {DHCP_CERT_FP_OPTION}");
+    /// Tries to fetch answer URL, SSL fingerprint info and HTTP headers from DHCP options
+    fn fetch_dhcp(
+        mut fingerprint: Option<String>,
+    ) -> Result<(String, Option<String>, BTreeMap<String, String>)> {
+        info!("Checking DHCP options.");
+        let leases = dhcp::find_leases();
 
-        for line in leases.lines() {
-            if answer_url.is_none() && line.trim().starts_with(url_match.as_str()) {
-                answer_url = Self::strip_dhcp_option(line.split(' ').nth_back(0));
+        // all options are taken from the same lease as the URL
+        let (lease, answer_url) = match leases
+            .iter()
+            .find_map(|lease| Some((lease, lease.option_text(DHCP_URL_OPTION)?)))
+        {
+            None => bail!("No DHCP option found for fetch URL."),
+            Some((lease, url)) => {
+                let interface = lease.interface.as_deref().unwrap_or("unknown interface");
+                info!("Found URL for answer in DHCP option on {interface}: '{url}'");
+                (lease, url)
             }
-            if fingerprint.is_none() && line.trim().starts_with(fp_match.as_str()) {
-                fingerprint = Self::strip_dhcp_option(line.split(' ').nth_back(0));
+        };
+
+        if fingerprint.is_none() {
+            fingerprint = lease.option_text(DHCP_CERT_FP_OPTION);
+            if let Some(fp) = fingerprint.clone() {
+                info!("Found SSL Fingerprint via DHCP: '{fp}'");
             }
         }
 
-        let answer_url = match answer_url {
-            None => bail!("No DHCP option found for fetch URL."),
-            Some(url) => {
-                info!("Found URL for answer in DHCP option: '{url}'");
-                url
-            }
-        };
+        // multiple headers are separated by a semicolon
+        let headers = lease
+            .option_text(DHCP_HEADERS_OPTION)
+            .map(|headers| Self::parse_headers(headers.split(';')))
+            .unwrap_or_default();
+
+        Ok((answer_url, fingerprint, headers))
+    }
 
-        if let Some(fp) = fingerprint.clone() {
-            info!("Found SSL Fingerprint via DHCP: '{fp}'");
+    /// Adds the headers defined in the ISO, replacing the ones from DHCP or DNS with the same
+    /// name. Names are compared case-insensitively, ISOs prepared by older versions of the
+    /// assistant do not store them in lowercase.
+    fn merge_headers(headers: &mut BTreeMap<String, String>, iso: &BTreeMap<String, String>) {
+        for (name, value) in iso {
+            headers.retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+            headers.insert(name.to_ascii_lowercase(), value.clone());
+        }
+    }
+
+    /// Parses HTTP headers in the `Name: value` format, invalid ones are skipped.
+    fn parse_headers<'a>(headers: impl Iterator<Item = &'a str>) -> BTreeMap<String, String> {
+        let mut result = BTreeMap::new();
+        for header in headers.map(str::trim).filter(|header| !header.is_empty()) {
+            match parse_http_header(header) {
+                Ok((name, value)) => {
+                    info!("Found HTTP header '{name}'");
+                    result.insert(name, value);
+                }
+                Err(err) => warn!("Ignoring HTTP header: {err}"),
+            }
         }
+        result
+    }
+}
 
-        Ok((answer_url, fingerprint))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_headers() {
+        let mut headers = FetchFromHTTP::parse_headers(
+            "authorization: Bearer dhcp; X-Foo: bar; invalid"
+                .split(';')
+                .map(str::trim),
+        );
+        assert_eq!(
+            headers,
+            BTreeMap::from([
+                ("authorization".to_string(), "Bearer dhcp".to_string()),
+                ("x-foo".to_string(), "bar".to_string()),
+            ])
+        );
+
+        let iso = BTreeMap::from([("Authorization".to_string(), "Bearer iso".to_string())]);
+        FetchFromHTTP::merge_headers(&mut headers, &iso);
+        assert_eq!(
+            headers,
+            BTreeMap::from([
+                ("authorization".to_string(), "Bearer iso".to_string()),
+                ("x-foo".to_string(), "bar".to_string()),
+            ])
+        );
     }
 
-    /// Clean DHCP option string
-    fn strip_dhcp_option(value: Option<&str>) -> Option<String> {
-        // value is expected to be in format: "value";
-        value.map(|value| String::from(&value[1..value.len() - 2]))
+    #[test]
+    fn client_cert_from_pem() {
+        let cert = include_str!("../../tests/resources/tls/client.crt");
+        let key = include_str!("../../tests/resources/tls/client.key");
+        assert!(http_post::ClientCert::from_pem(cert, key).is_ok());
+
+        // the key where the certificate is expected, and the other way round
+        assert!(http_post::ClientCert::from_pem(key, key).is_err());
+        assert!(http_post::ClientCert::from_pem(cert, cert).is_err());
+        assert!(http_post::ClientCert::from_pem("", key).is_err());
+        assert!(http_post::ClientCert::from_pem(cert, "").is_err());
+
+        let dsa_key = include_str!("../../tests/resources/tls/dsa.key");
+        assert!(http_post::ClientCert::from_pem(cert, dsa_key).is_err());
     }
 }
 
 mod http_post {
-    use anyhow::Result;
-    use rustls::ClientConfig;
+    use anyhow::{bail, format_err, Result};
+    use rustls::{Certificate, ClientConfig, PrivateKey};
+    use rustls_pemfile::Item;
     use sha2::{Digest, Sha256};
-    use std::sync::Arc;
+    use std::{collections::BTreeMap, sync::Arc};
     use ureq::{Agent, AgentBuilder};
 
+    /// Client certificate chain and its private key, used to authenticate against the answer
+    /// server (mutual TLS).
+    pub struct ClientCert {
+        certs: Vec<Certificate>,
+        key: PrivateKey,
+    }
+
+    impl ClientCert {
+        /// Parses the PEM encoded certificate chain and private key.
+        pub fn from_pem(cert: &str, key: &str) -> Result<Self> {
+            let certs: Vec<Certificate> = rustls_pemfile::certs(&mut cert.as_bytes())
+                .map_err(|err| format_err!("failed to parse client certificate - {err}"))?
+                .into_iter()
+                .map(Certificate)
+                .collect();
+            if certs.is_empty() {
+                bail!("No certificate found in client certificate PEM data.");
+            }
+
+            let key = rustls_pemfile::read_all(&mut key.as_bytes())
+                .map_err(|err| format_err!("failed to parse client key - {err}"))?
+                .into_iter()
+                .find_map(|item| match item {
+                    Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => {
+                        Some(PrivateKey(key))
+                    }
+                    _ => None,
+                })
+                .ok_or_else(|| format_err!("No private key found in client key PEM data."))?;
+            if rustls::sign::any_supported_type(&key).is_err() {
+                bail!("Unsupported type of client key, expected an RSA, ECDSA or Ed25519 key.");
+            }
+
+            Ok(Self { certs, key })
+        }
+    }
+
     /// Issues a POST request with the payload (JSON). Optionally a SHA256 fingerprint can be used to
     /// check the cert against it, instead of the regular cert validation.
     /// To gather the sha256 fingerprint you can use the following command:
@@ -197,45 +375,54 @@ mod http_post {
     /// # Arguemnts
     /// * `url` - URL to call
     /// * `fingerprint` - SHA256 cert fingerprint if certificate pinning should be used. Optional.
+    /// * `client_cert` - Client certificate to present to the server. Optional.
+    /// * `headers` - Additional HTTP headers to set on the request.
     /// * `payload` - The payload to send to the server. Expected to be a JSON formatted string.
-    pub fn call(url: String, fingerprint: Option<&str>, payload: String) -> Result<String> {
-        let answer;
-
-        if let Some(fingerprint) = fingerprint {
-            let tls_config = ClientConfig::builder()
+    pub fn call(
+        url: String,
+        fingerprint: Option<&str>,
+        client_cert: Option<&ClientCert>,
+        headers: &BTreeMap<String, String>,
+        payload: String,
+    ) -> Result<String> {
+        let agent: Agent = if let Some(fingerprint) = fingerprint {
+            let builder = ClientConfig::builder()
                 .with_safe_defaults()
-                .with_custom_certificate_verifier(VerifyCertFingerprint::new(fingerprint)?)
-                .with_no_client_auth();
-
-            let agent: Agent = AgentBuilder::new().tls_config(Arc::new(tls_config)).build();
+                .with_custom_certificate_verifier(VerifyCertFingerprint::new(fingerprint)?);
+            let tls_config = match client_cert {
+                Some(cert) => builder.with_single_cert(cert.certs.clone(), cert.key.clone())?,
+                None => builder.with_no_client_auth(),
+            };
 
-            answer = agent
-                .post(&url)
-                .set("Content-type", "application/json; charset=utf-8")
-                .send_string(&payload)?
-                .into_string()?;
+            AgentBuilder::new().tls_config(Arc::new(tls_config)).build()
         } else {
             let mut roots = rustls::RootCertStore::empty();
             for cert in rustls_native_certs::load_native_certs()? {
                 roots.add(&rustls::Certificate(cert.0)).unwrap();
             }
 
-            let tls_config = rustls::ClientConfig::builder()
+            let builder = rustls::ClientConfig::builder()
                 .with_safe_defaults()
-                .with_root_certificates(roots)
-                .with_no_client_auth();
+                .with_root_certificates(roots);
+            let tls_config = match client_cert {
+                Some(cert) => builder.with_single_cert(cert.certs.clone(), cert.key.clone())?,
+                None => builder.with_no_client_auth(),
+            };
 
-            let agent = AgentBuilder::new()
+            AgentBuilder::new()
                 .tls_connector(Arc::new(native_tls::TlsConnector::new()?))
                 .tls_config(Arc::new(tls_config))
-                .build();
-            answer = agent
-                .post(&url)
-                .set("Content-type", "application/json; charset=utf-8")
-                .timeout(std::time::Duration::from_secs(60))
-                .send_string(&payload)?
-                .into_string()?;
+                .build()
+        };
+
+        let mut request = agent
+            .post(&url)
+            .set("Content-type", "application/json; charset=utf-8")
+            .timeout(std::time::Duration::from_secs(60));
+        for (name, value) in headers {
+            request = request.set(name, value);
         }
+        let answer = request.send_string(&payload)?.into_string()?;
         Ok(answer)
     }
 
@@ -1,5 +1,6 @@
 use anyhow::{bail, format_err, Result};
 use log::{info, warn};
+use proxmox_auto_installer::sysinfo::SysInfo;
 use std::{
     fs::{self, create_dir_all},
     path::{Path, PathBuf},
@@ -7,6 +8,7 @@ use std::{
 };
 
 static ANSWER_FILE: &str = "answer.toml";
+static HOST_ANSWERS_DIR: &str = "answers";
 static ANSWER_MP: &str = "/mnt/answer";
 // FAT can only handle 11 characters, so shorten Automated Installer Source to AIS
 static PARTLABEL: &str = "proxmox-ais";
@@ -16,20 +18,83 @@ pub struct FetchFromPartition;
 
 impl FetchFromPartition {
     /// Returns the contents of the answer file
+    ///
+    /// Host specific answer files in the `answers` directory, named after a MAC address, the DMI
+    /// serial number or the DMI product UUID of the system, take precedence over the default
+    /// `answer.toml`.
     pub fn get_answer() -> Result<String> {
         info!("Checking for answer file on partition.");
 
-        let mut mount_path = PathBuf::from(mount_proxmoxinst_part()?);
-        mount_path.push(ANSWER_FILE);
-        let answer = fs::read_to_string(mount_path)
+        let mount_path = PathBuf::from(mount_proxmoxinst_part()?);
+
+        let host_answers = match SysInfo::get() {
+            Ok(sysinfo) => host_answer_files(
+                sysinfo.macs(),
+                sysinfo.product_serial(),
+                sysinfo.product_uuid(),
+            ),
+            Err(err) => {
+                warn!("Skipping host specific answer files: {err}");
+                Vec::new()
+            }
+        };
+
+        let mount_path_str = mount_path.to_string_lossy();
+        let answer_path = host_answers
+            .iter()
+            .find_map(|file| path_exists_logged(file, &mount_path_str))
+            .unwrap_or_else(|| mount_path.join(ANSWER_FILE));
+
+        let answer = fs::read_to_string(&answer_path)
             .map_err(|err| format_err!("failed to read answer file - {err}"))?;
 
-        info!("Found answer file on partition.");
+        info!("Found answer file {answer_path:?} on partition.");
 
         Ok(answer)
     }
 }
 
+/// Returns the possible host specific answer file paths, in the order they should be tried.
+///
+/// MAC addresses are tried with dashes as well, as colons are not allowed in FAT file names.
+fn host_answer_files<'a>(
+    macs: impl Iterator<Item = &'a str>,
+    serial: Option<&str>,
+    uuid: Option<&str>,
+) -> Vec<String> {
+    let mut names = Vec::new();
+    for mac in macs {
+        let mac = mac.to_lowercase();
+        names.push(mac.replace(':', "-"));
+        names.push(mac);
+    }
+
+    // vendors commonly fill unset DMI fields with placeholders, which are not unique
+    let is_usable = |value: &&str| {
+        let value = value.trim();
+        !value.is_empty()
+            && !value.contains('/')
+            && !value.eq_ignore_ascii_case("To Be Filled By O.E.M.")
+            && !value.eq_ignore_ascii_case("Not Specified")
+            && !value.eq_ignore_ascii_case("Default string")
+            && !value.chars().all(|c| c == '0' || c == '-')
+    };
+    names.extend(
+        serial
+            .filter(is_usable)
+            .map(|serial| serial.trim().to_string()),
+    );
+    names.extend(
+        uuid.filter(is_usable)
+            .map(|uuid| uuid.trim().to_lowercase()),
+    );
+
+    names
+        .into_iter()
+        .map(|name| format!("{HOST_ANSWERS_DIR}/{name}.toml"))
+        .collect()
+}
+
 fn path_exists_logged(file_name: &str, search_path: &str) -> Option<PathBuf> {
     let path = Path::new(search_path).join(&file_name);
     info!("Testing partition search path {path:?}");
@@ -104,3 +169,32 @@ fn check_if_mounted(target_path: &str) -> Result<bool> {
     }
     Ok(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_answer_file_order() {
+        let files = host_answer_files(
+            ["AA:BB:CC:DD:EE:FF"].into_iter(),
+            Some("To Be Filled By O.E.M."),
+            Some("4C4C4544-0042-3510-8051-C2C04F4E3632"),
+        );
+        assert_eq!(
+            files,
+            [
+                "answers/aa-bb-cc-dd-ee-ff.toml",
+                "answers/aa:bb:cc:dd:ee:ff.toml",
+                "answers/4c4c4544-0042-3510-8051-c2c04f4e3632.toml",
+            ]
+        );
+
+        let files = host_answer_files(
+            std::iter::empty(),
+            Some("CZ12345678"),
+            Some("00000000-0000-0000-0000-000000000000"),
+        );
+        assert_eq!(files, ["answers/CZ12345678.toml"]);
+    }
+}
@@ -1,20 +1,29 @@
 use std::process::ExitCode;
-use std::{fs, path::PathBuf};
+use std::{fmt, fs, path::PathBuf};
 
 use anyhow::{bail, format_err, Result};
-use log::{error, info, LevelFilter};
+use log::{error, info, warn, LevelFilter};
 
 use proxmox_auto_installer::{
+    answer::Answer,
     log::AutoInstLogger,
-    utils::{AutoInstSettings, FetchAnswerFrom, HttpOptions},
+    template,
+    utils::{AnswerSource, AutoInstSettings, FetchAnswerFrom, HttpOptions},
 };
 
-use fetch_plugins::{http::FetchFromHTTP, partition::FetchFromPartition};
+use fetch_plugins::{
+    http::{AnswerServer, FetchFromHTTP},
+    partition::FetchFromPartition,
+};
 
+mod dhcp;
+mod dns;
 mod fetch_plugins;
 
 static LOGGER: AutoInstLogger = AutoInstLogger;
 static AUTOINST_MODE_FILE: &str = "/cdrom/auto-installer-mode.toml";
+static KERNEL_CMDLINE_FILE: &str = "/proc/cmdline";
+static KERNEL_CMDLINE_PREFIX: &str = "proxmox-auto-install.";
 
 pub fn init_log() -> Result<()> {
     AutoInstLogger::init("/tmp/fetch_answer.log")?;
@@ -23,51 +32,201 @@ pub fn init_log() -> Result<()> {
         .map_err(|err| format_err!(err))
 }
 
-fn fetch_answer(install_settings: &AutoInstSettings) -> Result<String> {
-    info!("Fetching answer file in mode {:?}:", &install_settings.mode);
-    match install_settings.mode {
+/// Why a source did not provide a usable answer.
+#[derive(Debug)]
+enum SourceError {
+    /// The answer could not be retrieved.
+    Fetch(anyhow::Error),
+    /// The retrieved answer is not a valid answer file.
+    Invalid(anyhow::Error),
+}
+
+#[derive(Debug)]
+struct SourceFailure {
+    mode: FetchAnswerFrom,
+    error: SourceError,
+}
+
+impl fmt::Display for SourceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.error {
+            SourceError::Fetch(err) => write!(f, "{:?}: {err}", self.mode),
+            SourceError::Invalid(err) => write!(f, "{:?}: invalid answer file: {err}", self.mode),
+        }
+    }
+}
+
+/// Fetches the raw answer, returning the connection to the answer server in 'http' mode.
+fn fetch_answer_from(source: &AnswerSource) -> Result<(String, Option<AnswerServer>)> {
+    match source.mode {
         FetchAnswerFrom::Iso => {
             let answer_path = PathBuf::from("/cdrom/answer.toml");
-            match fs::read_to_string(answer_path) {
-                Ok(answer) => return Ok(answer),
-                Err(err) => info!("Fetching answer file from ISO failed: {err}"),
-            }
+            fs::read_to_string(answer_path)
+                .map(|answer| (answer, None))
+                .map_err(|err| format_err!("Fetching answer file from ISO failed: {err}"))
         }
-        FetchAnswerFrom::Partition => match FetchFromPartition::get_answer() {
-            Ok(answer) => return Ok(answer),
-            Err(err) => info!("Fetching answer file from partition failed: {err}"),
-        },
-        FetchAnswerFrom::Http => match FetchFromHTTP::get_answer(&install_settings.http) {
-            Ok(answer) => return Ok(answer),
-            Err(err) => info!("Fetching answer file via HTTP failed: {err}"),
-        },
+        FetchAnswerFrom::Partition => FetchFromPartition::get_answer()
+            .map(|answer| (answer, None))
+            .map_err(|err| format_err!("Fetching answer file from partition failed: {err}")),
+        FetchAnswerFrom::Http => FetchFromHTTP::get_answer(&source.http)
+            .map(|(answer, server)| (answer, Some(server)))
+            .map_err(|err| format_err!("Fetching answer file via HTTP failed: {err}")),
     }
-    bail!("Could not find any answer file!");
 }
 
-fn settings_from_cli_args(args: &[String]) -> Result<AutoInstSettings> {
-    // TODO: this was done in a bit of a hurry, needs tidying up
-    let mode = match args[1].to_lowercase().as_str() {
-        "iso" => FetchAnswerFrom::Iso,
-        "http" => FetchAnswerFrom::Http,
-        "partition" => FetchAnswerFrom::Partition,
-        "-h" | "--help" => bail!(
-            "usage: {} <http|iso|partition> [<http-url>] [<tls-cert-fingerprint>]",
-            args[0]
-        ),
-        _ => bail!("failed to parse fetch-from argument, not one of 'http', 'iso', or 'partition'"),
+/// Renders answer file templates and checks that the result parses as an `Answer`.
+///
+/// Returns the rendered answer file.
+fn validate_answer(answer: String) -> Result<String> {
+    let answer = if template::is_template(&answer) {
+        info!("Rendering answer file template");
+        let context = template::local_context()
+            .map_err(|err| format_err!("failed to gather system information: {err}"))?;
+        // the mount points of the answer partition and the ISO
+        let lookup_dirs = [PathBuf::from("/mnt/answer"), PathBuf::from("/cdrom")];
+        template::render(&answer, &context, &lookup_dirs)?
+    } else {
+        answer
     };
-    if args.len() > 4 {
-    } else if args.len() > 2 && mode != FetchAnswerFrom::Http {
-        bail!("only 'http' fetch-from mode supports additional url and cert-fingerprint mode");
+    toml::from_str::<Answer>(&answer)?;
+    Ok(answer)
+}
+
+/// Tries all configured sources in order and returns the first valid answer found.
+///
+/// Invalid answers from a HTTP source are reported back to the error report URL, if configured.
+fn fetch_answer(install_settings: &AutoInstSettings) -> Result<String> {
+    let mut failures = Vec::new();
+    for source in &install_settings.sources {
+        info!("Fetching answer file in mode {:?}:", source.mode);
+        let error = match fetch_answer_from(source) {
+            Ok((answer, server)) => match validate_answer(answer) {
+                Ok(answer) => return Ok(answer),
+                Err(err) => {
+                    if let (Some(server), Some(url)) = (&server, &source.http.error_report_url) {
+                        if let Err(report_err) =
+                            FetchFromHTTP::report_error(server, url, &format!("{err:#}"))
+                        {
+                            warn!("Reporting invalid answer file failed: {report_err}");
+                        }
+                    }
+                    SourceError::Invalid(err)
+                }
+            },
+            Err(err) => SourceError::Fetch(err),
+        };
+        let failure = SourceFailure {
+            mode: source.mode.clone(),
+            error,
+        };
+        info!("{failure}");
+        failures.push(failure);
+    }
+    bail!(
+        "Could not find any valid answer file! Tried sources:\n{}",
+        failures
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+}
+
+/// Answer source overrides passed via the kernel command line, e.g. from an iPXE script.
+#[derive(Debug, Default, PartialEq)]
+struct CmdlineOverrides {
+    modes: Option<Vec<FetchAnswerFrom>>,
+    url: Option<String>,
+    cert_fingerprint: Option<String>,
+}
+
+impl CmdlineOverrides {
+    /// Parses the `proxmox-auto-install.{mode,url,fp}` parameters, all others are ignored.
+    fn parse(cmdline: &str) -> Result<Self> {
+        let mut overrides = Self::default();
+        for param in cmdline.split_whitespace() {
+            let Some(param) = param.strip_prefix(KERNEL_CMDLINE_PREFIX) else {
+                continue;
+            };
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match key {
+                "mode" => {
+                    let modes = value
+                        .split(',')
+                        .map(|mode| match mode.to_lowercase().as_str() {
+                            "iso" => Ok(FetchAnswerFrom::Iso),
+                            "http" => Ok(FetchAnswerFrom::Http),
+                            "partition" => Ok(FetchAnswerFrom::Partition),
+                            _ => bail!(
+                                "failed to parse mode '{mode}', not one of 'http', 'iso', or 'partition'"
+                            ),
+                        })
+                        .collect::<Result<_>>()?;
+                    overrides.modes = Some(modes);
+                }
+                "url" => overrides.url = Some(value.to_string()),
+                "fp" => overrides.cert_fingerprint = Some(value.to_string()),
+                _ => bail!("unknown kernel parameter '{KERNEL_CMDLINE_PREFIX}{key}'"),
+            }
+        }
+        Ok(overrides)
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Applies the overrides on top of the settings from the ISO, if there are any.
+    fn apply(self, settings: Option<AutoInstSettings>) -> Result<AutoInstSettings> {
+        let mut settings = match (self.modes, settings) {
+            (Some(modes), settings) => {
+                // keep the other HTTP options of the ISO, like client certificates or headers
+                let iso_http = settings
+                    .iter()
+                    .flat_map(|settings| &settings.sources)
+                    .find(|source| source.mode == FetchAnswerFrom::Http)
+                    .map(|source| source.http.clone())
+                    .unwrap_or_default();
+                AutoInstSettings {
+                    sources: modes
+                        .into_iter()
+                        .map(|mode| AnswerSource {
+                            http: match mode {
+                                FetchAnswerFrom::Http => iso_http.clone(),
+                                _ => HttpOptions::default(),
+                            },
+                            mode,
+                        })
+                        .collect(),
+                }
+            }
+            (None, Some(settings)) => settings,
+            (None, None) => bail!("no answer source configured"),
+        };
+
+        if self.url.is_some() || self.cert_fingerprint.is_some() {
+            let mut http_sources = settings
+                .sources
+                .iter_mut()
+                .filter(|source| source.mode == FetchAnswerFrom::Http)
+                .peekable();
+            if http_sources.peek().is_none() {
+                bail!("the 'url' and 'fp' kernel parameters are only supported by the 'http' mode");
+            }
+            for source in http_sources {
+                if self.url.is_some() {
+                    source.http.url.clone_from(&self.url);
+                }
+                if self.cert_fingerprint.is_some() {
+                    source
+                        .http
+                        .cert_fingerprint
+                        .clone_from(&self.cert_fingerprint);
+                }
+            }
+        }
+        Ok(settings)
     }
-    Ok(AutoInstSettings {
-        mode,
-        http: HttpOptions {
-            url: args.get(2).cloned(),
-            cert_fingerprint: args.get(3).cloned(),
-        },
-    })
 }
 
 fn do_main() -> Result<()> {
@@ -75,20 +234,32 @@ fn do_main() -> Result<()> {
         bail!("could not initialize logging: {err}");
     }
 
-    let args: Vec<String> = std::env::args().collect();
+    let cmdline = fs::read_to_string(KERNEL_CMDLINE_FILE).unwrap_or_else(|err| {
+        info!("Could not read '{KERNEL_CMDLINE_FILE}': {err}");
+        String::new()
+    });
+    let overrides = CmdlineOverrides::parse(&cmdline)
+        .map_err(|err| format_err!("Failed to parse kernel command line: {err}"))?;
 
-    let install_settings: AutoInstSettings = if args.len() > 1 {
-        settings_from_cli_args(&args)?
-    } else {
-        let raw_install_settings = fs::read_to_string(AUTOINST_MODE_FILE).map_err(|err| {
-            format_err!(
-                "Could not find needed file '{AUTOINST_MODE_FILE}' in live environment: {err}"
-            )
-        })?;
-        toml::from_str(raw_install_settings.as_str())
-            .map_err(|err| format_err!("Failed to parse '{AUTOINST_MODE_FILE}': {err}"))?
+    let install_settings: Option<AutoInstSettings> = match fs::read_to_string(AUTOINST_MODE_FILE) {
+        Ok(raw_install_settings) => Some(
+            toml::from_str(raw_install_settings.as_str())
+                .map_err(|err| format_err!("Failed to parse '{AUTOINST_MODE_FILE}': {err}"))?,
+        ),
+        Err(err) if overrides.modes.is_some() => {
+            info!("Could not read '{AUTOINST_MODE_FILE}': {err}");
+            None
+        }
+        Err(err) => {
+            bail!("Could not find needed file '{AUTOINST_MODE_FILE}' in live environment: {err}")
+        }
     };
 
+    if !overrides.is_empty() {
+        info!("Overriding answer sources with parameters from the kernel command line");
+    }
+    let install_settings = overrides.apply(install_settings)?;
+
     let answer = fetch_answer(&install_settings).map_err(|err| format_err!("Aborting: {err}"))?;
     info!("queried answer file for automatic installation successfully");
 
@@ -106,3 +277,95 @@ fn main() -> ExitCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_source(url: Option<&str>) -> AnswerSource {
+        AnswerSource {
+            mode: FetchAnswerFrom::Http,
+            http: HttpOptions {
+                url: url.map(String::from),
+                client_cert: Some("cert".to_string()),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn validate_answers() {
+        let answer = "[global]\n\
+                      keyboard = \"de\"\n\
+                      country = \"at\"\n\
+                      fqdn = \"pveauto.testinstall\"\n\
+                      mailto = \"mail@no.invalid\"\n\
+                      timezone = \"Europe/Vienna\"\n\
+                      root_password = \"123456\"\n\
+                      [network]\n\
+                      source = \"from-dhcp\"\n\
+                      [disk-setup]\n\
+                      filesystem = \"ext4\"\n\
+                      disk_list = [\"sda\"]\n";
+        assert_eq!(validate_answer(answer.to_string()).unwrap(), answer);
+
+        assert!(validate_answer("<html>Not Found</html>".to_string()).is_err());
+        assert!(validate_answer(answer.replace("ext4", "ntfs")).is_err());
+        assert!(validate_answer(answer.replace("[network]", "")).is_err());
+    }
+
+    #[test]
+    fn parse_cmdline_overrides() {
+        let overrides = CmdlineOverrides::parse(
+            "BOOT_IMAGE=/boot/linux26 ro quiet proxmox-start-auto-installer \
+             proxmox-auto-install.mode=partition,HTTP proxmox-auto-install.url=https://example.com/answer",
+        )
+        .unwrap();
+        assert_eq!(
+            overrides,
+            CmdlineOverrides {
+                modes: Some(vec![FetchAnswerFrom::Partition, FetchAnswerFrom::Http]),
+                url: Some("https://example.com/answer".to_string()),
+                cert_fingerprint: None,
+            }
+        );
+
+        assert!(CmdlineOverrides::parse("ro quiet").unwrap().is_empty());
+        assert!(CmdlineOverrides::parse("proxmox-auto-install.mode=tftp").is_err());
+        assert!(CmdlineOverrides::parse("proxmox-auto-install.foo=bar").is_err());
+    }
+
+    #[test]
+    fn apply_cmdline_overrides() {
+        let iso_settings = AutoInstSettings {
+            sources: vec![http_source(Some("https://iso.example.com"))],
+        };
+
+        let overrides = CmdlineOverrides {
+            modes: Some(vec![FetchAnswerFrom::Partition, FetchAnswerFrom::Http]),
+            url: Some("https://cmdline.example.com".to_string()),
+            cert_fingerprint: None,
+        };
+        let settings = overrides.apply(Some(iso_settings)).unwrap();
+        assert_eq!(settings.sources.len(), 2);
+        assert_eq!(settings.sources[0].mode, FetchAnswerFrom::Partition);
+        assert_eq!(settings.sources[0].http, HttpOptions::default());
+        assert_eq!(
+            settings.sources[1],
+            http_source(Some("https://cmdline.example.com"))
+        );
+
+        let overrides = CmdlineOverrides {
+            url: Some("https://cmdline.example.com".to_string()),
+            ..Default::default()
+        };
+        let iso_settings = AutoInstSettings {
+            sources: vec![AnswerSource {
+                mode: FetchAnswerFrom::Iso,
+                http: HttpOptions::default(),
+            }],
+        };
+        assert!(overrides.apply(Some(iso_settings)).is_err());
+        assert!(CmdlineOverrides::default().apply(None).is_err());
+    }
+}
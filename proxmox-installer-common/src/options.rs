@@ -81,6 +81,23 @@ impl fmt::Display for FsType {
     }
 }
 
+pub const FS_TYPES: &[FsType] = {
+    use FsType::*;
+    &[
+        Ext4,
+        Xfs,
+        Zfs(ZfsRaidLevel::Raid0),
+        Zfs(ZfsRaidLevel::Raid1),
+        Zfs(ZfsRaidLevel::Raid10),
+        Zfs(ZfsRaidLevel::RaidZ),
+        Zfs(ZfsRaidLevel::RaidZ2),
+        Zfs(ZfsRaidLevel::RaidZ3),
+        Btrfs(BtrfsRaidLevel::Raid0),
+        Btrfs(BtrfsRaidLevel::Raid1),
+        Btrfs(BtrfsRaidLevel::Raid10),
+    ]
+};
+
 #[derive(Clone, Debug)]
 pub struct LvmBootdiskOptions {
     pub total_size: f64,
@@ -202,6 +219,10 @@ impl ZfsBootdiskOptions {
     }
 }
 
+/// Lower bound of the ZFS ARC size, in MiB. Lower values besides 0 (the ZFS default) are raised
+/// to it by the installer.
+pub const ZFS_ARC_MIN_SIZE_MIB: usize = 64;
+
 /// Calculates the default upper limit for the ZFS ARC size.
 /// See also <https://bugzilla.proxmox.com/show_bug.cgi?id=4829> and
 /// https://openzfs.github.io/openzfs-docs/Performance%20and%20Tuning/Module%20Parameters.html#zfs-arc-max
@@ -212,14 +233,14 @@ impl ZfsBootdiskOptions {
 ///
 /// # Returns
 /// The default ZFS maximum ARC size in MiB for this system.
-fn default_zfs_arc_max(product: ProxmoxProduct, total_memory: usize) -> usize {
+pub fn default_zfs_arc_max(product: ProxmoxProduct, total_memory: usize) -> usize {
     if product != ProxmoxProduct::PVE {
         // Use ZFS default for non-PVE
         0
     } else {
         ((total_memory as f64) / 10.)
             .round()
-            .clamp(64., 16. * 1024.) as usize
+            .clamp(ZFS_ARC_MIN_SIZE_MIB as f64, 16. * 1024.) as usize
     }
 }
 
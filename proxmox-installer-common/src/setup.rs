@@ -335,7 +335,7 @@ pub struct RuntimeInfo {
     pub hvm_supported: bool,
 }
 
-#[derive(Copy, Clone, Eq, Deserialize, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum BootType {
     Bios,
@@ -452,6 +452,17 @@ pub fn spawn_low_level_installer(test_mode: bool) -> io::Result<process::Child>
         .spawn()
 }
 
+/// A file to copy from the installation medium into the installed system.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct InstallExtraFile {
+    /// Absolute path of the file on the installation medium
+    pub source: String,
+    /// Absolute path in the installed system
+    pub target: String,
+    /// Octal file mode, e.g. '0644'
+    pub mode: String,
+}
+
 /// See Proxmox::Install::Config
 #[derive(Deserialize, Serialize)]
 pub struct InstallConfig {
@@ -485,7 +496,7 @@ pub struct InstallConfig {
         skip_deserializing
     )]
     pub target_hd: Option<Disk>,
-    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub disk_selection: BTreeMap<String, String>,
 
     pub existing_storage_auto_rename: usize,
@@ -496,8 +507,10 @@ pub struct InstallConfig {
 
     pub root_password: InstallRootPassword,
     pub mailto: String,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub root_ssh_keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_files: Vec<InstallExtraFile>,
 
     pub mngmt_nic: String,
 
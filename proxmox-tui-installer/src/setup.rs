@@ -32,6 +32,7 @@ impl From<InstallerOptions> for InstallConfig {
             },
             mailto: options.password.email,
             root_ssh_keys: vec![],
+            extra_files: vec![],
 
             mngmt_nic: options.network.ifname,
 
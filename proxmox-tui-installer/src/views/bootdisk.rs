@@ -10,7 +10,6 @@ use cursive::{
 };
 
 use super::{DiskSizeEditView, FormView, IntegerEditView, TabbedView};
-use crate::options::FS_TYPES;
 use crate::InstallerState;
 
 use proxmox_installer_common::{
@@ -20,7 +19,8 @@ use proxmox_installer_common::{
     },
     options::{
         AdvancedBootdiskOptions, BootdiskOptions, BtrfsBootdiskOptions, Disk, FsType,
-        LvmBootdiskOptions, ZfsBootdiskOptions, ZFS_CHECKSUM_OPTIONS, ZFS_COMPRESS_OPTIONS,
+        LvmBootdiskOptions, ZfsBootdiskOptions, FS_TYPES, ZFS_CHECKSUM_OPTIONS,
+        ZFS_COMPRESS_OPTIONS,
     },
     setup::{BootType, ProductConfig, ProxmoxProduct, RuntimeInfo},
 };
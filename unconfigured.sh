@@ -6,6 +6,7 @@ trap "err_reboot" ERR
 
 parse_cmdline() {
     start_auto_installer=0
+    auto_installer_mode_override=0
     proxdebug=0
     proxtui=0
     serial=0
@@ -21,6 +22,9 @@ parse_cmdline() {
             proxauto|proxmox-start-auto-installer)
                 start_auto_installer=1
             ;;
+            proxmox-auto-install.mode=*)
+                auto_installer_mode_override=1
+            ;;
             console=ttyS*)
                 serial=1
             ;;
@@ -242,13 +246,13 @@ elif [ $start_auto_installer -ne 0 ]; then
     echo "Caching device info from udev"
     /usr/bin/proxmox-low-level-installer dump-udev
 
-    if [ -f /cdrom/auto-installer-mode.toml ]; then
+    if [ -f /cdrom/auto-installer-mode.toml ] || [ $auto_installer_mode_override -ne 0 ]; then
         echo "Fetching answers for automatic installation"
         /usr/bin/proxmox-fetch-answer >/run/automatic-installer-answers
     else
         printf "\nAutomatic installation selected but no config for fetching the answer file found!\n"
-        echo "Starting debug shell, to fetch the answer file manually use:"
-        echo "  proxmox-fetch-answer MODE >/run/automatic-installer-answers"
+        echo "Starting debug shell, to provide the answer file manually write it to:"
+        echo "  /run/automatic-installer-answers"
         echo "and enter 'exit' or press 'CTRL' + 'D' when finished."
         debugsh || true
     fi